@@ -1,4 +1,4 @@
-use clap::Parser; // ValueEnum
+use clap::{Parser, ValueEnum};
 
 #[derive(Debug, Clone, clap::Subcommand)]
 pub enum Action {
@@ -19,7 +19,47 @@ pub enum Action {
         /// if specified, only check this stage
         #[clap(short, long)]
         stage: Option<String>,
+        /// text shows progress, json and yaml print the report of the run
+        #[clap(long, value_enum, default_value = "text")]
+        output: OutputFormat,
     },
+    /// Print facts of the host as JSON, without running the playbook
+    Facts {
+        /// path to files
+        #[clap(short, long)]
+        file: std::path::PathBuf,
+    },
+    /// Compare two outputs of the facts action and print what drifted
+    DiffFacts {
+        /// earlier snapshot
+        before: std::path::PathBuf,
+        /// later snapshot
+        after: std::path::PathBuf,
+    },
+    /// List available modules
+    ListModules,
+    /// Show parameters of the module
+    ModuleHelp {
+        /// name of the module
+        name: String,
+    },
+}
+
+/// output of the check
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Yaml,
+}
+
+/// format of the webhook notification payload
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum NotifyFormat {
+    /// JSON report of the run
+    Json,
+    /// Slack-compatible message
+    Slack,
 }
 
 // struct for clap CLI args
@@ -42,6 +82,29 @@ pub struct Opts {
     #[clap(long, default_value = "~/.ssh/id_rsa", env = "REMOTE_SSH_KEY_FILE")]
     pub remote_key_file: String,
 
+    /// webhook URL, the report of the run is posted to it
+    #[clap(long, env = "REMOTE_NOTIFY_WEBHOOK")]
+    pub notify_webhook: Option<String>,
+    /// format of the webhook payload
+    #[clap(long, value_enum, default_value = "json")]
+    pub notify_format: NotifyFormat,
+
+    /// report module failures as ignored and don't fail the run
+    #[clap(long)]
+    pub warn_only: bool,
+
+    /// exit with 2 when the run succeeded and some modules changed the host
+    #[clap(long)]
+    pub detailed_exitcode: bool,
+
+    /// allow unverified host keys when strict_host_key_checking is set
+    #[clap(long)]
+    pub insecure: bool,
+
+    /// write timings of all spans into the file in Chrome trace format
+    #[clap(long)]
+    pub profile: Option<std::path::PathBuf>,
+
     /// Action
     #[command(subcommand)]
     pub action: Action,
@@ -53,31 +116,35 @@ pub struct Opts {
 impl Opts {
     pub fn into_ssh(&self) -> crate::config::Ssh {
         crate::config::Ssh {
-            remote_host: if self.remote_host.len() > 0 {
+            remote_host: if !self.remote_host.is_empty() {
                 Some(self.remote_host.clone())
             } else {
                 None
             },
-            remote_user: if self.remote_user.len() > 0 {
+            remote_user: if !self.remote_user.is_empty() {
                 Some(self.remote_user.clone())
             } else {
                 None
             },
-            remote_password: if self.remote_password.len() > 0 {
+            remote_password: if !self.remote_password.is_empty() {
                 Some(self.remote_password.clone())
             } else {
                 None
             },
             remote_port: if self.remote_port > 0 {
-                Some(self.remote_port.clone())
+                Some(self.remote_port)
             } else {
                 None
             },
-            remote_key_file: if self.remote_key_file.len() > 0 {
+            remote_key_file: if !self.remote_key_file.is_empty() {
                 Some(self.remote_key_file.clone())
             } else {
                 None
             },
+            password_from_keyring: None,
+            connect_timeout_secs: None,
+            host_key_check: None,
+            compression: None,
         }
     }
 }
@@ -1,5 +1,7 @@
 use crate::remote::Stage;
 
+use crate::prelude::add_secret;
+use anyhow::{bail, Context};
 use serde::Deserialize;
 use std::collections::BTreeMap as Map;
 
@@ -8,7 +10,77 @@ pub struct Config {
     pub ssh: Option<Ssh>,
     pub stages: Map<String, Stage>,
     pub aliases: Option<Map<String, String>>,
-    pub exports: Option<Map<String, String>>,
+    pub exports: Option<Map<String, ExportValue>>,
+    /// wrapper for every remote command, i.e. `nice -n 10 ionice -c3`
+    pub command_prefix: Option<String>,
+    /// umask for every remote command, i.e. 022
+    pub umask: Option<String>,
+    /// python for modules that need it, python3 or python is looked up by default
+    pub python_interpreter: Option<String>,
+    /// skip the remaining stages of install after this many module failures in a row
+    pub host_failure_threshold: Option<usize>,
+    /// refuse to run with host_key_check = "none", unless --insecure is passed
+    pub strict_host_key_checking: Option<bool>,
+}
+
+/// value of the exported variable, either inline or printed by a local command
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ExportValue {
+    Value(String),
+    FromCommand { from_command: String },
+}
+
+impl ExportValue {
+    // local commands are evaluated once, their output is treated as a secret
+    pub fn resolve(&self) -> anyhow::Result<String> {
+        match self {
+            ExportValue::Value(value) => Ok(value.clone()),
+            ExportValue::FromCommand { from_command } => {
+                let output = std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(from_command)
+                    .output()
+                    .with_context(|| format!("failed to run `{}`", from_command))?;
+                if !output.status.success() {
+                    bail!(
+                        "`{}` failed with {}: {}",
+                        from_command,
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                let value = String::from_utf8_lossy(&output.stdout)
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string();
+                add_secret(&value);
+                Ok(value)
+            }
+        }
+    }
+}
+
+impl Config {
+    /// whether the stage, or all stages without one, has modules that run with sudo.
+    /// aliases and exports are written to the files of the SSH user
+    pub fn needs_sudo(&self, stage: Option<&str>) -> bool {
+        match stage {
+            Some(name) => self.stages.get(name).is_some_and(Stage::needs_sudo),
+            None => self.stages.values().any(Stage::needs_sudo),
+        }
+    }
+
+    pub fn resolve_exports(&self) -> anyhow::Result<Option<Map<String, String>>> {
+        let Some(exports) = &self.exports else {
+            return Ok(None);
+        };
+        let mut out = Map::new();
+        for (key, value) in exports {
+            let value = value.resolve().with_context(|| format!("export {}", key))?;
+            out.insert(key.clone(), value);
+        }
+        Ok(Some(out))
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -18,6 +90,146 @@ pub struct Ssh {
     pub remote_port: Option<u16>,
     pub remote_password: Option<String>,
     pub remote_key_file: Option<String>,
+    /// password from the keyring of the OS
+    pub password_from_keyring: Option<Keyring>,
+    /// how long a connection attempt may take, from the SSH banner of a slow server
+    /// through the key exchange to authentication, 30 by default
+    #[serde(alias = "banner_timeout_secs")]
+    pub connect_timeout_secs: Option<u64>,
+    /// none by default, known_hosts verifies against ~/.ssh/known_hosts
+    pub host_key_check: Option<HostKeyCheck>,
+    /// negotiate zlib compression, helps over slow links, off by default
+    pub compression: Option<bool>,
+}
+
+/// how the host key of the server is verified
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostKeyCheck {
+    #[default]
+    None,
+    KnownHosts,
+}
+
+/// entry of the OS keyring: the Secret Service on Linux (GNOME Keyring, KWallet),
+/// the Keychain on macOS and the Credential Manager on Windows
+#[derive(Debug, Deserialize)]
+pub struct Keyring {
+    pub service: String,
+    pub account: String,
+}
+
+/// program printing the password of the entry, the entry is passed
+/// in arguments or the environment and is never a part of a script
+#[derive(Debug, PartialEq)]
+pub struct LookupCommand {
+    pub program: &'static str,
+    pub args: Vec<String>,
+    pub envs: Vec<(&'static str, String)>,
+}
+
+/// reads a generic credential with CredRead, the password is stored as UTF-16
+const CRED_READ_PS1: &str = r#"Add-Type -TypeDefinition @'
+using System;
+using System.Runtime.InteropServices;
+public static class RemotePlaybookCredential {
+    [StructLayout(LayoutKind.Sequential, CharSet = CharSet.Unicode)]
+    struct CREDENTIAL {
+        public int Flags;
+        public int Type;
+        public string TargetName;
+        public string Comment;
+        public long LastWritten;
+        public int CredentialBlobSize;
+        public IntPtr CredentialBlob;
+        public int Persist;
+        public int AttributeCount;
+        public IntPtr Attributes;
+        public string TargetAlias;
+        public string UserName;
+    }
+    [DllImport("advapi32.dll", CharSet = CharSet.Unicode, SetLastError = true)]
+    static extern bool CredRead(string target, int type, int flags, out IntPtr credential);
+    [DllImport("advapi32.dll")]
+    static extern void CredFree(IntPtr credential);
+    public static string Read(string target) {
+        IntPtr ptr;
+        if (!CredRead(target, 1, 0, out ptr)) {
+            return null;
+        }
+        try {
+            var cred = (CREDENTIAL)Marshal.PtrToStructure(ptr, typeof(CREDENTIAL));
+            return Marshal.PtrToStringUni(cred.CredentialBlob, cred.CredentialBlobSize / 2);
+        } finally {
+            CredFree(ptr);
+        }
+    }
+}
+'@
+$password = [RemotePlaybookCredential]::Read($env:REMOTE_PLAYBOOK_CREDENTIAL)
+if ($null -eq $password) { exit 1 }
+[Console]::Out.Write($password)
+"#;
+
+impl Keyring {
+    /// `os` is the value of std::env::consts::OS
+    pub fn lookup_command(&self, os: &str) -> LookupCommand {
+        let args = |args: &[&str]| args.iter().map(|x| x.to_string()).collect();
+        match os {
+            "macos" => LookupCommand {
+                program: "security",
+                args: args(&[
+                    "find-generic-password",
+                    "-s",
+                    &self.service,
+                    "-a",
+                    &self.account,
+                    "-w",
+                ]),
+                envs: vec![],
+            },
+            // the target name the keyring crate uses for generic credentials
+            "windows" => LookupCommand {
+                program: "powershell",
+                args: args(&["-NoProfile", "-NonInteractive", "-Command", CRED_READ_PS1]),
+                envs: vec![(
+                    "REMOTE_PLAYBOOK_CREDENTIAL",
+                    format!("{}.{}", self.account, self.service),
+                )],
+            },
+            _ => LookupCommand {
+                program: "secret-tool",
+                args: args(&["lookup", "service", &self.service, "account", &self.account]),
+                envs: vec![],
+            },
+        }
+    }
+
+    pub fn lookup(&self) -> anyhow::Result<String> {
+        let command = self.lookup_command(std::env::consts::OS);
+        self.run_lookup(command.program, &command)
+    }
+
+    /// runs the lookup command with the given program, i.e. a fake one in tests
+    pub fn run_lookup(&self, program: &str, command: &LookupCommand) -> anyhow::Result<String> {
+        let output = std::process::Command::new(program)
+            .args(&command.args)
+            .envs(command.envs.iter().cloned())
+            .output()
+            .with_context(|| format!("keyring is unavailable, `{}` failed to start", program))?;
+        let value = String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches(['\r', '\n'])
+            .to_string();
+        if !output.status.success() || value.is_empty() {
+            bail!(
+                "no keyring entry for service {} account {}",
+                self.service,
+                self.account
+            );
+        }
+        add_secret(&value);
+        Ok(value)
+    }
 }
 
 #[cfg(test)]
@@ -41,7 +253,145 @@ docker = { path = "/data" }
 terraform = {}
     "#;
 
-        let config: Config = toml::from_str(&contents).unwrap();
+        let config: Config = toml::from_str(contents).unwrap();
         println!("{:?}", config);
     }
+
+    #[test]
+    fn it_needs_sudo_for_selected_stages() {
+        let contents = r#"
+[stages.user]
+git = { clone = "https://github.com/wcrbrm/remote-playbook.git", to = "~/src" }
+
+[stages.system]
+apt = { install = ["git"] }
+
+[aliases]
+ll = "ls -la"
+    "#;
+        let config: Config = toml::from_str(contents).unwrap();
+        assert!(!config.needs_sudo(Some("user")));
+        assert!(config.needs_sudo(Some("system")));
+        assert!(!config.needs_sudo(Some("aliases")));
+        assert!(config.needs_sudo(None));
+    }
+
+    #[test]
+    fn it_resolves_exports() {
+        let contents = r#"
+[stages]
+
+[exports]
+PLAIN = "value"
+TOKEN = { from_command = "echo secret-from-command" }
+    "#;
+        let config: Config = toml::from_str(contents).unwrap();
+        let exports = config.resolve_exports().unwrap().unwrap();
+        assert_eq!(exports["PLAIN"], "value");
+        assert_eq!(exports["TOKEN"], "secret-from-command");
+        assert_eq!(
+            crate::prelude::redact("export TOKEN=secret-from-command"),
+            "export TOKEN=********"
+        );
+    }
+
+    #[test]
+    fn it_fails_on_export_command() {
+        let contents = r#"
+[stages]
+
+[exports]
+TOKEN = { from_command = "echo oops >&2; exit 3" }
+    "#;
+        let config: Config = toml::from_str(contents).unwrap();
+        let err = format!("{:#}", config.resolve_exports().unwrap_err());
+        assert!(err.starts_with("export TOKEN: "));
+        assert!(err.contains("oops"));
+    }
+
+    fn entry(account: &str) -> Keyring {
+        Keyring {
+            service: "ssh".to_string(),
+            account: account.to_string(),
+        }
+    }
+
+    #[test]
+    fn it_looks_up_keyring() {
+        // fake secret-tool knows a single entry, it is written and closed
+        // in a folder of its own before it is executed
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("rp-keyring-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("secret-tool");
+        std::fs::write(
+            &tool,
+            "#!/bin/sh\n[ \"$*\" = \"lookup service ssh account deploy\" ] || exit 1\nprintf 'kr-s3cret'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let tool = tool.to_str().unwrap();
+        let deploy = entry("deploy");
+        let command = deploy.lookup_command("linux");
+        assert_eq!(deploy.run_lookup(tool, &command).unwrap(), "kr-s3cret");
+        assert_eq!(crate::prelude::redact("pw kr-s3cret"), "pw ********");
+        let missing = entry("nobody");
+        assert_eq!(
+            missing
+                .run_lookup(tool, &missing.lookup_command("linux"))
+                .unwrap_err()
+                .to_string(),
+            "no keyring entry for service ssh account nobody"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(deploy
+            .run_lookup("/nonexistent/secret-tool", &command)
+            .unwrap_err()
+            .to_string()
+            .starts_with("keyring is unavailable"));
+    }
+
+    #[test]
+    fn it_looks_up_libsecret_on_linux() {
+        let command = entry("deploy").lookup_command("linux");
+        assert_eq!(command.program, "secret-tool");
+        assert_eq!(
+            command.args,
+            ["lookup", "service", "ssh", "account", "deploy"]
+        );
+        assert!(command.envs.is_empty());
+    }
+
+    #[test]
+    fn it_looks_up_keychain_on_macos() {
+        let command = entry("deploy").lookup_command("macos");
+        assert_eq!(command.program, "security");
+        assert_eq!(
+            command.args,
+            ["find-generic-password", "-s", "ssh", "-a", "deploy", "-w"]
+        );
+    }
+
+    #[test]
+    fn it_looks_up_credential_manager_on_windows() {
+        let command = entry("it's me").lookup_command("windows");
+        assert_eq!(command.program, "powershell");
+        assert_eq!(
+            command.args[..3],
+            ["-NoProfile", "-NonInteractive", "-Command"]
+        );
+        // the entry is only in the environment, not in the script
+        assert!(!command.args[3].contains("it's me"));
+        assert!(command.args[3].contains("$env:REMOTE_PLAYBOOK_CREDENTIAL"));
+        assert_eq!(
+            command.envs,
+            [("REMOTE_PLAYBOOK_CREDENTIAL", "it's me.ssh".to_string())]
+        );
+    }
+
+    #[test]
+    fn it_accepts_banner_timeout_alias() {
+        let ssh: Ssh = toml::from_str("banner_timeout_secs = 60").unwrap();
+        assert_eq!(ssh.connect_timeout_secs, Some(60));
+    }
 }
@@ -1,21 +1,27 @@
-use crate::config::{Config, Ssh};
-use anyhow::Context;
+use crate::config::{Config, HostKeyCheck, Ssh};
+use crate::facts::{gather_facts, Facts};
+use crate::prelude::{osinfo, Executor, Os};
+use anyhow::{bail, Context};
 use async_ssh2_tokio::client::{AuthMethod, Client, ServerCheckMethod};
+use async_ssh2_tokio::Error;
+use std::future::Future;
+use std::io;
 use std::path::Path;
+use std::time::Duration;
+use tracing::*;
 
-pub fn tilde_with_context<SI: ?Sized, P, HD>(input: &SI, home_dir: HD) -> String
+pub fn tilde_with_context<SI, P, HD>(input: &SI, home_dir: HD) -> String
 where
-    SI: AsRef<str>,
+    SI: AsRef<str> + ?Sized,
     P: AsRef<Path>,
     HD: FnOnce() -> Option<P>,
 {
     let input_str = input.as_ref();
-    if input_str.starts_with("~") {
-        let input_after_tilde = &input_str[1..];
+    if let Some(input_after_tilde) = input_str.strip_prefix("~") {
         if input_after_tilde.is_empty() || input_after_tilde.starts_with("/") {
             if let Some(hd) = home_dir() {
                 let result = format!("{}{}", hd.as_ref().display(), input_after_tilde);
-                result.into()
+                result
             } else {
                 // home dir is not available
                 input_str.into()
@@ -30,62 +36,625 @@ where
     }
 }
 
-// get ssh client, combine args and config, config has higher priority
-pub async fn get_client(args: Ssh, cfg: &Config) -> anyhow::Result<Client> {
-    let method = {
-        let password = match &cfg.ssh {
-            Some(ssh) => match &ssh.remote_password {
-                Some(password) => password.to_string(),
-                None => args.remote_password.unwrap_or("".to_string()),
-            },
-            None => "".to_string(),
-        };
+/// exponential backoff between connection attempts
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub attempts: u32,
+}
 
-        if password.len() > 0 {
-            AuthMethod::with_password(&password)
-        } else {
-            let raw_path_key = match &cfg.ssh {
-                Some(ssh) => match &ssh.remote_key_file {
-                    Some(file) => file.to_string(),
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            attempts: 8,
+        }
+    }
+}
+
+impl Backoff {
+    /// delay before the next attempt, doubled every time up to the cap
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max)
+    }
+}
+
+/// transport drops and timeouts are worth retrying,
+/// authentication failures and unknown or changed host keys are not
+pub fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::CommandDidntExit => true,
+        Error::SshError(e) => matches!(
+            e,
+            russh::Error::IO(_)
+                | russh::Error::Disconnect
+                | russh::Error::HUP
+                | russh::Error::ConnectionTimeout
+                | russh::Error::Elapsed(_)
+                | russh::Error::SendError
+        ),
+        _ => false,
+    }
+}
+
+/// runs the operation until it succeeds, fails permanently or runs out of attempts
+pub async fn with_backoff<T, F, Fut>(backoff: &Backoff, mut op: F) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if is_transient(&e) && attempt + 1 < backoff.attempts => {
+                let delay = backoff.delay(attempt);
+                warn!("connection failed: {}, reconnecting in {:?}", e, delay);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// default bound of a connection attempt, a slow server fails with a clear error instead of hanging
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn server_check_method(check: HostKeyCheck) -> ServerCheckMethod {
+    match check {
+        HostKeyCheck::None => ServerCheckMethod::NoCheck,
+        HostKeyCheck::KnownHosts => ServerCheckMethod::DefaultKnownHostsFile,
+    }
+}
+
+/// zlib is preferred with compression, the default list starts with none that every server accepts
+pub fn client_config(compression: bool) -> russh::client::Config {
+    let mut config = russh::client::Config::default();
+    if compression {
+        config.preferred.compression = &["zlib@openssh.com", "zlib", "none"];
+    }
+    config
+}
+
+/// refuses unverified host keys in strict mode, unless the run is explicitly insecure
+pub fn enforce_host_key_check(
+    check: HostKeyCheck,
+    strict: bool,
+    insecure: bool,
+) -> anyhow::Result<()> {
+    if strict && check == HostKeyCheck::None && !insecure {
+        bail!("host_key_check is none while strict_host_key_checking is set, pass --insecure to run anyway");
+    }
+    Ok(())
+}
+
+/// connection parameters, kept to reconnect when the connection drops
+#[derive(Debug, Clone)]
+pub struct Connector {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    method: AuthMethod,
+    pub backoff: Backoff,
+    /// bounds every connection attempt: banner, key exchange and authentication
+    pub connect_timeout: Duration,
+    pub host_key_check: HostKeyCheck,
+    pub compression: bool,
+}
+
+impl Connector {
+    // combine args and config, config has higher priority
+    pub fn new(args: Ssh, cfg: &Config) -> anyhow::Result<Self> {
+        let method = {
+            let password = match &cfg.ssh {
+                Some(ssh) => match (&ssh.remote_password, &ssh.password_from_keyring) {
+                    (Some(password), _) => password.to_string(),
+                    (None, Some(entry)) => entry.lookup()?,
+                    (None, None) => args.remote_password.unwrap_or("".to_string()),
+                },
+                None => "".to_string(),
+            };
+
+            if !password.is_empty() {
+                AuthMethod::with_password(&password)
+            } else {
+                let raw_path_key = match &cfg.ssh {
+                    Some(ssh) => match &ssh.remote_key_file {
+                        Some(file) => file.to_string(),
+                        None => args
+                            .remote_key_file
+                            .context("no private key file provided")?,
+                    },
                     None => args
                         .remote_key_file
                         .context("no private key file provided")?,
-                },
-                None => args
-                    .remote_key_file
-                    .context("no private key file provided")?,
-            };
-            let path_key = tilde_with_context(&raw_path_key, dirs::home_dir);
-            let private_key = std::fs::read_to_string(&path_key)
-                .context(format!("invalid private key {}", path_key))?;
-            AuthMethod::with_key(&private_key, None)
-        }
-    };
+                };
+                let path_key = tilde_with_context(&raw_path_key, dirs::home_dir);
+                let private_key = std::fs::read_to_string(&path_key)
+                    .context(format!("invalid private key {}", path_key))?;
+                AuthMethod::with_key(&private_key, None)
+            }
+        };
 
-    let host = match &cfg.ssh {
-        Some(ssh) => match &ssh.remote_host {
-            Some(host) => host.to_string(),
+        let host = match &cfg.ssh {
+            Some(ssh) => match &ssh.remote_host {
+                Some(host) => host.to_string(),
+                None => args.remote_host.unwrap_or("".to_string()),
+            },
             None => args.remote_host.unwrap_or("".to_string()),
-        },
-        None => args.remote_host.unwrap_or("".to_string()),
-    };
-    let port = match &cfg.ssh {
-        Some(ssh) => match &ssh.remote_port {
-            Some(port) => *port,
+        };
+        let port = match &cfg.ssh {
+            Some(ssh) => match &ssh.remote_port {
+                Some(port) => *port,
+                None => args.remote_port.unwrap_or(22),
+            },
             None => args.remote_port.unwrap_or(22),
-        },
-        None => args.remote_port.unwrap_or(22),
-    };
-    let username = match &cfg.ssh {
-        Some(ssh) => match &ssh.remote_user {
-            Some(host) => host.to_string(),
+        };
+        let username = match &cfg.ssh {
+            Some(ssh) => match &ssh.remote_user {
+                Some(host) => host.to_string(),
+                None => args.remote_user.unwrap_or("".to_string()),
+            },
             None => args.remote_user.unwrap_or("".to_string()),
-        },
-        None => args.remote_user.unwrap_or("".to_string()),
-    };
-    Ok(
-        Client::connect((host, port), &username, method, ServerCheckMethod::NoCheck)
+        };
+        Ok(Self {
+            host,
+            port,
+            username,
+            method,
+            backoff: Backoff::default(),
+            connect_timeout: cfg
+                .ssh
+                .as_ref()
+                .and_then(|ssh| ssh.connect_timeout_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            host_key_check: cfg
+                .ssh
+                .as_ref()
+                .and_then(|ssh| ssh.host_key_check)
+                .unwrap_or_default(),
+            compression: cfg
+                .ssh
+                .as_ref()
+                .and_then(|ssh| ssh.compression)
+                .unwrap_or(false),
+        })
+    }
+
+    async fn connect_once(&self) -> Result<Client, Error> {
+        let handshake = Client::connect_with_config(
+            (self.host.as_str(), self.port),
+            &self.username,
+            self.method.clone(),
+            server_check_method(self.host_key_check),
+            client_config(self.compression),
+        );
+        match tokio::time::timeout(self.connect_timeout, handshake).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::SshError(russh::Error::from(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "could not connect and authenticate to {}:{} within {:?}",
+                    self.host, self.port, self.connect_timeout
+                ),
+            )))),
+        }
+    }
+
+    /// the first connection is attempted once, an unreachable host is reported right away
+    #[instrument(skip(self), fields(host = %self.host, port = self.port))]
+    pub async fn connect(&self) -> anyhow::Result<Client> {
+        let client = self
+            .connect_once()
+            .await
+            .with_context(|| format!("failed to connect to {}:{}", self.host, self.port))?;
+        if self.host_key_check == HostKeyCheck::None {
+            crate::prelude::add_warning(format!(
+                "host key of {}:{} is not verified",
+                self.host, self.port
+            ));
+        }
+        Ok(client)
+    }
+
+    /// connects again during the run, retrying transport errors with backoff
+    #[instrument(skip(self), fields(host = %self.host, port = self.port))]
+    pub async fn reconnect(&self) -> anyhow::Result<Client> {
+        with_backoff(&self.backoff, || self.connect_once())
             .await
-            .unwrap(),
-    )
+            .with_context(|| format!("failed to reconnect to {}:{}", self.host, self.port))
+    }
+
+    /// closes the session and opens a new one, i.e. after group membership changes
+    pub async fn reset(&self, client: &mut Client) -> anyhow::Result<()> {
+        if let Err(e) = client.disconnect().await {
+            debug!("disconnect failed: {}", e);
+        }
+        *client = self.reconnect().await?;
+        info!("connection to {}:{} was reset", self.host, self.port);
+        Ok(())
+    }
+
+    /// replaces the client with a new connection if the current one was dropped
+    pub async fn ensure_connected(&self, client: &mut Client) -> anyhow::Result<()> {
+        match client.execute("true").await {
+            Err(e) if is_transient(&e) => {
+                warn!("connection to {}:{} dropped: {}", self.host, self.port, e);
+                *client = self.reconnect().await?;
+                info!("reconnected to {}:{}", self.host, self.port);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// result of `sudo -n true` on the remote host
+#[derive(Debug, PartialEq)]
+pub enum SudoCheck {
+    Ok,
+    PasswordRequired,
+    /// `Defaults requiretty` in sudoers, commands are run without a tty
+    TtyRequired,
+    NotAllowed,
+    Missing,
+    Failed(String),
+}
+
+impl SudoCheck {
+    pub fn classify(exit_status: u32, output: &str) -> Self {
+        if exit_status == 0 {
+            return SudoCheck::Ok;
+        }
+        let output = output.trim();
+        if output.contains("must have a tty") {
+            SudoCheck::TtyRequired
+        } else if output.contains("password is required") || output.contains("terminal is required")
+        {
+            SudoCheck::PasswordRequired
+        } else if output.contains("not in the sudoers file") || output.contains("not allowed to") {
+            SudoCheck::NotAllowed
+        } else if output.contains("command not found") || exit_status == 127 {
+            SudoCheck::Missing
+        } else {
+            SudoCheck::Failed(output.to_string())
+        }
+    }
+}
+
+impl SudoCheck {
+    /// error explaining how to fix sudo for the user on the host
+    pub fn ensure(&self, host: &str, user: &str) -> anyhow::Result<()> {
+        match self {
+            SudoCheck::Ok => Ok(()),
+            SudoCheck::PasswordRequired => {
+                bail!(
+                    "{}: sudo requires a password, configure NOPASSWD in sudoers",
+                    host
+                )
+            }
+            SudoCheck::TtyRequired => {
+                bail!(
+                    "{}: sudo requires a tty, add `Defaults:{} !requiretty` to sudoers",
+                    host,
+                    user
+                )
+            }
+            SudoCheck::NotAllowed => bail!("{}: user is not allowed to use sudo", host),
+            SudoCheck::Missing => bail!("{}: sudo is not installed", host),
+            SudoCheck::Failed(output) => bail!("{}: sudo failed: {}", host, output),
+        }
+    }
+}
+
+pub async fn sudo_check(client: &impl Executor) -> anyhow::Result<SudoCheck> {
+    let result = client.execute("sudo -n true 2>&1").await?;
+    Ok(SudoCheck::classify(result.exit_status, &result.output))
+}
+
+// verify that sudo works without password before running the playbook
+pub async fn check_sudo(client: &Client) -> anyhow::Result<()> {
+    let host = client.get_connection_address().to_string();
+    sudo_check(client)
+        .await?
+        .ensure(&host, client.get_connection_username())
+}
+
+/// host after the upfront probing, before any stage is run
+pub struct PreparedHost<C = Client> {
+    pub client: C,
+    /// time to connect, including retries
+    pub latency: Duration,
+    pub os: Os,
+    pub facts: Facts,
+    pub sudo: SudoCheck,
+}
+
+impl<C> PreparedHost<C> {
+    pub fn can_become(&self) -> bool {
+        self.sudo == SudoCheck::Ok
+    }
+}
+
+impl PreparedHost {
+    /// fails if sudo doesn't work without password
+    pub fn require_become(&self) -> anyhow::Result<()> {
+        let host = self.client.get_connection_address().to_string();
+        self.sudo
+            .ensure(&host, self.client.get_connection_username())
+    }
+}
+
+/// connects and probes the host once: OS, facts and sudo
+#[instrument(skip(connector), fields(host = %connector.host))]
+pub async fn prepare_host(connector: &Connector) -> anyhow::Result<PreparedHost> {
+    let started = std::time::Instant::now();
+    let client = connector.connect().await?;
+    probe_host(client, started.elapsed()).await
+}
+
+/// probes the connected host, the part of prepare_host after connecting
+pub async fn probe_host<C: Executor>(
+    client: C,
+    latency: Duration,
+) -> anyhow::Result<PreparedHost<C>> {
+    let os = osinfo(&client).await;
+    let facts = gather_facts(&client).await?;
+    let sudo = sudo_check(&client).await?;
+    Ok(PreparedHost {
+        client,
+        latency,
+        os,
+        facts,
+        sudo,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::ScriptedHost;
+    use crate::remote::cloud_init::CloudInitStatus;
+    use std::cell::Cell;
+
+    fn quick() -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(4),
+            attempts: 5,
+        }
+    }
+
+    #[test]
+    fn it_doubles_delay_up_to_cap() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(0), Duration::from_secs(1));
+        assert_eq!(backoff.delay(3), Duration::from_secs(8));
+        assert_eq!(backoff.delay(10), Duration::from_secs(60));
+        assert_eq!(backoff.delay(100), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn it_recovers_after_drops() {
+        let calls = Cell::new(0);
+        let result = with_backoff(&quick(), || {
+            calls.set(calls.get() + 1);
+            let n = calls.get();
+            async move {
+                if n < 4 {
+                    Err(Error::CommandDidntExit)
+                } else {
+                    Ok(n)
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_after_attempts() {
+        let calls = Cell::new(0);
+        let result: Result<(), Error> = with_backoff(&quick(), || {
+            calls.set(calls.get() + 1);
+            async { Err(Error::CommandDidntExit) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 5);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_retry_auth_failures() {
+        let calls = Cell::new(0);
+        let result: Result<(), Error> = with_backoff(&quick(), || {
+            calls.set(calls.get() + 1);
+            async { Err(Error::KeyAuthFailed) }
+        })
+        .await;
+        assert!(matches!(result, Err(Error::KeyAuthFailed)));
+        assert_eq!(calls.get(), 1);
+
+        for e in [
+            Error::PasswordWrong,
+            Error::ServerCheckFailed,
+            Error::SshError(russh::Error::UnknownKey),
+            Error::SshError(russh::Error::KeyChanged { line: 3 }),
+            Error::SshError(russh::Error::NotAuthenticated),
+            Error::SshError(russh::Error::NoCommonKexAlgo),
+        ] {
+            assert!(!is_transient(&e), "{:?}", e);
+        }
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert!(is_transient(&Error::SshError(russh::Error::from(refused))));
+        assert!(is_transient(&Error::SshError(russh::Error::Disconnect)));
+    }
+
+    #[test]
+    fn it_classifies_sudo() {
+        assert_eq!(SudoCheck::classify(0, ""), SudoCheck::Ok);
+        assert_eq!(
+            SudoCheck::classify(1, "sudo: a password is required\n"),
+            SudoCheck::PasswordRequired
+        );
+        assert_eq!(
+            SudoCheck::classify(
+                1,
+                "sudo: a terminal is required to read the password; either use the -S option to read from standard input or configure an askpass helper\n"
+            ),
+            SudoCheck::PasswordRequired
+        );
+        assert_eq!(
+            SudoCheck::classify(1, "sudo: sorry, you must have a tty to run sudo\n"),
+            SudoCheck::TtyRequired
+        );
+        assert_eq!(
+            SudoCheck::classify(
+                1,
+                "azureuser is not in the sudoers file.  This incident will be reported.\n"
+            ),
+            SudoCheck::NotAllowed
+        );
+        assert_eq!(
+            SudoCheck::classify(127, "bash: line 1: sudo: command not found\n"),
+            SudoCheck::Missing
+        );
+        assert_eq!(
+            SudoCheck::classify(1, "sudo: unknown error\n"),
+            SudoCheck::Failed("sudo: unknown error".to_string())
+        );
+    }
+
+    #[test]
+    fn it_prefers_compression() {
+        assert_eq!(client_config(false).preferred.compression[0], "none");
+        assert_eq!(
+            client_config(true).preferred.compression,
+            &["zlib@openssh.com", "zlib", "none"]
+        );
+    }
+
+    #[test]
+    fn it_enforces_host_key_check() {
+        assert!(enforce_host_key_check(HostKeyCheck::None, true, false).is_err());
+        assert!(enforce_host_key_check(HostKeyCheck::None, true, true).is_ok());
+        assert!(enforce_host_key_check(HostKeyCheck::KnownHosts, true, false).is_ok());
+        assert!(enforce_host_key_check(HostKeyCheck::None, false, false).is_ok());
+    }
+
+    #[test]
+    fn it_explains_sudo_failures() {
+        assert!(SudoCheck::Ok.ensure("10.0.0.5:22", "azureuser").is_ok());
+        let err = SudoCheck::TtyRequired
+            .ensure("10.0.0.5:22", "azureuser")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "10.0.0.5:22: sudo requires a tty, add `Defaults:azureuser !requiretty` to sudoers"
+        );
+        let err = SudoCheck::Missing
+            .ensure("10.0.0.5:22", "azureuser")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "10.0.0.5:22: sudo is not installed");
+    }
+
+    // server that accepts the connection and never sends the banner
+    async fn silent_server() -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+        port
+    }
+
+    fn local_connector(port: u16) -> Connector {
+        Connector {
+            host: "127.0.0.1".to_string(),
+            port,
+            username: "azureuser".to_string(),
+            method: AuthMethod::with_password("password"),
+            backoff: Backoff::default(),
+            connect_timeout: Duration::from_millis(100),
+            host_key_check: HostKeyCheck::None,
+            compression: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn it_connects_once_and_reconnects_with_backoff() {
+        // nothing listens on the port once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let mut connector = local_connector(port);
+        let started = std::time::Instant::now();
+        assert!(connector.connect().await.is_err());
+        // the default backoff would wait a second before the second attempt
+        assert!(started.elapsed() < Duration::from_millis(900));
+
+        connector.backoff = quick();
+        let Err(err) = connector.reconnect().await else {
+            panic!("reconnected to a closed port");
+        };
+        assert!(format!("{:#}", err).starts_with("failed to reconnect to 127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn it_bounds_the_handshake() {
+        let connector = local_connector(silent_server().await);
+        let started = std::time::Instant::now();
+        let Err(err) = connector.connect().await else {
+            panic!("connected to a silent server");
+        };
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(format!("{:#}", err).contains("could not connect and authenticate to 127.0.0.1"));
+        let err = err.downcast::<Error>().unwrap();
+        assert!(is_transient(&err));
+    }
+
+    #[tokio::test]
+    async fn it_probes_the_host_once() {
+        let host = ScriptedHost::new(vec![
+            (
+                "uname -a",
+                0,
+                "Linux web 6.8.0-45-generic #45-Ubuntu SMP x86_64 GNU/Linux\n",
+            ),
+            ("dpkg --print-architecture", 0, "amd64\n"),
+            ("/etc/os-release", 0, "ID=ubuntu\nVERSION_ID=\"24.04\"\n"),
+            ("cloud-init status", 0, "status: done\n"),
+            (
+                "systemctl list-units",
+                0,
+                "ssh.service loaded active running OpenBSD Secure Shell server\n",
+            ),
+            (
+                "systemctl list-unit-files",
+                0,
+                "ssh.service enabled enabled\n",
+            ),
+            ("sudo -n true", 1, "sudo: a password is required\n"),
+        ]);
+        let prepared = probe_host(host, Duration::from_millis(12)).await.unwrap();
+        assert_eq!(prepared.latency, Duration::from_millis(12));
+        assert!(matches!(prepared.os, Os::Ubuntu));
+        assert_eq!(prepared.facts.dpkg_architecture.as_deref(), Some("amd64"));
+        assert_eq!(prepared.facts.pkg_mgr.as_deref(), Some("apt"));
+        assert_eq!(prepared.facts.cloud_init, Some(CloudInitStatus::Done));
+        let ssh = &prepared.facts.services["ssh"];
+        assert!(ssh.is_running() && ssh.is_enabled());
+        assert_eq!(prepared.sudo, SudoCheck::PasswordRequired);
+        assert!(!prepared.can_become());
+        let executed = prepared.client.executed();
+        assert_eq!(executed.iter().filter(|c| c.contains("uname")).count(), 1);
+    }
 }
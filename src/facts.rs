@@ -0,0 +1,403 @@
+use crate::prelude::*;
+use crate::remote::cloud_init::{cloud_init_status, CloudInitStatus};
+
+/// facts gathered from the remote host
+#[derive(Debug, Default, Serialize)]
+pub struct Facts {
+    /// native architecture reported by dpkg, i.e. amd64
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dpkg_architecture: Option<String>,
+    /// package manager of the distribution, i.e. apt, dnf, yum, apk or pacman
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkg_mgr: Option<String>,
+    /// state of cloud-init, if it is installed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_init: Option<CloudInitStatus>,
+    /// systemd services, by the name without `.service` suffix
+    pub services: Map<String, Service>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Service {
+    /// load state, i.e. loaded, not-found
+    pub load: String,
+    /// active state, i.e. active, inactive, failed
+    pub active: String,
+    /// low-level state, i.e. running, exited, dead
+    pub sub: String,
+    /// unit file state, i.e. enabled, disabled, static
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<String>,
+}
+
+impl Service {
+    pub fn is_running(&self) -> bool {
+        self.active == "active" && self.sub == "running"
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.as_deref() == Some("enabled")
+    }
+}
+
+fn service_name(unit: &str) -> &str {
+    unit.strip_suffix(".service").unwrap_or(unit)
+}
+
+/// parses `systemctl list-units --type=service --all --no-legend` output
+pub fn parse_list_units(output: &str) -> Map<String, Service> {
+    let mut services = Map::new();
+    for line in output.lines() {
+        // failed and missing units are marked with a bullet
+        let line = line.trim_start().trim_start_matches('●').trim_start();
+        let mut columns = line.split_whitespace();
+        let (Some(unit), Some(load), Some(active), Some(sub)) = (
+            columns.next(),
+            columns.next(),
+            columns.next(),
+            columns.next(),
+        ) else {
+            continue;
+        };
+        services.insert(
+            service_name(unit).to_string(),
+            Service {
+                load: load.to_string(),
+                active: active.to_string(),
+                sub: sub.to_string(),
+                enabled: None,
+            },
+        );
+    }
+    services
+}
+
+/// parses `systemctl list-unit-files --type=service --no-legend` output
+/// into the enabled state of every unit file
+pub fn parse_list_unit_files(output: &str) -> Map<String, String> {
+    let mut states = Map::new();
+    for line in output.lines() {
+        let mut columns = line.split_whitespace();
+        if let (Some(unit), Some(state)) = (columns.next(), columns.next()) {
+            states.insert(service_name(unit).to_string(), state.to_string());
+        }
+    }
+    states
+}
+
+#[instrument(skip(client))]
+pub async fn services(client: &impl Executor) -> anyhow::Result<Map<String, Service>> {
+    let cmd = "systemctl list-units --type=service --all --no-legend --plain 2>&1";
+    let mut services = parse_list_units(&run(client, cmd).await?.output);
+
+    let cmd = "systemctl list-unit-files --type=service --no-legend 2>&1";
+    for (name, state) in parse_list_unit_files(&run(client, cmd).await?.output) {
+        // unit files that were never loaded are still worth reporting
+        let service = services.entry(name).or_insert_with(|| Service {
+            load: "not-loaded".to_string(),
+            active: "inactive".to_string(),
+            sub: "dead".to_string(),
+            enabled: None,
+        });
+        service.enabled = Some(state);
+    }
+    Ok(services)
+}
+
+/// parses KEY=value pairs of /etc/os-release
+pub fn parse_os_release(content: &str) -> Map<String, String> {
+    content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().trim_matches('"').to_string()))
+        .collect()
+}
+
+fn pkg_mgr_of(id: &str, version: &str) -> Option<&'static str> {
+    let major = version
+        .split('.')
+        .next()
+        .and_then(|v| v.parse::<u32>().ok());
+    match id {
+        "debian" | "ubuntu" | "raspbian" | "linuxmint" | "pop" => Some("apt"),
+        // dnf replaced yum in EL8
+        "centos" | "rhel" | "ol" | "scientific" if major.is_some_and(|m| m < 8) => Some("yum"),
+        "amzn" if version == "2" => Some("yum"),
+        "fedora" | "rhel" | "centos" | "rocky" | "almalinux" | "ol" | "amzn" => Some("dnf"),
+        "alpine" => Some("apk"),
+        "arch" | "manjaro" | "endeavouros" => Some("pacman"),
+        _ => None,
+    }
+}
+
+/// package manager by ID of the distribution, falling back to ID_LIKE
+pub fn pkg_mgr(os_release: &Map<String, String>) -> Option<&'static str> {
+    let version = os_release
+        .get("VERSION_ID")
+        .map(|x| x.as_str())
+        .unwrap_or("");
+    let id = os_release.get("ID").map(|x| x.as_str()).unwrap_or("");
+    pkg_mgr_of(id, version).or_else(|| {
+        os_release
+            .get("ID_LIKE")?
+            .split_whitespace()
+            .find_map(|like| pkg_mgr_of(like, version))
+    })
+}
+
+#[instrument(skip(client))]
+pub async fn dpkg_architecture(client: &impl Executor) -> anyhow::Result<String> {
+    let out = run(client, "dpkg --print-architecture 2>&1").await?;
+    Ok(out.output.trim().to_string())
+}
+
+#[instrument(skip(client))]
+pub async fn gather_facts(client: &impl Executor) -> anyhow::Result<Facts> {
+    Ok(Facts {
+        dpkg_architecture: dpkg_architecture(client).await.ok(),
+        pkg_mgr: best_effort(
+            "pkg_mgr",
+            read_file(client, "/etc/os-release").await.map(|content| {
+                content.and_then(|c| pkg_mgr(&parse_os_release(&c)).map(|x| x.to_string()))
+            }),
+        ),
+        cloud_init: best_effort("cloud_init", cloud_init_status(client).await),
+        services: best_effort("services", services(client).await),
+    })
+}
+
+/// facts are gathered for every run, so a fact that the host can't provide,
+/// i.e. services without systemd, is a warning and not a failure
+fn best_effort<T: Default>(fact: &str, result: anyhow::Result<T>) -> T {
+    result.unwrap_or_else(|e| {
+        add_warning(format!("{} fact is not available: {}", fact, e));
+        T::default()
+    })
+}
+
+/// facts keyed by the host, the output of the facts subcommand
+pub fn by_host(host: &str, facts: Facts) -> Map<String, Facts> {
+    let mut out = Map::new();
+    out.insert(host.to_string(), facts);
+    out
+}
+
+/// change of a single fact between two snapshots, by its dotted path
+#[derive(Debug, PartialEq)]
+pub enum FactChange {
+    Added(String, serde_json::Value),
+    Removed(String, serde_json::Value),
+    Changed(String, serde_json::Value, serde_json::Value),
+}
+
+impl std::fmt::Display for FactChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FactChange::Added(path, value) => write!(f, "+ {}: {}", path, value),
+            FactChange::Removed(path, value) => write!(f, "- {}: {}", path, value),
+            FactChange::Changed(path, before, after) => {
+                write!(f, "~ {}: {} -> {}", path, before, after)
+            }
+        }
+    }
+}
+
+fn diff_values(
+    path: &str,
+    before: &serde_json::Value,
+    after: &serde_json::Value,
+    out: &mut Vec<FactChange>,
+) {
+    use serde_json::Value;
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            for (key, value) in before {
+                match after.get(key) {
+                    Some(other) => diff_values(&join(key), value, other, out),
+                    None => out.push(FactChange::Removed(join(key), value.clone())),
+                }
+            }
+            for (key, value) in after {
+                if !before.contains_key(key) {
+                    out.push(FactChange::Added(join(key), value.clone()));
+                }
+            }
+        }
+        _ if before != after => out.push(FactChange::Changed(
+            path.to_string(),
+            before.clone(),
+            after.clone(),
+        )),
+        _ => {}
+    }
+}
+
+/// changes between two outputs of the facts action, by the host
+pub fn diff_facts(
+    before: &serde_json::Value,
+    after: &serde_json::Value,
+) -> Map<String, Vec<FactChange>> {
+    let empty = serde_json::Value::Object(Default::default());
+    let hosts = |v: &serde_json::Value| {
+        v.as_object()
+            .map(|x| x.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default()
+    };
+    let mut out = Map::new();
+    for host in hosts(before).into_iter().chain(hosts(after)) {
+        if out.contains_key(&host) {
+            continue;
+        }
+        let mut changes = vec![];
+        diff_values(
+            "",
+            before.get(&host).unwrap_or(&empty),
+            after.get(&host).unwrap_or(&empty),
+            &mut changes,
+        );
+        out.insert(host, changes);
+    }
+    out.retain(|_, changes| !changes.is_empty());
+    out
+}
+
+/// text report of the drift, empty if nothing changed
+pub fn drift_report(diff: &Map<String, Vec<FactChange>>) -> String {
+    let mut out = String::new();
+    for (host, changes) in diff {
+        out.push_str(&format!("{}\n", host));
+        for change in changes {
+            out.push_str(&format!("  {}\n", change));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_list_units() {
+        let output = r#"  cron.service                 loaded    active   running Regular background program processing daemon
+● docker.service               loaded    failed   failed  Docker Application Container Engine
+  plymouth-start.service       not-found inactive dead    plymouth-start.service
+ssh.service loaded active running OpenBSD Secure Shell server
+"#;
+        let services = parse_list_units(output);
+        assert_eq!(services.len(), 4);
+        assert!(services["cron"].is_running());
+        assert_eq!(services["docker"].active, "failed");
+        assert!(!services["docker"].is_running());
+        assert_eq!(services["plymouth-start"].load, "not-found");
+        assert!(services["ssh"].is_running());
+    }
+
+    #[test]
+    fn it_parses_list_unit_files() {
+        let output = r#"cron.service                           enabled         enabled
+getty@.service                         enabled         enabled
+systemd-fsck@.service                  static          -
+rsync.service                          disabled        enabled
+"#;
+        let states = parse_list_unit_files(output);
+        assert_eq!(states["cron"], "enabled");
+        assert_eq!(states["getty@"], "enabled");
+        assert_eq!(states["systemd-fsck@"], "static");
+        assert_eq!(states["rsync"], "disabled");
+    }
+
+    #[test]
+    fn it_serializes_facts_by_host() {
+        let output = "ssh.service loaded active running OpenBSD Secure Shell server\n";
+        let facts = Facts {
+            dpkg_architecture: Some("amd64".to_string()),
+            pkg_mgr: Some("apt".to_string()),
+            cloud_init: Some(CloudInitStatus::Done),
+            services: parse_list_units(output),
+        };
+        let json = serde_json::to_value(by_host("10.0.0.5:22", facts)).unwrap();
+        let host = &json["10.0.0.5:22"];
+        assert_eq!(host["dpkg_architecture"], "amd64");
+        assert_eq!(host["cloud_init"], "done");
+        assert_eq!(host["services"]["ssh"]["active"], "active");
+        assert!(host["services"]["ssh"].get("enabled").is_none());
+    }
+
+    #[test]
+    fn it_detects_pkg_mgr() {
+        let samples = [
+            ("PRETTY_NAME=\"Debian GNU/Linux 12 (bookworm)\"\nID=debian\nVERSION_ID=\"12\"\n", Some("apt")),
+            ("NAME=\"Ubuntu\"\nVERSION_ID=\"22.04\"\nID=ubuntu\nID_LIKE=debian\n", Some("apt")),
+            ("NAME=\"Rocky Linux\"\nID=\"rocky\"\nID_LIKE=\"rhel centos fedora\"\nVERSION_ID=\"9.3\"\n", Some("dnf")),
+            ("NAME=\"CentOS Linux\"\nID=\"centos\"\nVERSION_ID=\"7\"\n", Some("yum")),
+            ("NAME=\"CentOS\"\nID=\"centos\"\nVERSION_ID=\"6.10\"\n", Some("yum")),
+            ("NAME=\"Red Hat Enterprise Linux\"\nID=\"rhel\"\nVERSION_ID=\"8.9\"\n", Some("dnf")),
+            ("NAME=\"Scientific Linux\"\nID=\"scientific\"\nID_LIKE=\"rhel centos fedora\"\nVERSION_ID=\"7.9\"\n", Some("yum")),
+            ("NAME=\"Amazon Linux\"\nID=\"amzn\"\nVERSION_ID=\"2\"\n", Some("yum")),
+            ("NAME=\"Alpine Linux\"\nID=alpine\nVERSION_ID=3.19.1\n", Some("apk")),
+            ("NAME=\"Arch Linux\"\nID=arch\n", Some("pacman")),
+            ("NAME=\"Kali GNU/Linux\"\nID=kali\nID_LIKE=debian\n", Some("apt")),
+            ("NAME=\"openSUSE\"\nID=opensuse-leap\nID_LIKE=\"suse opensuse\"\n", None),
+        ];
+        for (content, expected) in samples {
+            assert_eq!(pkg_mgr(&parse_os_release(content)), expected, "{}", content);
+        }
+    }
+
+    #[test]
+    fn it_diffs_fact_snapshots() {
+        let before = serde_json::json!({
+            "10.0.0.5:22": {
+                "pkg_mgr": "apt",
+                "cloud_init": "running",
+                "services": {
+                    "ssh": { "load": "loaded", "active": "active", "sub": "running" },
+                    "cron": { "load": "loaded", "active": "active", "sub": "running" }
+                }
+            },
+            "10.0.0.6:22": { "pkg_mgr": "apt" }
+        });
+        let after = serde_json::json!({
+            "10.0.0.5:22": {
+                "pkg_mgr": "apt",
+                "cloud_init": "done",
+                "services": {
+                    "ssh": { "load": "loaded", "active": "inactive", "sub": "dead" },
+                    "nginx": { "load": "loaded", "active": "active", "sub": "running" }
+                }
+            },
+            "10.0.0.6:22": { "pkg_mgr": "apt" }
+        });
+        let diff = diff_facts(&before, &after);
+        assert_eq!(diff.len(), 1);
+        let changes = &diff["10.0.0.5:22"];
+        assert!(changes.contains(&FactChange::Changed(
+            "cloud_init".to_string(),
+            "running".into(),
+            "done".into()
+        )));
+        assert!(changes.contains(&FactChange::Changed(
+            "services.ssh.active".to_string(),
+            "active".into(),
+            "inactive".into()
+        )));
+        assert!(changes
+            .iter()
+            .any(|x| matches!(x, FactChange::Removed(path, _) if path == "services.cron")));
+        assert!(changes
+            .iter()
+            .any(|x| matches!(x, FactChange::Added(path, _) if path == "services.nginx")));
+        assert_eq!(changes.len(), 5);
+        let report = drift_report(&diff);
+        assert!(report.starts_with("10.0.0.5:22\n  ~ cloud_init: \"running\" -> \"done\"\n"));
+        assert!(drift_report(&diff_facts(&before, &before)).is_empty());
+    }
+}
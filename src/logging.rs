@@ -1,15 +1,71 @@
+use crate::profile::{profile, ProfileGuard};
 use atty::Stream;
+use std::path::Path;
 use tracing_error::ErrorLayer;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-pub fn start() {
+/// starts logging, with `profile` all spans are also recorded into the trace file
+pub fn start(profile_path: Option<&Path>) -> Option<ProfileGuard> {
     let defaults = "warn";
     let env_filter = EnvFilter::try_from_default_env().unwrap_or(EnvFilter::new(defaults));
     let is_terminal = atty::is(Stream::Stdout);
-    let subscriber = tracing_subscriber::fmt::fmt()
-        .with_env_filter(env_filter)
+    let fmt_layer = fmt::layer()
         .with_ansi(is_terminal)
         .with_span_events(fmt::format::FmtSpan::CLOSE) // enable durations
-        .finish();
-    _ = subscriber.with(ErrorLayer::default()).try_init();
+        .with_filter(env_filter);
+    let (profile_layer, guard) = match profile_path {
+        Some(path) => {
+            let (layer, guard) = profile(path);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    _ = tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(profile_layer)
+        .with(ErrorLayer::default())
+        .try_init();
+    guard
+}
+
+/// span of the whole run, its run_id is attached to every event
+pub fn run_span() -> tracing::Span {
+    tracing::info_span!("run", run_id = %crate::report::run_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_tags_events_with_run_id() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let _run = run_span().entered();
+            tracing::info!("connected");
+            tracing::info_span!("stage", name = "disk").in_scope(|| tracing::warn!("failed"));
+        });
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let tag = format!("run{{run_id={}}}", crate::report::run_id());
+        assert_eq!(output.lines().count(), 2);
+        assert!(output.lines().all(|line| line.contains(&tag)), "{}", output);
+    }
 }
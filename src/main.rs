@@ -1,103 +1,261 @@
 pub mod cli;
 pub mod config;
 pub mod connect;
+pub mod facts;
 pub mod logging;
+pub mod notify;
 pub mod prelude;
+pub mod profile;
 pub mod remote;
+pub mod report;
 
 use clap::Parser;
+use std::process::ExitCode;
 use tracing::*;
 
 #[tokio::main]
-pub async fn main() -> anyhow::Result<()> {
+pub async fn main() -> anyhow::Result<ExitCode> {
     let _ = dotenv::dotenv();
     color_eyre::install().unwrap();
-    logging::start();
 
     let args = cli::Opts::parse();
+    let _profile = logging::start(args.profile.as_deref());
+    run(args).instrument(logging::run_span()).await
+}
+
+async fn run(args: cli::Opts) -> anyhow::Result<ExitCode> {
     debug!("{:?}", args);
     let ssh = args.into_ssh();
 
     match args.action {
+        cli::Action::ListModules => {
+            print!("{}", remote::list_modules());
+        }
+        cli::Action::ModuleHelp { name } => {
+            print!("{}", remote::module_help(&name)?);
+        }
+        cli::Action::Facts { file } => {
+            let cfg: config::Config =
+                toml::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+            prelude::set_command_prefix(cfg.command_prefix.clone());
+            prelude::set_umask(cfg.umask.clone())?;
+            prelude::set_python_interpreter(cfg.python_interpreter.clone());
+            let connector = connect::Connector::new(ssh, &cfg)?;
+            connect::enforce_host_key_check(
+                connector.host_key_check,
+                cfg.strict_host_key_checking.unwrap_or(false),
+                args.insecure,
+            )?;
+            let prepared = connect::prepare_host(&connector).await?;
+            let host = prepared.client.get_connection_address().to_string();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&facts::by_host(&host, prepared.facts))?
+            );
+        }
+        cli::Action::DiffFacts { before, after } => {
+            let read = |path: &std::path::Path| -> anyhow::Result<serde_json::Value> {
+                use anyhow::Context;
+                let content =
+                    std::fs::read_to_string(path).with_context(|| format!("{}", path.display()))?;
+                serde_json::from_str(&content).with_context(|| format!("{}", path.display()))
+            };
+            let diff = facts::diff_facts(&read(&before)?, &read(&after)?);
+            print!("{}", facts::drift_report(&diff));
+        }
         cli::Action::Install { file, stage } => {
             // read toml config from file
             let cfg: config::Config =
                 toml::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
-            let client = connect::get_client(ssh, &cfg).await.unwrap();
+            prelude::set_command_prefix(cfg.command_prefix.clone());
+            prelude::set_umask(cfg.umask.clone())?;
+            prelude::set_python_interpreter(cfg.python_interpreter.clone());
+            let connector = connect::Connector::new(ssh, &cfg)?;
+            connect::enforce_host_key_check(
+                connector.host_key_check,
+                cfg.strict_host_key_checking.unwrap_or(false),
+                args.insecure,
+            )?;
+            let prepared = match connect::prepare_host(&connector).await {
+                Ok(prepared) => prepared,
+                Err(e) => {
+                    let host = format!("{}:{}", connector.host, connector.port);
+                    let report = report::RunReport::unreachable("install", &host, &e);
+                    notify::notify(args.notify_webhook.as_deref(), args.notify_format, &report);
+                    return Err(e);
+                }
+            };
+            let host = prepared.client.get_connection_address().to_string();
+            let mut report = report::RunReport::new("install", &host);
+            report.warn_only = args.warn_only;
+            report.failure_threshold = cfg.host_failure_threshold;
+            if cfg.needs_sudo(stage.as_deref()) {
+                if let Err(e) = prepared.require_become() {
+                    report.fail(&e);
+                    report.finish();
+                    print!("{}", report.recap());
+                    notify::notify(args.notify_webhook.as_deref(), args.notify_format, &report);
+                    return Err(e);
+                }
+            }
+            let mut client = prepared.client;
             match stage {
                 Some(stage) => {
                     if stage == "aliases" {
                         if let Some(items) = &cfg.aliases {
-                            remote::alias::install(&client, items).await.unwrap();
+                            report.add(remote::alias::install(&client, items).await.unwrap());
                         } else {
                             panic!("no aliases declared");
                         }
                     } else if stage == "exports" {
-                        if let Some(items) = &cfg.exports {
-                            remote::export::install(&client, items).await.unwrap();
+                        // from_command values are resolved only when they are installed
+                        if let Some(items) = &cfg.resolve_exports()? {
+                            report.add(remote::export::install(&client, items).await.unwrap());
                         } else {
                             panic!("no exports declared");
                         }
                     } else {
-                        remote::install(&client, &stage, &cfg.stages[&stage])
-                            .await
-                            .unwrap();
+                        report.add(
+                            remote::install(&client, &stage, &cfg.stages[&stage])
+                                .await
+                                .unwrap(),
+                        );
+                        if cfg.stages[&stage].resets_connection() {
+                            connector.reset(&mut client).await?;
+                        }
                         if let Some(aliases) = &cfg.aliases {
-                            remote::alias::install(&client, aliases).await.unwrap();
+                            report.add(remote::alias::install(&client, aliases).await.unwrap());
                         }
                     }
                 }
                 None => {
-                    for (name, stage) in cfg.stages {
-                        remote::install(&client, &name, &stage).await.unwrap();
+                    for (name, stage) in &cfg.stages {
+                        if report.failing_fast() {
+                            report.skip(name);
+                            continue;
+                        }
+                        connector.ensure_connected(&mut client).await?;
+                        report.add(remote::install(&client, name, stage).await.unwrap());
+                        if stage.resets_connection() {
+                            connector.reset(&mut client).await?;
+                        }
                     }
                     if let Some(items) = &cfg.aliases {
-                        remote::alias::install(&client, items).await.unwrap();
+                        if report.failing_fast() {
+                            report.skip("aliases");
+                        } else {
+                            connector.ensure_connected(&mut client).await?;
+                            report.add(remote::alias::install(&client, items).await.unwrap());
+                        }
                     }
-                    if let Some(items) = &cfg.exports {
-                        remote::export::install(&client, items).await.unwrap();
+                    if cfg.exports.is_some() {
+                        if report.failing_fast() {
+                            report.skip("exports");
+                        } else {
+                            let items = cfg.resolve_exports()?.unwrap_or_default();
+                            connector.ensure_connected(&mut client).await?;
+                            report.add(remote::export::install(&client, &items).await.unwrap());
+                        }
                     }
                 }
             }
+            report.finish();
+            print!("{}", report.recap());
+            notify::notify(args.notify_webhook.as_deref(), args.notify_format, &report);
+            if !report.success {
+                anyhow::bail!("{} modules failed", report.failed);
+            }
+            return Ok(report.exit_code(args.detailed_exitcode).into());
         }
-        cli::Action::Check { file, stage } => {
+        cli::Action::Check {
+            file,
+            stage,
+            output,
+        } => {
             let cfg: config::Config =
                 toml::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
-            let client = connect::get_client(ssh, &cfg).await.unwrap();
+            prelude::set_command_prefix(cfg.command_prefix.clone());
+            prelude::set_umask(cfg.umask.clone())?;
+            prelude::set_python_interpreter(cfg.python_interpreter.clone());
+            let connector = connect::Connector::new(ssh, &cfg)?;
+            connect::enforce_host_key_check(
+                connector.host_key_check,
+                cfg.strict_host_key_checking.unwrap_or(false),
+                args.insecure,
+            )?;
+            let structured = output != cli::OutputFormat::Text;
+            report::set_quiet(structured);
+            let prepared = match connect::prepare_host(&connector).await {
+                Ok(prepared) => prepared,
+                Err(e) => {
+                    let host = format!("{}:{}", connector.host, connector.port);
+                    let report = report::RunReport::unreachable("check", &host, &e);
+                    if structured {
+                        print!("{}", report.render(output)?);
+                    }
+                    notify::notify(args.notify_webhook.as_deref(), args.notify_format, &report);
+                    return Err(e);
+                }
+            };
+            let host = prepared.client.get_connection_address().to_string();
+            let mut report = report::RunReport::new("check", &host);
+            report.warn_only = args.warn_only;
+            report.connected(prepared.latency, prepared.os.name());
+            if cfg.needs_sudo(stage.as_deref()) {
+                if let Err(e) = prepared.require_become() {
+                    report.fail(&e);
+                    report.finish();
+                    print!("{}", report.render(output)?);
+                    notify::notify(args.notify_webhook.as_deref(), args.notify_format, &report);
+                    return Err(e);
+                }
+            }
+            let mut client = prepared.client;
             match stage {
                 Some(stage) => {
                     if stage == "aliases" {
                         if let Some(items) = &cfg.aliases {
-                            remote::alias::check(&client, items).await.unwrap();
+                            report.add(remote::alias::check(&client, items).await.unwrap());
                         } else {
                             panic!("no aliases declared");
                         }
-                    } else if stage == "aliases" {
+                    } else if stage == "exports" {
                         if let Some(items) = &cfg.exports {
-                            remote::export::check(&client, items).await.unwrap();
+                            report.add(remote::export::check(&client, items).await.unwrap());
                         } else {
                             panic!("no exports declared");
                         }
                     } else {
-                        remote::check(&client, &stage, &cfg.stages[&stage])
-                            .await
-                            .unwrap();
+                        report.add(
+                            remote::check(&client, &stage, &cfg.stages[&stage])
+                                .await
+                                .unwrap(),
+                        );
                     }
                 }
                 None => {
                     for (name, stage) in cfg.stages {
-                        remote::check(&client, &name, &stage).await.unwrap();
+                        connector.ensure_connected(&mut client).await?;
+                        report.add(remote::check(&client, &name, &stage).await.unwrap());
                     }
+                    connector.ensure_connected(&mut client).await?;
                     if let Some(aliases) = &cfg.aliases {
-                        remote::alias::check(&client, aliases).await.unwrap();
+                        report.add(remote::alias::check(&client, aliases).await.unwrap());
                     }
                     if let Some(exports) = &cfg.exports {
-                        remote::export::check(&client, exports).await.unwrap();
+                        report.add(remote::export::check(&client, exports).await.unwrap());
                     }
                 }
             }
+            report.finish();
+            print!("{}", report.render(output)?);
+            notify::notify(args.notify_webhook.as_deref(), args.notify_format, &report);
+            if !report.success {
+                anyhow::bail!("{} modules failed", report.failed);
+            }
+            return Ok(report.exit_code(args.detailed_exitcode).into());
         }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
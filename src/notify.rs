@@ -0,0 +1,181 @@
+use crate::cli::NotifyFormat;
+use crate::report::RunReport;
+use anyhow::{bail, Context};
+use serde_json::json;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tracing::*;
+
+pub fn payload(report: &RunReport, format: NotifyFormat) -> anyhow::Result<serde_json::Value> {
+    match format {
+        NotifyFormat::Json => Ok(serde_json::to_value(report)?),
+        NotifyFormat::Slack => {
+            if !report.reachable {
+                let text = format!(
+                    "remote-playbook {} on {}: UNREACHABLE, {}",
+                    report.action,
+                    report.host,
+                    report.error.as_deref().unwrap_or_default()
+                );
+                return Ok(json!({ "text": text }));
+            }
+            let result = if report.success { "SUCCESS" } else { "FAILURE" };
+            let mut text = format!(
+                "remote-playbook {} on {}: {}, {} ok, {} changed, {} failed",
+                report.action, report.host, result, report.ok, report.changed, report.failed
+            );
+            if let Some(error) = &report.error {
+                text.push_str(&format!(", {}", error));
+            }
+            for stage in &report.stages {
+                for module in stage.modules.iter().filter(|m| !m.success) {
+                    text.push_str(&format!("\n- {}/{}", stage.name, module.module));
+                    if let Some(error) = &module.error {
+                        text.push_str(&format!(": {}", error));
+                    }
+                }
+            }
+            Ok(json!({ "text": text }))
+        }
+    }
+}
+
+// the request is sent with local curl, same as the rest of the tool relies on shell tools
+pub fn post(url: &str, body: &serde_json::Value) -> anyhow::Result<()> {
+    let mut child = Command::new("curl")
+        .args(["-fsS", "-X", "POST", "-H", "Content-Type: application/json"])
+        .args(["--data-binary", "@-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run curl")?;
+    child
+        .stdin
+        .take()
+        .context("curl stdin")?
+        .write_all(body.to_string().as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// post the report to the webhook, failure to notify doesn't fail the run
+pub fn notify(url: Option<&str>, format: NotifyFormat, report: &RunReport) {
+    let Some(url) = url else {
+        return;
+    };
+    let result = payload(report, format).and_then(|body| post(url, &body));
+    if let Err(e) = result {
+        warn!("webhook notification failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::StageReport;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+
+    fn sample() -> RunReport {
+        let mut stage = StageReport::new("disk");
+        stage.installed("mount", Ok(true));
+        stage.installed(
+            "mkdir",
+            Err::<bool, _>(anyhow::anyhow!("permission denied")),
+        );
+        let mut report = RunReport::new("install", "10.0.0.5:22");
+        report.add(stage);
+        report
+    }
+
+    // accepts a single request and returns its body
+    fn serve_once(listener: TcpListener) -> std::thread::JoinHandle<String> {
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(body).unwrap()
+        })
+    }
+
+    #[test]
+    fn it_posts_json_report() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = serve_once(listener);
+
+        let body = payload(&sample(), NotifyFormat::Json).unwrap();
+        post(&url, &body).unwrap();
+
+        let posted: serde_json::Value = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert_eq!(posted["action"], "install");
+        assert_eq!(posted["host"], "10.0.0.5:22");
+        assert_eq!(posted["success"], false);
+        assert_eq!(posted["ok"], 1);
+        assert_eq!(posted["changed"], 1);
+        assert_eq!(posted["failed"], 1);
+        assert_eq!(posted["stages"][0]["name"], "disk");
+        assert_eq!(
+            posted["stages"][0]["modules"][1]["error"],
+            "permission denied"
+        );
+    }
+
+    #[test]
+    fn it_formats_slack_message() {
+        let body = payload(&sample(), NotifyFormat::Slack).unwrap();
+        assert_eq!(
+            body["text"],
+            "remote-playbook install on 10.0.0.5:22: FAILURE, 1 ok, 1 changed, 1 failed\n- disk/mkdir: permission denied"
+        );
+
+        let error = anyhow::anyhow!("connection refused").context("failed to connect");
+        let report = RunReport::unreachable("install", "10.0.0.6:22", &error);
+        let body = payload(&report, NotifyFormat::Slack).unwrap();
+        assert_eq!(
+            body["text"],
+            "remote-playbook install on 10.0.0.6:22: UNREACHABLE, failed to connect: connection refused"
+        );
+        let body = payload(&report, NotifyFormat::Json).unwrap();
+        assert_eq!(body["reachable"], false);
+        assert_eq!(body["changed"], 0);
+
+        let mut report = RunReport::new("install", "10.0.0.5:22");
+        report.fail(&anyhow::anyhow!("sudo requires a password"));
+        let body = payload(&report, NotifyFormat::Slack).unwrap();
+        assert_eq!(
+            body["text"],
+            "remote-playbook install on 10.0.0.5:22: FAILURE, 0 ok, 0 changed, 0 failed, sudo requires a password"
+        );
+    }
+
+    #[test]
+    fn it_fails_on_unreachable_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(post(&url, &json!({})).is_err());
+        // notification errors are only logged
+        notify(Some(&url), NotifyFormat::Json, &sample());
+    }
+}
@@ -1,19 +1,163 @@
 pub use anyhow::{bail, Context};
 pub use async_ssh2_tokio::client::{Client, CommandExecutedResult};
 pub use color_eyre::owo_colors::OwoColorize;
-pub use serde::{Deserialize, Serialize};
+pub use serde::{de::DeserializeOwned, Deserialize, Serialize};
 pub use serde_aux::prelude::*;
 pub use std::collections::BTreeMap as Map;
 pub use tracing::*;
 
+use base64::{engine::general_purpose, Engine as _};
+use std::sync::Mutex;
+
+/// runs a command on the host, implemented by the SSH client
+/// and by scripted hosts in tests
+pub trait Executor: Sync {
+    fn execute(
+        &self,
+        cmd: &str,
+    ) -> impl std::future::Future<Output = Result<CommandExecutedResult, async_ssh2_tokio::Error>> + Send;
+}
+
+impl Executor for Client {
+    fn execute(
+        &self,
+        cmd: &str,
+    ) -> impl std::future::Future<Output = Result<CommandExecutedResult, async_ssh2_tokio::Error>> + Send
+    {
+        Client::execute(self, cmd)
+    }
+}
+
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// register the value that should never appear in logs
+pub fn add_secret(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secrets.iter().any(|x| x == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+pub fn is_secret(value: &str) -> bool {
+    SECRETS.lock().unwrap().iter().any(|x| x == value)
+}
+
+/// replace registered secrets in the text
+pub fn redact(text: &str) -> String {
+    let mut out = text.to_string();
+    for secret in SECRETS.lock().unwrap().iter() {
+        out = out.replace(secret, "********");
+    }
+    out
+}
+
+/// quotes the argument for the shell, single quotes inside are written as `'\''`
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// quotes a remote path for the shell, a leading `~/` is kept unquoted
+/// so the home folder is still expanded
+pub fn quote_path(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("~/{}", shell_quote(rest)),
+        None if path == "~" => path.to_string(),
+        None => shell_quote(path),
+    }
+}
+
+/// quotes every argument and joins them into a command line
+pub fn shell_join(args: &[&str]) -> String {
+    args.iter()
+        .map(|x| shell_quote(x))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+static COMMAND_PREFIX: Mutex<Option<String>> = Mutex::new(None);
+
+/// wrapper for every command, i.e. `nice -n 10 ionice -c3` or `timeout 600`
+pub fn set_command_prefix(prefix: Option<String>) {
+    *COMMAND_PREFIX.lock().unwrap() = prefix.filter(|x| !x.trim().is_empty());
+}
+
+/// the command is passed to bash as a single quoted argument,
+/// so the prefix doesn't change its escaping and the exit status is kept
+pub fn with_prefix(prefix: Option<&str>, cmd: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{} bash -c {}", prefix, shell_quote(cmd)),
+        None => cmd.to_string(),
+    }
+}
+
+static UMASK: Mutex<Option<String>> = Mutex::new(None);
+
+/// umask for every command, so created files get predictable modes
+pub fn set_umask(umask: Option<String>) -> anyhow::Result<()> {
+    if let Some(umask) = &umask {
+        validate_umask(umask)?;
+    }
+    *UMASK.lock().unwrap() = umask;
+    Ok(())
+}
+
+pub fn validate_umask(umask: &str) -> anyhow::Result<()> {
+    if !(3..=4).contains(&umask.len()) || !umask.chars().all(|c| ('0'..='7').contains(&c)) {
+        bail!("invalid umask {}, expected octal like 022", umask);
+    }
+    Ok(())
+}
+
+pub fn with_umask(umask: Option<&str>, cmd: &str) -> String {
+    match umask {
+        Some(umask) => format!("umask {}; {}", umask, cmd),
+        None => cmd.to_string(),
+    }
+}
+
+fn prefixed(cmd: &str) -> String {
+    let cmd = with_umask(UMASK.lock().unwrap().as_deref(), cmd);
+    with_prefix(COMMAND_PREFIX.lock().unwrap().as_deref(), &cmd)
+}
+
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// log the warning and keep it for the recap of the run
+pub fn add_warning(message: impl Into<String>) {
+    let message = message.into();
+    warn!("{}", message);
+    let mut warnings = WARNINGS.lock().unwrap();
+    if !warnings.contains(&message) {
+        warnings.push(message);
+    }
+}
+
+/// warnings collected since the last call
+pub fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap())
+}
+
 pub enum Os {
     Ubuntu,
     Debian,
     Unsupported,
 }
 
-pub async fn osinfo(client: &Client) -> Os {
-    match silent(&client, "uname -a").await {
+impl Os {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Os::Ubuntu => "ubuntu",
+            Os::Debian => "debian",
+            Os::Unsupported => "unsupported",
+        }
+    }
+}
+
+pub async fn osinfo(client: &impl Executor) -> Os {
+    match silent(client, "uname -a").await {
         Ok(out) => {
             if out.output.contains("Ubuntu") {
                 Os::Ubuntu
@@ -27,11 +171,11 @@ pub async fn osinfo(client: &Client) -> Os {
     }
 }
 
-pub async fn which(client: &Client, cmd: &str) -> anyhow::Result<String> {
-    match silent(&client, &cmd).await {
+pub async fn which(client: &impl Executor, cmd: &str) -> anyhow::Result<String> {
+    match silent(client, cmd).await {
         Ok(out) => {
             if out.exit_status == 0 {
-                return Ok(out.output.trim().to_string());
+                Ok(out.output.trim().to_string())
             } else {
                 bail!(out.output.trim().replace("bash: line 1: ", "").to_string());
             }
@@ -40,8 +184,8 @@ pub async fn which(client: &Client, cmd: &str) -> anyhow::Result<String> {
     }
 }
 
-pub async fn some_output(client: &Client, cmd: &str) -> bool {
-    match silent(&client, &cmd).await {
+pub async fn some_output(client: &impl Executor, cmd: &str) -> bool {
+    match silent(client, cmd).await {
         Ok(out) => {
             if out.exit_status == 0 {
                 !out.output.trim().is_empty()
@@ -53,35 +197,527 @@ pub async fn some_output(client: &Client, cmd: &str) -> bool {
     }
 }
 
-pub async fn file_exists(client: &Client, filename: &str) -> bool {
-    let cmd = format!("ls -1 {}", filename);
-    match silent(&client, &cmd).await {
+fn which_any_cmd(candidates: &[&str]) -> String {
+    format!(
+        "for c in {}; do p=$(command -v \"$c\") && echo \"$c $p\"; done",
+        shell_join(candidates)
+    )
+}
+
+/// picks the first found candidate in the order of preference
+pub fn parse_which_any(candidates: &[&str], output: &str) -> Option<(String, String)> {
+    let found: Map<&str, &str> = output
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .collect();
+    candidates
+        .iter()
+        .find_map(|c| found.get(c).map(|path| (c.to_string(), path.to_string())))
+}
+
+/// returns the first available command and its path, i.e. for `&["curl", "wget"]`
+pub async fn which_any(
+    client: &impl Executor,
+    candidates: &[&str],
+) -> anyhow::Result<(String, String)> {
+    let out = silent(client, &which_any_cmd(candidates)).await?;
+    match parse_which_any(candidates, &out.output) {
+        Some(found) => Ok(found),
+        None => bail!("none of {} is installed", candidates.join(", ")),
+    }
+}
+
+static PYTHON_INTERPRETER: Mutex<Option<String>> = Mutex::new(None);
+static PYTHON: Mutex<Option<String>> = Mutex::new(None);
+
+/// interpreter for modules shelling out to python, i.e. python3.11 or /opt/python/bin/python3
+pub fn set_python_interpreter(python: Option<String>) {
+    *PYTHON_INTERPRETER.lock().unwrap() = python.filter(|x| !x.trim().is_empty());
+    *PYTHON.lock().unwrap() = None;
+}
+
+/// picks the interpreter from `which_any` output, the configured one must be present
+pub fn resolve_python(configured: Option<&str>, output: &str) -> anyhow::Result<String> {
+    match configured {
+        Some(python) => match parse_which_any(&[python], output) {
+            Some((_, path)) => Ok(path),
+            None => bail!(
+                "python interpreter {} is not found, check python_interpreter",
+                python
+            ),
+        },
+        None => match parse_which_any(&["python3", "python"], output) {
+            Some((_, path)) => Ok(path),
+            None => bail!("neither python3 nor python is installed"),
+        },
+    }
+}
+
+/// python interpreter of the host, resolved once and reused
+pub async fn python(client: &impl Executor) -> anyhow::Result<String> {
+    if let Some(path) = PYTHON.lock().unwrap().clone() {
+        return Ok(path);
+    }
+    let configured = PYTHON_INTERPRETER.lock().unwrap().clone();
+    let candidates = match &configured {
+        Some(python) if python.split_whitespace().count() != 1 => {
+            bail!("invalid python_interpreter {}", python)
+        }
+        Some(python) => vec![python.as_str()],
+        None => vec!["python3", "python"],
+    };
+    let out = silent(client, &which_any_cmd(&candidates)).await?;
+    let path = resolve_python(configured.as_deref(), &out.output)?;
+    debug!("python interpreter: {}", path);
+    *PYTHON.lock().unwrap() = Some(path.clone());
+    Ok(path)
+}
+
+pub async fn file_exists(client: &impl Executor, filename: &str) -> bool {
+    let cmd = format!("ls -1 {}", quote_path(filename));
+    match silent(client, &cmd).await {
         Ok(out) => out.exit_status == 0,
         Err(_) => false,
     }
 }
 
+/// mode and ownership of a remote file, as reported by `stat -c '%a %U %G'`
+#[derive(Debug, PartialEq)]
+pub struct FileStat {
+    pub mode: String,
+    pub owner: String,
+    pub group: String,
+}
+
+impl FileStat {
+    pub fn parse(output: &str) -> Option<Self> {
+        let mut columns = output.split_whitespace();
+        let mode = columns
+            .next()
+            .filter(|m| m.chars().all(|c| c.is_digit(8)))?;
+        Some(Self {
+            mode: mode.to_string(),
+            owner: columns.next()?.to_string(),
+            group: columns.next()?.to_string(),
+        })
+    }
+
+    /// fails if the requested octal mode or `user[:group]` owner didn't stick,
+    /// symbolic modes like `a+r` are not compared
+    pub fn verify(
+        &self,
+        path: &str,
+        mode: Option<&str>,
+        owner: Option<&str>,
+    ) -> anyhow::Result<()> {
+        if let Some(mode) = mode.filter(|m| m.chars().all(|c| c.is_digit(8))) {
+            if mode.trim_start_matches('0') != self.mode.trim_start_matches('0') {
+                bail!("{}: mode is {}, expected {}", path, self.mode, mode);
+            }
+        }
+        if let Some(owner) = owner {
+            let (user, group) = match owner.split_once(':') {
+                Some((user, group)) => (user, Some(group)),
+                None => (owner, None),
+            };
+            if user != self.owner || group.is_some_and(|g| g != self.group) {
+                bail!(
+                    "{}: owner is {}:{}, expected {}",
+                    path,
+                    self.owner,
+                    self.group,
+                    owner
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn stat(client: &impl Executor, path: &str) -> anyhow::Result<FileStat> {
+    let cmd = format!("sudo stat -c '%a %U %G' {} 2>&1", quote_path(path));
+    let out = run(client, &cmd).await?;
+    FileStat::parse(&out.output).with_context(|| format!("unexpected stat output: {}", out.output))
+}
+
+/// mode and ownership of the remote file, None if it doesn't exist
+pub async fn try_stat(client: &impl Executor, path: &str) -> anyhow::Result<Option<FileStat>> {
+    let cmd = format!("sudo stat -c '%a %U %G' {} 2>/dev/null", quote_path(path));
+    let out = silent(client, &cmd).await?;
+    Ok(FileStat::parse(&out.output).filter(|_| out.exit_status == 0))
+}
+
+/// makes sure that chmod/chown were applied, i.e. weren't rejected silently
+pub async fn verify_stat(
+    client: &impl Executor,
+    path: &str,
+    mode: Option<&str>,
+    owner: Option<&str>,
+) -> anyhow::Result<()> {
+    stat(client, path).await?.verify(path, mode, owner)
+}
+
+/// read remote file, returns None if the file doesn't exist
+pub async fn read_file(client: &impl Executor, path: &str) -> anyhow::Result<Option<String>> {
+    let cmd = format!("sudo cat {} 2>/dev/null", quote_path(path));
+    let out = silent(client, &cmd).await?;
+    if out.exit_status == 0 {
+        Ok(Some(out.output))
+    } else {
+        Ok(None)
+    }
+}
+
+/// write remote file with sudo, contents are uploaded into a temporary file
+/// in the same folder, which is then renamed into place,
+/// so the file is never observed partially written
+pub async fn write_file(client: &impl Executor, path: &str, contents: &str) -> anyhow::Result<()> {
+    atomic_write(client, path, contents, true).await
+}
+
+/// same as write_file, but without sudo, so new files are owned by the SSH user
+pub async fn write_user_file(
+    client: &impl Executor,
+    path: &str,
+    contents: &str,
+) -> anyhow::Result<()> {
+    atomic_write(client, path, contents, false).await
+}
+
+/// write remote file if its contents differ, returns true if the file was changed
+pub async fn ensure_file(
+    client: &impl Executor,
+    path: &str,
+    contents: &str,
+) -> anyhow::Result<bool> {
+    if read_file(client, path).await?.as_deref() == Some(contents) {
+        debug!("{} is up to date", path);
+        return Ok(false);
+    }
+    write_file(client, path, contents).await?;
+    Ok(true)
+}
+
+/// `state` option of the modules, returns true for present
+pub fn parse_state(state: Option<&str>) -> anyhow::Result<bool> {
+    match state.unwrap_or("present") {
+        "present" => Ok(true),
+        "absent" => Ok(false),
+        other => bail!("invalid state {}, expected present or absent", other),
+    }
+}
+
+const PENDING_DIR: &str = "/var/lib/remote-playbook/pending";
+
+fn pending_marker(name: &str) -> String {
+    shell_quote(&format!("{}/{}", PENDING_DIR, name))
+}
+
+/// returns true if the follow-up of a file, i.e. update-grub, should run:
+/// the file was just changed, or the follow-up failed after an earlier change.
+/// The marker is kept on the host until clear_pending, as the file alone
+/// is already up to date on the next run
+pub async fn mark_pending(
+    client: &impl Executor,
+    name: &str,
+    changed: bool,
+) -> anyhow::Result<bool> {
+    let marker = pending_marker(name);
+    if changed {
+        let cmd = format!("sudo mkdir -p {} && sudo touch {}", PENDING_DIR, marker);
+        run(client, &cmd).await?;
+        return Ok(true);
+    }
+    let pending = silent(client, &format!("test -e {}", marker))
+        .await?
+        .exit_status
+        == 0;
+    if pending {
+        info!("{} is pending since the previous run", name);
+    }
+    Ok(pending)
+}
+
+/// the follow-up succeeded
+pub async fn clear_pending(client: &impl Executor, name: &str) -> anyhow::Result<()> {
+    run(client, &format!("sudo rm -f {}", pending_marker(name))).await?;
+    Ok(())
+}
+
+/// parses available bytes from `df -P -B1 <path>` output
+pub fn parse_df_avail(output: &str) -> Option<u64> {
+    let line = output.lines().nth(1)?;
+    line.split_whitespace().nth(3)?.parse().ok()
+}
+
+/// fails with a clear message if available bytes are below the minimum
+pub fn check_free_space(path: &str, avail: u64, min_bytes: u64) -> anyhow::Result<()> {
+    if avail < min_bytes {
+        bail!(
+            "not enough free space on {}: {} MB available, {} MB required",
+            path,
+            avail / 1_000_000,
+            min_bytes / 1_000_000
+        );
+    }
+    Ok(())
+}
+
+/// checks free space on the filesystem of the path before large operations
+pub async fn require_free_space(
+    client: &impl Executor,
+    path: &str,
+    min_bytes: u64,
+) -> anyhow::Result<()> {
+    let out = run(client, &format!("df -P -B1 {} 2>&1", shell_quote(path))).await?;
+    let avail = parse_df_avail(&out.output)
+        .with_context(|| format!("unexpected df output: {}", out.output.trim()))?;
+    check_free_space(path, avail, min_bytes)
+}
+
+/// base64 characters per command, well below the limit of a single argument
+/// (MAX_ARG_STRLEN, 128 KiB), as the whole command is one argument of the shell.
+/// It is a multiple of 4, so every chunk is decoded on its own
+const UPLOAD_CHUNK: usize = 64 * 1024;
+
+/// commands appending the base64-encoded contents to the file, chunk by chunk
+fn append_cmds(tmp: &str, encoded: &str, sudo: bool) -> Vec<String> {
+    let sudo = if sudo { "sudo " } else { "" };
+    encoded
+        .as_bytes()
+        .chunks(UPLOAD_CHUNK)
+        .map(|chunk| {
+            format!(
+                "echo {} | base64 -d - | {}tee -a {} > /dev/null",
+                String::from_utf8_lossy(chunk),
+                sudo,
+                shell_quote(tmp)
+            )
+        })
+        .collect()
+}
+
+/// uploads the contents into a new file created by the `mktemp` command
+/// and returns its path, the file is removed if the upload fails.
+/// The client has no stdin, so the contents go base64-encoded in the commands
+pub async fn upload_temp(
+    client: &impl Executor,
+    mktemp: &str,
+    contents: &str,
+    sudo: bool,
+) -> anyhow::Result<String> {
+    let tmp = run(client, mktemp).await?.output.trim().to_string();
+    if tmp.is_empty() {
+        bail!("{} returned no path", mktemp);
+    }
+    let encoded = general_purpose::STANDARD.encode(contents.as_bytes());
+    for cmd in append_cmds(&tmp, &encoded, sudo) {
+        if let Err(e) = run(client, &cmd).await {
+            remove_temp(client, &tmp, sudo).await.ok();
+            return Err(e);
+        }
+    }
+    Ok(tmp)
+}
+
+pub async fn remove_temp(client: &impl Executor, tmp: &str, sudo: bool) -> anyhow::Result<()> {
+    let sudo = if sudo { "sudo " } else { "" };
+    run(client, &format!("{}rm -f {}", sudo, shell_quote(tmp))).await?;
+    Ok(())
+}
+
+/// temporary file in the folder of the path, so it can be renamed into place
+fn mktemp_cmd(path: &str, sudo: bool) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((dir, name)) => (dir, name),
+        None => (".", path),
+    };
+    format!(
+        "{}mktemp -p {} {}",
+        if sudo { "sudo " } else { "" },
+        quote_path(dir),
+        shell_quote(&format!(".{}.XXXXXX", name))
+    )
+}
+
+/// keeps mode and owner of the existing file,
+/// new files get the mode allowed by the umask of the session
+fn replace_cmd(path: &str, tmp: &str, sudo: bool) -> String {
+    format!(
+        "if [ -e {0} ]; then {1}chmod --reference={0} {2} && {1}chown --reference={0} {2}; \
+         else {1}chmod \"$(printf '%04o' $((0666 & ~$(umask))))\" {2}; fi && {1}mv -f {2} {0}",
+        quote_path(path),
+        if sudo { "sudo " } else { "" },
+        shell_quote(tmp)
+    )
+}
+
+async fn atomic_write(
+    client: &impl Executor,
+    path: &str,
+    contents: &str,
+    sudo: bool,
+) -> anyhow::Result<()> {
+    let tmp = upload_temp(client, &mktemp_cmd(path, sudo), contents, sudo).await?;
+    if let Err(e) = run(client, &replace_cmd(path, &tmp, sudo)).await {
+        remove_temp(client, &tmp, sudo).await.ok();
+        return Err(e);
+    }
+    Ok(())
+}
+
 /// run and fail on any exit_status that is not 0
-#[instrument(skip(client), level = "debug")]
-pub async fn run(client: &Client, cmd: &str) -> anyhow::Result<CommandExecutedResult> {
-    let exec_result: CommandExecutedResult = client.execute(&cmd).await?;
+#[instrument(skip(client, cmd), fields(cmd = %redact(cmd)), level = "debug")]
+pub async fn run(client: &impl Executor, cmd: &str) -> anyhow::Result<CommandExecutedResult> {
+    let exec_result: CommandExecutedResult = client.execute(&prefixed(cmd)).await?;
     if exec_result.exit_status == 0 {
-        debug!("{} {:?}", cmd, exec_result);
+        debug!("{} {}", redact(cmd), redact(&format!("{:?}", exec_result)));
         Ok(exec_result)
     } else {
-        warn!("{} {:?}", cmd, exec_result);
-        Err(anyhow::Error::msg(exec_result.output))
+        warn!("{} {}", redact(cmd), redact(&format!("{:?}", exec_result)));
+        Err(anyhow::Error::msg(redact(&exec_result.output)))
     }
 }
 
 /// run and ingore the possible erro
-#[instrument(skip(client), level = "debug")]
-pub async fn silent(client: &Client, cmd: &str) -> anyhow::Result<CommandExecutedResult> {
-    let exec_result: CommandExecutedResult = client.execute(&cmd).await?;
-    debug!("{} {:?}", cmd, exec_result);
+#[instrument(skip(client, cmd), fields(cmd = %redact(cmd)), level = "debug")]
+pub async fn silent(client: &impl Executor, cmd: &str) -> anyhow::Result<CommandExecutedResult> {
+    let exec_result: CommandExecutedResult = client.execute(&prefixed(cmd)).await?;
+    debug!("{} {}", redact(cmd), redact(&format!("{:?}", exec_result)));
     Ok(exec_result)
 }
 
+/// run and deserialize JSON output of the command
+#[instrument(skip(client), level = "debug")]
+pub async fn run_json<T: DeserializeOwned>(client: &impl Executor, cmd: &str) -> anyhow::Result<T> {
+    let exec_result = run(client, cmd).await?;
+    parse_json(cmd, &exec_result.output)
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        // JSON strings are valid YAML scalars
+        toml::Value::String(s) => Some(serde_json::to_string(s).unwrap_or_default()),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) if f.is_nan() => Some(".nan".to_string()),
+        toml::Value::Float(f) if f.is_infinite() && *f > 0.0 => Some(".inf".to_string()),
+        toml::Value::Float(f) if f.is_infinite() => Some("-.inf".to_string()),
+        // debug format keeps the fraction of whole numbers, 1.0 is not read as an integer
+        toml::Value::Float(f) => Some(format!("{:?}", f)),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        toml::Value::Datetime(d) => Some(format!("\"{}\"", d)),
+        toml::Value::Array(a) if a.is_empty() => Some("[]".to_string()),
+        toml::Value::Table(t) if t.is_empty() => Some("{}".to_string()),
+        _ => None,
+    }
+}
+
+fn write_yaml(out: &mut String, value: &toml::Value, indent: usize) {
+    let pad = " ".repeat(indent);
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                // quoted keys are never read as booleans, numbers or nulls, i.e. `on` or `1`
+                let key = serde_json::to_string(key).unwrap_or_default();
+                match scalar(value) {
+                    Some(s) => out.push_str(&format!("{}{}: {}\n", pad, key, s)),
+                    None => {
+                        out.push_str(&format!("{}{}:\n", pad, key));
+                        write_yaml(out, value, indent + 2);
+                    }
+                }
+            }
+        }
+        toml::Value::Array(items) => {
+            for item in items {
+                match scalar(item) {
+                    Some(s) => out.push_str(&format!("{}- {}\n", pad, s)),
+                    None => {
+                        // nested block starts on the line of the dash
+                        let mut nested = String::new();
+                        write_yaml(&mut nested, item, indent + 2);
+                        let nested = nested.get(indent + 2..).unwrap_or_default();
+                        out.push_str(&format!("{}- {}", pad, nested));
+                    }
+                }
+            }
+        }
+        other => out.push_str(&format!("{}{}\n", pad, scalar(other).unwrap_or_default())),
+    }
+}
+
+/// block-style YAML of the value, keys are sorted
+pub fn toml_to_yaml(value: &toml::Value) -> String {
+    let mut out = String::new();
+    write_yaml(&mut out, value, 0);
+    out
+}
+
+// nulls have no TOML counterpart, they are left out like None fields
+fn json_to_toml(value: serde_json::Value) -> Option<toml::Value> {
+    use serde_json::Value;
+    Some(match value {
+        Value::Null => return None,
+        Value::Bool(b) => toml::Value::Boolean(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => toml::Value::Integer(i),
+            None => toml::Value::Float(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => toml::Value::String(s),
+        Value::Array(items) => {
+            toml::Value::Array(items.into_iter().filter_map(json_to_toml).collect())
+        }
+        Value::Object(map) => toml::Value::Table(
+            map.into_iter()
+                .filter_map(|(k, v)| json_to_toml(v).map(|v| (k, v)))
+                .collect(),
+        ),
+    })
+}
+
+/// YAML through the Serialize impl, keys are sorted for stable diffs
+pub fn serialize_yaml<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    let value = json_to_toml(serde_json::to_value(value)?)
+        .unwrap_or(toml::Value::Table(Default::default()));
+    Ok(toml_to_yaml(&value))
+}
+
+const JSON_SNIPPET_LEN: usize = 200;
+
+pub fn parse_json<T: DeserializeOwned>(cmd: &str, output: &str) -> anyhow::Result<T> {
+    serde_json::from_str::<T>(output).map_err(|e| {
+        let snippet: String = output.trim().chars().take(JSON_SNIPPET_LEN).collect();
+        let ellipsis = if output.trim().chars().count() > JSON_SNIPPET_LEN {
+            "..."
+        } else {
+            ""
+        };
+        anyhow::anyhow!(
+            "invalid JSON output of `{}`: {}, output: {}{}",
+            cmd,
+            e,
+            snippet,
+            ellipsis
+        )
+    })
+}
+
+/// change the installation would make, found by the check
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Preview {
+    /// i.e. install, upgrade or remove
+    pub action: String,
+    /// i.e. the packages
+    pub items: Vec<String>,
+}
+
+impl std::fmt::Display for Preview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "would {}: {}", self.action, self.items.join(" "))
+    }
+}
+
 #[derive(Serialize)]
 pub enum Status {
     Installed {
@@ -93,6 +729,8 @@ pub enum Status {
         success: Vec<String>,
         #[serde(skip_serializing_if = "Vec::is_empty")]
         fail: Vec<String>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        preview: Vec<Preview>,
     },
 }
 impl std::fmt::Debug for Status {
@@ -100,19 +738,27 @@ impl std::fmt::Debug for Status {
         match self {
             Status::Installed { success } => {
                 let mut out = f.debug_struct("Installed");
-                if success.len() > 0 {
+                if !success.is_empty() {
                     out.field("success", success);
                 }
                 out.finish()
             }
-            Status::NotInstalled { success, fail } => {
+            Status::NotInstalled {
+                success,
+                fail,
+                preview,
+            } => {
                 let mut out = f.debug_struct("NotInstalled");
-                if success.len() > 0 {
+                if !success.is_empty() {
                     out.field("success", success);
                 }
-                if fail.len() > 0 {
+                if !fail.is_empty() {
                     out.field("fail", fail);
                 }
+                if !preview.is_empty() {
+                    let preview: Vec<String> = preview.iter().map(|x| x.to_string()).collect();
+                    out.field("preview", &preview);
+                }
                 out.finish()
             }
         }
@@ -127,19 +773,461 @@ impl Status {
             Status::NotInstalled {
                 success,
                 fail: failure,
+                preview: vec![],
             }
         }
     }
 
-    pub fn print(&self, alias: &str) {
-        let out = format!("{:?}", self);
-        match &self {
-            Status::Installed { .. } => {
-                println!("+ {}: {}", alias.green(), format!("{}", out).green());
-            }
-            Status::NotInstalled { .. } => {
-                println!("+ {}: {}", alias.red(), format!("{}", out).red());
-            }
+    /// what the installation would change, there is nothing to change once installed
+    pub fn with_preview(mut self, changes: Vec<Preview>) -> Self {
+        if let Status::NotInstalled { preview, .. } = &mut self {
+            preview.extend(changes);
+        }
+        self
+    }
+
+    pub fn preview(&self) -> &[Preview] {
+        match self {
+            Status::Installed { .. } => &[],
+            Status::NotInstalled { preview, .. } => preview,
+        }
+    }
+
+    /// outcome of the check
+    pub fn outcome(&self) -> Outcome {
+        match self {
+            Status::Installed { .. } => Outcome::Ok,
+            Status::NotInstalled { .. } => Outcome::Failed,
+        }
+    }
+
+    pub fn print(&self, alias: &str, outcome: Outcome) {
+        println!("{}", outcome.line(alias, &format!("{:?}", self)));
+    }
+}
+
+/// result of the module for the output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Changed,
+    Ok,
+    Skipped,
+    Failed,
+}
+
+impl Outcome {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Outcome::Changed => "CHANGED",
+            Outcome::Ok => "OK",
+            Outcome::Skipped => "SKIPPED",
+            Outcome::Failed => "FAILURE",
+        }
+    }
+
+    /// green for changed, cyan for ok, yellow for skipped, red for failed
+    pub fn paint(&self, text: &str) -> String {
+        match self {
+            Outcome::Changed => text.green().to_string(),
+            Outcome::Ok => text.cyan().to_string(),
+            Outcome::Skipped => text.yellow().to_string(),
+            Outcome::Failed => text.red().to_string(),
+        }
+    }
+
+    /// formatted output line of the module
+    pub fn line(&self, alias: &str, detail: &str) -> String {
+        let sign = if *self == Outcome::Failed { "-" } else { "+" };
+        format!("{} {}: {}", sign, self.paint(alias), self.paint(detail))
+    }
+}
+
+/// host answering the commands from a script, by a substring of the command;
+/// commands out of the script succeed without output, mktemp prints a path
+#[cfg(test)]
+pub struct ScriptedHost<'a> {
+    script: Vec<(&'a str, u32, &'a str)>,
+    executed: Mutex<Vec<String>>,
+}
+
+#[cfg(test)]
+impl<'a> ScriptedHost<'a> {
+    pub fn new(script: Vec<(&'a str, u32, &'a str)>) -> Self {
+        Self {
+            script,
+            executed: Mutex::new(vec![]),
+        }
+    }
+
+    /// commands in the order they were executed
+    pub fn executed(&self) -> Vec<String> {
+        self.executed.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Executor for ScriptedHost<'_> {
+    async fn execute(&self, cmd: &str) -> Result<CommandExecutedResult, async_ssh2_tokio::Error> {
+        self.executed.lock().unwrap().push(cmd.to_string());
+        let (exit_status, output) = self
+            .script
+            .iter()
+            .find(|(pattern, _, _)| cmd.contains(pattern))
+            .map_or((0, ""), |(_, exit_status, output)| (*exit_status, *output));
+        let output = match output {
+            "" if cmd.contains("mktemp") => "/tmp/tmp.scripted\n",
+            output => output,
+        };
+        Ok(CommandExecutedResult {
+            output: output.to_string(),
+            exit_status,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Sample {
+        name: String,
+        size: u64,
+    }
+
+    #[test]
+    fn it_parses_json() {
+        let sample: Sample = parse_json("cmd", r#"{"name": "sda", "size": 10}"#).unwrap();
+        assert_eq!(sample.name, "sda");
+        assert_eq!(sample.size, 10);
+    }
+
+    #[tokio::test]
+    async fn it_writes_through_temp_file() {
+        let host = ScriptedHost::new(vec![]);
+        write_file(&host, "/etc/default/grub", "GRUB_TIMEOUT=1\n")
+            .await
+            .unwrap();
+        let executed = host.executed();
+        assert_eq!(executed.len(), 3);
+        assert_eq!(executed[0], "sudo mktemp -p '/etc/default' '.grub.XXXXXX'");
+        assert!(executed[1].ends_with("| sudo tee -a '/tmp/tmp.scripted' > /dev/null"));
+        assert!(executed[2].contains("chmod --reference='/etc/default/grub' '/tmp/tmp.scripted'"));
+        assert!(executed[2].ends_with("sudo mv -f '/tmp/tmp.scripted' '/etc/default/grub'"));
+        // the destination is only touched by the rename
+        assert!(!executed
+            .iter()
+            .any(|cmd| cmd.contains("tee -a '/etc/default/grub'")));
+
+        // the temporary file is removed if it can't be renamed into place
+        let host = ScriptedHost::new(vec![("mv -f", 1, "mv: cannot move")]);
+        assert!(write_file(&host, "/etc/default/grub", "").await.is_err());
+        assert_eq!(
+            host.executed().last().unwrap(),
+            "sudo rm -f '/tmp/tmp.scripted'"
+        );
+
+        assert_eq!(
+            mktemp_cmd("/motd", true),
+            "sudo mktemp -p '/' '.motd.XXXXXX'"
+        );
+        assert_eq!(
+            mktemp_cmd("~/.ssh/id_rsa", false),
+            "mktemp -p ~/'.ssh' '.id_rsa.XXXXXX'"
+        );
+        let cmd = replace_cmd("~/.ssh/id_rsa", "/home/u/.ssh/.id_rsa.x", false);
+        assert!(cmd.ends_with("mv -f '/home/u/.ssh/.id_rsa.x' ~/'.ssh/id_rsa'"));
+        assert!(!cmd.contains("sudo"));
+    }
+
+    /// runs the commands locally with the umask of the session
+    struct LocalShell {
+        umask: &'static str,
+    }
+
+    impl Executor for LocalShell {
+        async fn execute(
+            &self,
+            cmd: &str,
+        ) -> Result<CommandExecutedResult, async_ssh2_tokio::Error> {
+            let out = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("umask {}; {}", self.umask, cmd))
+                .output()
+                .unwrap();
+            Ok(CommandExecutedResult {
+                output: String::from_utf8_lossy(&out.stdout).to_string(),
+                exit_status: out.status.code().unwrap_or(1) as u32,
+            })
+        }
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rp-{} {}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn it_writes_new_files_with_umask() {
+        let dir = temp_dir("write");
+        let path = dir.join("it's new").to_str().unwrap().to_string();
+        let shell = LocalShell { umask: "027" };
+        write_user_file(&shell, &path, "hello\n").await.unwrap();
+        let meta = std::fs::metadata(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\n");
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+        // the mode of an existing file is kept
+        sh(&format!("chmod 600 {}", shell_quote(&path)));
+        let shell = LocalShell { umask: "022" };
+        write_user_file(&shell, &path, "hello\n").await.unwrap();
+        let meta = std::fs::metadata(&path).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        // no temporary files are left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_writes_files_over_the_argument_limit() {
+        let dir = temp_dir("large");
+        let path = dir.join("large").to_str().unwrap().to_string();
+        let contents: String = (0..40_000).map(|i| format!("line {}\n", i)).collect();
+        assert!(contents.len() > 3 * UPLOAD_CHUNK);
+        let encoded = general_purpose::STANDARD.encode(contents.as_bytes());
+        assert!(append_cmds(&path, &encoded, false)
+            .iter()
+            .all(|cmd| cmd.len() < UPLOAD_CHUNK + 100));
+        write_user_file(&LocalShell { umask: "022" }, &path, &contents)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_writes_yaml_that_reads_back_as_written() {
+        let value: toml::Value = toml::from_str(
+            r#"
+on = true
+1 = "one"
+"a: b" = 1.0
+floats = [nan, inf, -inf, 0.5]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            toml_to_yaml(&value),
+            r#""1": "one"
+"a: b": 1.0
+"floats":
+  - .nan
+  - .inf
+  - -.inf
+  - 0.5
+"on": true
+"#
+        );
+    }
+
+    #[test]
+    fn it_parses_state() {
+        assert!(parse_state(None).unwrap());
+        assert!(parse_state(Some("present")).unwrap());
+        assert!(!parse_state(Some("absent")).unwrap());
+        assert_eq!(
+            parse_state(Some("latest")).unwrap_err().to_string(),
+            "invalid state latest, expected present or absent"
+        );
+    }
+
+    #[test]
+    fn it_quotes_paths() {
+        assert_eq!(quote_path("/etc/a b"), "'/etc/a b'");
+        assert_eq!(quote_path("~/.ssh/it's"), r#"~/'.ssh/it'\''s'"#);
+        assert_eq!(quote_path("~"), "~");
+        assert_eq!(quote_path("~user/x"), "'~user/x'");
+    }
+
+    #[test]
+    fn it_reports_invalid_json() {
+        let err = parse_json::<Sample>("lsblk -J", "lsblk: command not found")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`lsblk -J`"));
+        assert!(err.contains("output: lsblk: command not found"));
+
+        let long = "x".repeat(500);
+        let err = parse_json::<Sample>("cmd", &long).unwrap_err().to_string();
+        assert!(err.ends_with(&format!("{}...", "x".repeat(200))));
+    }
+
+    #[test]
+    fn it_verifies_file_stat() {
+        let stat = FileStat::parse("644 root root\n").unwrap();
+        assert!(stat
+            .verify("/etc/x", Some("0644"), Some("root:root"))
+            .is_ok());
+        assert!(stat.verify("/etc/x", Some("a+r"), Some("root")).is_ok());
+        assert!(stat.verify("/etc/x", None, None).is_ok());
+        assert_eq!(
+            stat.verify("/etc/x", Some("0600"), None)
+                .unwrap_err()
+                .to_string(),
+            "/etc/x: mode is 644, expected 0600"
+        );
+        // chown to another user was rejected
+        assert_eq!(
+            stat.verify("/etc/x", None, Some("www-data:www-data"))
+                .unwrap_err()
+                .to_string(),
+            "/etc/x: owner is root:root, expected www-data:www-data"
+        );
+        assert!(FileStat::parse("stat: cannot stat").is_none());
+    }
+
+    #[test]
+    fn it_checks_free_space() {
+        let output = "Filesystem        1-blocks       Used   Available Capacity Mounted on
+/dev/sda1      41555521536 9876543210 29543567360      26% /
+";
+        assert_eq!(parse_df_avail(output), Some(29543567360));
+        assert_eq!(
+            parse_df_avail("df: /nope: No such file or directory\n"),
+            None
+        );
+        assert!(check_free_space("/", 29543567360, 500_000_000).is_ok());
+        let err = check_free_space("/", 300_000_000, 500_000_000).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "not enough free space on /: 300 MB available, 500 MB required"
+        );
+    }
+
+    #[test]
+    fn it_finds_any_candidate() {
+        let candidates = ["curl", "wget"];
+        assert_eq!(
+            parse_which_any(&candidates, "wget /usr/bin/wget\ncurl /usr/bin/curl\n"),
+            Some(("curl".to_string(), "/usr/bin/curl".to_string()))
+        );
+        assert_eq!(
+            parse_which_any(&candidates, "wget /usr/bin/wget\n"),
+            Some(("wget".to_string(), "/usr/bin/wget".to_string()))
+        );
+        assert_eq!(parse_which_any(&candidates, ""), None);
+        assert_eq!(
+            which_any_cmd(&candidates),
+            r#"for c in 'curl' 'wget'; do p=$(command -v "$c") && echo "$c $p"; done"#
+        );
+    }
+
+    #[test]
+    fn it_resolves_python() {
+        let output = "python3 /usr/bin/python3\npython /usr/bin/python\n";
+        assert_eq!(resolve_python(None, output).unwrap(), "/usr/bin/python3");
+        assert_eq!(
+            resolve_python(None, "python /usr/bin/python\n").unwrap(),
+            "/usr/bin/python"
+        );
+        assert_eq!(
+            resolve_python(Some("python3.11"), "python3.11 /usr/local/bin/python3.11\n").unwrap(),
+            "/usr/local/bin/python3.11"
+        );
+        let err = resolve_python(Some("python3.11"), "").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "python interpreter python3.11 is not found, check python_interpreter"
+        );
+        assert!(resolve_python(None, "").is_err());
+    }
+
+    #[test]
+    fn it_applies_command_prefix() {
+        assert_eq!(with_prefix(None, "ls -1 /data"), "ls -1 /data");
+        let cmd = with_prefix(Some("nice -n 10"), "echo 'a b' && exit 3");
+        assert_eq!(cmd, r#"nice -n 10 bash -c 'echo '\''a b'\'' && exit 3'"#);
+        let out = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(&cmd)
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "a b\n");
+        assert_eq!(out.status.code(), Some(3));
+    }
+
+    fn sh(cmd: &str) -> String {
+        let out = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    }
+
+    #[test]
+    fn it_applies_umask() {
+        assert!(validate_umask("022").is_ok());
+        assert!(validate_umask("0077").is_ok());
+        assert!(validate_umask("22").is_err());
+        assert!(validate_umask("0o22").is_err());
+        assert!(validate_umask("089").is_err());
+        assert_eq!(with_umask(None, "touch a"), "touch a");
+        let cmd = with_umask(Some("077"), "umask && exit 3");
+        assert_eq!(cmd, "umask 077; umask && exit 3");
+        let out = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(with_prefix(Some("nice -n 10"), &cmd))
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "0077\n");
+        assert_eq!(out.status.code(), Some(3));
+    }
+
+    #[test]
+    fn it_quotes_for_shell() {
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r#"'it'\''s'"#);
+        let samples = [
+            "",
+            "a b",
+            "it's",
+            "'",
+            "line\nbreak",
+            "$HOME ${PATH}",
+            "`id` $(id)",
+            "\"double\" \\ back",
+            "; rm -rf / #",
+        ];
+        for sample in samples {
+            assert_eq!(sh(&format!("printf %s {}", shell_quote(sample))), sample);
         }
+        assert_eq!(
+            shell_join(&["printf", "%s|", "a b", "it's", ""]),
+            r#"'printf' '%s|' 'a b' 'it'\''s' ''"#
+        );
+        assert_eq!(
+            sh(&shell_join(&["printf", "%s|", "a b", "$x", ""])),
+            "a b|$x||"
+        );
+    }
+
+    #[test]
+    fn it_colors_outcomes() {
+        assert_eq!(Outcome::Changed.paint("x"), "\u{1b}[32mx\u{1b}[39m");
+        assert_eq!(Outcome::Ok.paint("x"), "\u{1b}[36mx\u{1b}[39m");
+        assert_eq!(Outcome::Skipped.paint("x"), "\u{1b}[33mx\u{1b}[39m");
+        assert_eq!(Outcome::Failed.paint("x"), "\u{1b}[31mx\u{1b}[39m");
+        assert_eq!(
+            Outcome::Ok.line("apt", "OK"),
+            "+ \u{1b}[36mapt\u{1b}[39m: \u{1b}[36mOK\u{1b}[39m"
+        );
+        assert!(Outcome::Failed.line("apt", "boom").starts_with("- "));
+        assert_eq!(Outcome::Skipped.label(), "SKIPPED");
+        let status = Status::new(vec![], vec!["curl missing".to_string()]);
+        assert_eq!(status.outcome(), Outcome::Failed);
+        assert_eq!(Status::new(vec![], vec![]).outcome(), Outcome::Ok);
     }
 }
@@ -0,0 +1,140 @@
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// records closed spans as complete events of the Chrome trace format,
+/// the file can be loaded into about:tracing or ui.perfetto.dev
+pub struct ProfileLayer {
+    started: Instant,
+    events: Arc<Mutex<Vec<Value>>>,
+}
+
+/// writes the trace file when dropped
+pub struct ProfileGuard {
+    path: PathBuf,
+    events: Arc<Mutex<Vec<Value>>>,
+}
+
+/// start time and fields of an open span
+struct Timing {
+    start: Instant,
+    args: serde_json::Map<String, Value>,
+}
+
+struct FieldVisitor<'a>(&'a mut serde_json::Map<String, Value>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+}
+
+pub fn profile(path: impl Into<PathBuf>) -> (ProfileLayer, ProfileGuard) {
+    let events = Arc::new(Mutex::new(vec![]));
+    let layer = ProfileLayer {
+        started: Instant::now(),
+        events: events.clone(),
+    };
+    let guard = ProfileGuard {
+        path: path.into(),
+        events,
+    };
+    (layer, guard)
+}
+
+impl<S> Layer<S> for ProfileLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut args = serde_json::Map::new();
+        attrs.record(&mut FieldVisitor(&mut args));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Timing {
+                start: Instant::now(),
+                args,
+            });
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let extensions = span.extensions();
+        let Some(timing) = extensions.get::<Timing>() else {
+            return;
+        };
+        let event = json!({
+            "name": span.name(),
+            "cat": span.metadata().target(),
+            "ph": "X",
+            "ts": timing.start.duration_since(self.started).as_micros() as u64,
+            "dur": timing.start.elapsed().as_micros() as u64,
+            "pid": 1,
+            "tid": 1,
+            "args": timing.args,
+        });
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+impl ProfileGuard {
+    pub fn write(&self) -> anyhow::Result<()> {
+        let events = self.events.lock().unwrap();
+        let trace = json!({ "traceEvents": *events });
+        std::fs::write(&self.path, serde_json::to_string(&trace)?)?;
+        Ok(())
+    }
+}
+
+impl Drop for ProfileGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.write() {
+            eprintln!("failed to write profile {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::info_span;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn it_writes_chrome_trace() {
+        let path = std::env::temp_dir().join(format!("profile-{}.json", std::process::id()));
+        let (layer, guard) = profile(&path);
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            info_span!("connect", host = "10.0.0.5").in_scope(|| {});
+            info_span!("gather_facts").in_scope(|| {});
+            info_span!("install", name = "disk").in_scope(|| {
+                info_span!("run", cmd = "mkdir -p /data").in_scope(|| {});
+            });
+        });
+        drop(guard);
+
+        let trace: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let names: Vec<&str> = events.iter().map(|e| e["name"].as_str().unwrap()).collect();
+        // spans are recorded when they are closed
+        assert_eq!(names, vec!["connect", "gather_facts", "run", "install"]);
+        assert!(events.iter().all(|e| e["ph"] == "X"));
+        assert_eq!(events[0]["args"]["host"], "10.0.0.5");
+        assert_eq!(events[2]["args"]["cmd"], "mkdir -p /data");
+    }
+}
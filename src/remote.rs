@@ -1,4 +1,49 @@
+/// declares the options struct of a module along with its `PARAMS`,
+/// which describe every field by its doc comment for `module-help`,
+/// `#[param(default = "...")]` documents the value used when the field is not set
+macro_rules! module_options {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[doc = $doc:literal])*
+                $(#[param(default = $default:literal)])?
+                $(#[serde($($serde:tt)*)])*
+                pub $field:ident: $ty:ty,
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        pub struct $name {
+            $(
+                $(#[doc = $doc])*
+                $(#[serde($($serde)*)])*
+                pub $field: $ty,
+            )*
+        }
+
+        impl $name {
+            pub const PARAMS: &'static [$crate::remote::ParamInfo] = &[$(
+                $crate::remote::ParamInfo {
+                    name: stringify!($field),
+                    kind: <$ty as $crate::remote::ParamKind>::KIND,
+                    required: <$ty as $crate::remote::ParamKind>::REQUIRED,
+                    default: module_options!(@default $($default)?),
+                    description: concat!($($doc, "\n"),*),
+                },
+            )*];
+        }
+    };
+    (@default) => {
+        None
+    };
+    (@default $default:literal) => {
+        Some($default)
+    };
+}
+
 pub mod alias; // this module is treated as aseparate section
+pub mod config_kv; // helper for the modules editing KEY=value files
 pub mod export; // this module is treated as aseparate section
 
 pub mod apt;
@@ -31,14 +76,212 @@ pub use node_exporter::NodeExporterOptions;
 pub mod docker_stats;
 pub use docker_stats::DockerStatsOptions;
 
+pub mod alternatives;
+pub use alternatives::AlternativesOptions;
+
+pub mod etc_default;
+pub use etc_default::EtcDefaultOptions;
+
+pub mod backports;
+pub use backports::BackportsOptions;
+
+pub mod journald;
+pub use journald::JournaldOptions;
+
+pub mod dns;
+pub use dns::DnsOptions;
+
+pub mod fetch;
+pub use fetch::FetchOptions;
+
+pub mod sudoers;
+pub use sudoers::SudoersOptions;
+
+pub mod dpkg_architecture;
+pub use dpkg_architecture::DpkgArchitectureOptions;
+
+pub mod netplan;
+pub use netplan::NetplanOptions;
+
+pub mod grub_cmdline;
+pub use grub_cmdline::GrubCmdlineOptions;
+
+pub mod tcp_wrappers;
+pub use tcp_wrappers::TcpWrappersOptions;
+
+pub mod logrotate;
+pub use logrotate::LogrotateOptions;
+
+pub mod resolved;
+pub use resolved::ResolvedOptions;
+
+pub mod cloud_init;
+pub use cloud_init::CloudInitOptions;
+
+pub mod dpkg_selections;
+pub use dpkg_selections::DpkgSelectionsOptions;
+
+pub mod periodic_job;
+pub use periodic_job::PeriodicJobOptions;
+
+pub mod apt_mirror;
+pub use apt_mirror::AptMirrorOptions;
+
+pub mod virtualenv;
+pub use virtualenv::VirtualenvOptions;
+
+pub mod filesystem;
+pub use filesystem::FilesystemOptions;
+
+pub mod debconf;
+pub use debconf::DebconfOptions;
+
+pub mod cron_d;
+pub use cron_d::CronDOptions;
+
 // use crate::prelude::*;
+use crate::report::StageReport;
 use async_ssh2_tokio::client::Client;
-use color_eyre::owo_colors::OwoColorize;
 use serde::Deserialize;
+use std::collections::BTreeMap as Map;
 use tracing::*;
 
+/// description of the playbook module, used for `list-modules` and `module-help`
+pub struct ModuleInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub params: &'static [ParamInfo],
+    /// runs remote commands with sudo, so the SSH user has to become root without a password
+    pub sudo: bool,
+}
+
+/// description of a single module parameter
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub kind: &'static str,
+    pub required: bool,
+    pub default: Option<&'static str>,
+    /// doc comment of the field, one line per doc line
+    pub description: &'static str,
+}
+
+/// how the type of an options field is shown in `module-help`
+pub trait ParamKind {
+    const KIND: &'static str;
+    const REQUIRED: bool = true;
+}
+
+impl<T: ParamKind> ParamKind for Option<T> {
+    const KIND: &'static str = T::KIND;
+    const REQUIRED: bool = false;
+}
+
+impl ParamKind for String {
+    const KIND: &'static str = "string";
+}
+
+impl ParamKind for bool {
+    const KIND: &'static str = "bool";
+}
+
+impl ParamKind for u64 {
+    const KIND: &'static str = "integer";
+}
+
+impl ParamKind for i32 {
+    const KIND: &'static str = "integer";
+}
+
+impl ParamKind for Vec<String> {
+    const KIND: &'static str = "list of strings";
+}
+
+impl ParamKind for Map<String, String> {
+    const KIND: &'static str = "table of strings";
+}
+
+impl ParamKind for Map<String, toml::Value> {
+    const KIND: &'static str = "table";
+}
+
+/// all modules that could be declared in a stage
+pub const MODULES: &[ModuleInfo] = &[
+    mount::MODULE,
+    mkdir::MODULE,
+    apt::MODULE,
+    keys::MODULE,
+    git::MODULE,
+    aws::MODULE,
+    docker::MODULE,
+    terraform::MODULE,
+    node_exporter::MODULE,
+    docker_stats::MODULE,
+    alternatives::MODULE,
+    etc_default::MODULE,
+    backports::MODULE,
+    journald::MODULE,
+    dns::MODULE,
+    fetch::MODULE,
+    sudoers::MODULE,
+    dpkg_architecture::MODULE,
+    netplan::MODULE,
+    grub_cmdline::MODULE,
+    tcp_wrappers::MODULE,
+    logrotate::MODULE,
+    resolved::MODULE,
+    cloud_init::MODULE,
+    dpkg_selections::MODULE,
+    periodic_job::MODULE,
+    apt_mirror::MODULE,
+    virtualenv::MODULE,
+    filesystem::MODULE,
+    debconf::MODULE,
+    cron_d::MODULE,
+];
+
+pub fn list_modules() -> String {
+    let width = MODULES.iter().map(|m| m.name.len()).max().unwrap_or(0);
+    MODULES
+        .iter()
+        .map(|m| format!("{:width$}  {}\n", m.name, m.description, width = width))
+        .collect()
+}
+
+pub fn module_help(name: &str) -> anyhow::Result<String> {
+    let Some(module) = MODULES
+        .iter()
+        .find(|m| m.name == name || m.name.replace('-', "_") == name)
+    else {
+        anyhow::bail!("unknown module {}", name);
+    };
+    let mut out = format!("{}: {}\n", module.name, module.description);
+    if module.params.is_empty() {
+        out.push_str("  no parameters\n");
+    }
+    for param in module.params {
+        let mut kind = param.kind.to_string();
+        if !param.required {
+            kind = format!("{}, optional", kind);
+        }
+        if let Some(default) = param.default {
+            kind = format!("{}, default {:?}", kind, default);
+        }
+        let description = param.description.split_whitespace().collect::<Vec<_>>();
+        out.push_str(&format!(
+            "  {} ({}) - {}\n",
+            param.name,
+            kind,
+            description.join(" ")
+        ));
+    }
+    Ok(out)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Stage {
+    /// reconnect after the stage, i.e. for new group membership to take effect
+    #[serde(alias = "reset-connection")]
+    pub reset_connection: Option<bool>,
     pub mount: Option<MountOptions>,
     pub mkdir: Option<MkdirOptions>,
     pub apt: Option<AptOptions>,
@@ -51,158 +294,440 @@ pub struct Stage {
     pub node_exporter: Option<NodeExporterOptions>,
     #[serde(alias = "docker-stats")]
     pub docker_stats: Option<DockerStatsOptions>,
+    pub alternatives: Option<AlternativesOptions>,
+    #[serde(alias = "etc-default")]
+    pub etc_default: Option<EtcDefaultOptions>,
+    pub backports: Option<BackportsOptions>,
+    pub journald: Option<JournaldOptions>,
+    pub dns: Option<DnsOptions>,
+    pub fetch: Option<FetchOptions>,
+    pub sudoers: Option<SudoersOptions>,
+    #[serde(alias = "dpkg-architecture")]
+    pub dpkg_architecture: Option<DpkgArchitectureOptions>,
+    pub netplan: Option<NetplanOptions>,
+    #[serde(alias = "grub-cmdline")]
+    pub grub_cmdline: Option<GrubCmdlineOptions>,
+    #[serde(alias = "tcp-wrappers")]
+    pub tcp_wrappers: Option<TcpWrappersOptions>,
+    pub logrotate: Option<LogrotateOptions>,
+    pub resolved: Option<ResolvedOptions>,
+    #[serde(alias = "cloud-init")]
+    pub cloud_init: Option<CloudInitOptions>,
+    #[serde(alias = "dpkg-selections")]
+    pub dpkg_selections: Option<DpkgSelectionsOptions>,
+    #[serde(alias = "periodic-job")]
+    pub periodic_job: Option<PeriodicJobOptions>,
+    #[serde(alias = "apt-mirror")]
+    pub apt_mirror: Option<AptMirrorOptions>,
+    pub virtualenv: Option<VirtualenvOptions>,
+    pub filesystem: Option<FilesystemOptions>,
+    pub debconf: Option<DebconfOptions>,
+    #[serde(alias = "cron-d")]
+    pub cron_d: Option<CronDOptions>,
 }
 
-#[instrument(skip(client))]
-pub async fn install(client: &Client, name: &str, stage: &Stage) -> anyhow::Result<()> {
-    println!("= {}", name.yellow());
+impl Stage {
+    pub fn resets_connection(&self) -> bool {
+        self.reset_connection.unwrap_or(false)
+    }
 
+    /// modules declared in the stage
+    pub fn modules(&self) -> Vec<&'static ModuleInfo> {
+        let declared: [(bool, &'static ModuleInfo); 31] = [
+            (self.mount.is_some(), &mount::MODULE),
+            (self.mkdir.is_some(), &mkdir::MODULE),
+            (self.apt.is_some(), &apt::MODULE),
+            (self.keys.is_some(), &keys::MODULE),
+            (self.git.is_some(), &git::MODULE),
+            (self.aws.is_some(), &aws::MODULE),
+            (self.docker.is_some(), &docker::MODULE),
+            (self.terraform.is_some(), &terraform::MODULE),
+            (self.node_exporter.is_some(), &node_exporter::MODULE),
+            (self.docker_stats.is_some(), &docker_stats::MODULE),
+            (self.alternatives.is_some(), &alternatives::MODULE),
+            (self.etc_default.is_some(), &etc_default::MODULE),
+            (self.backports.is_some(), &backports::MODULE),
+            (self.journald.is_some(), &journald::MODULE),
+            (self.dns.is_some(), &dns::MODULE),
+            (self.fetch.is_some(), &fetch::MODULE),
+            (self.sudoers.is_some(), &sudoers::MODULE),
+            (self.dpkg_architecture.is_some(), &dpkg_architecture::MODULE),
+            (self.netplan.is_some(), &netplan::MODULE),
+            (self.grub_cmdline.is_some(), &grub_cmdline::MODULE),
+            (self.tcp_wrappers.is_some(), &tcp_wrappers::MODULE),
+            (self.logrotate.is_some(), &logrotate::MODULE),
+            (self.resolved.is_some(), &resolved::MODULE),
+            (self.cloud_init.is_some(), &cloud_init::MODULE),
+            (self.dpkg_selections.is_some(), &dpkg_selections::MODULE),
+            (self.periodic_job.is_some(), &periodic_job::MODULE),
+            (self.apt_mirror.is_some(), &apt_mirror::MODULE),
+            (self.virtualenv.is_some(), &virtualenv::MODULE),
+            (self.filesystem.is_some(), &filesystem::MODULE),
+            (self.debconf.is_some(), &debconf::MODULE),
+            (self.cron_d.is_some(), &cron_d::MODULE),
+        ];
+        declared
+            .into_iter()
+            .filter(|(declared, _)| *declared)
+            .map(|(_, module)| module)
+            .collect()
+    }
+
+    /// whether any module of the stage runs commands with sudo
+    pub fn needs_sudo(&self) -> bool {
+        self.mkdir.as_ref().is_some_and(|opt| opt.sudo) || self.modules().iter().any(|m| m.sudo)
+    }
+}
+
+#[instrument(skip(client, stage))]
+pub async fn install(client: &Client, name: &str, stage: &Stage) -> anyhow::Result<StageReport> {
+    let mut report = StageReport::start(name);
+
+    if let Some(opt) = &stage.cloud_init {
+        let alias = "cloud-init";
+        report.installed(alias, cloud_init::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.filesystem {
+        let alias = "filesystem";
+        report.installed(alias, filesystem::on_install(client, opt).await);
+    }
     if let Some(opt) = &stage.mount {
         let alias = "mount";
-        match mount::on_install(client, opt).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, mount::on_install(client, opt).await);
     }
     if let Some(opt) = &stage.mkdir {
         let alias = "mkdir";
-        match mkdir::on_install(client, opt).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, mkdir::on_install(client, opt).await);
     }
     if let Some(opt) = &stage.keys {
         let alias = "keys";
-        match keys::on_install(client, opt).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, keys::on_install(client, opt).await);
     }
     if let Some(opt) = &stage.git {
         let alias = "git";
-        match git::on_install(client, opt).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, git::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.apt_mirror {
+        let alias = "apt-mirror";
+        report.installed(alias, apt_mirror::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.backports {
+        let alias = "backports";
+        report.installed(alias, backports::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.dpkg_architecture {
+        let alias = "dpkg-architecture";
+        report.installed(alias, dpkg_architecture::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.debconf {
+        let alias = "debconf";
+        report.installed(alias, debconf::on_install(client, opt).await);
     }
     if let Some(opt) = &stage.apt {
         let alias = "apt";
-        match apt::on_install(client, opt).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, apt::on_install(client, opt).await);
     }
     if let Some(opt) = &stage.docker {
         let alias = "docker";
-        match docker::on_install(client, opt).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, docker::on_install(client, opt).await);
     }
     if let Some(opt) = &stage.terraform {
         let alias = "terraform";
-        match terraform::on_install(client, opt).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, terraform::on_install(client, opt).await);
     }
     if let Some(opt) = &stage.aws {
         let alias = "aws";
-        match aws::on_install(client, opt).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, aws::on_install(client, opt).await);
     }
     if let Some(opt) = &stage.node_exporter {
         let alias = "node-exporter";
-        match node_exporter::on_install(client, opt).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, node_exporter::on_install(client, opt).await);
     }
     if let Some(opt) = &stage.docker_stats {
         let alias = "docker-stats";
-        match docker_stats::on_install(client, opt).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, docker_stats::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.alternatives {
+        let alias = "alternatives";
+        report.installed(alias, alternatives::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.etc_default {
+        let alias = "etc-default";
+        report.installed(alias, etc_default::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.journald {
+        let alias = "journald";
+        report.installed(alias, journald::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.dns {
+        let alias = "dns";
+        report.installed(alias, dns::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.fetch {
+        let alias = "fetch";
+        report.installed(alias, fetch::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.sudoers {
+        let alias = "sudoers";
+        report.installed(alias, sudoers::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.netplan {
+        let alias = "netplan";
+        report.installed(alias, netplan::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.grub_cmdline {
+        let alias = "grub-cmdline";
+        report.installed(alias, grub_cmdline::on_install(client, opt).await);
     }
-    Ok(())
+    if let Some(opt) = &stage.tcp_wrappers {
+        let alias = "tcp-wrappers";
+        report.installed(alias, tcp_wrappers::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.logrotate {
+        let alias = "logrotate";
+        report.installed(alias, logrotate::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.resolved {
+        let alias = "resolved";
+        report.installed(alias, resolved::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.dpkg_selections {
+        let alias = "dpkg-selections";
+        report.installed(alias, dpkg_selections::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.periodic_job {
+        let alias = "periodic-job";
+        report.installed(alias, periodic_job::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.virtualenv {
+        let alias = "virtualenv";
+        report.installed(alias, virtualenv::on_install(client, opt).await);
+    }
+    if let Some(opt) = &stage.cron_d {
+        let alias = "cron-d";
+        report.installed(alias, cron_d::on_install(client, opt).await);
+    }
+    Ok(report)
 }
 
-#[instrument(skip(client))]
-pub async fn check(client: &Client, name: &str, stage: &Stage) -> anyhow::Result<()> {
-    println!("= {}", name.yellow());
+#[instrument(skip(client, stage))]
+pub async fn check(client: &Client, name: &str, stage: &Stage) -> anyhow::Result<StageReport> {
+    let mut report = StageReport::start(name);
 
+    if let Some(opt) = &stage.cloud_init {
+        let alias = "cloud-init";
+        report.checked(alias, cloud_init::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.filesystem {
+        let alias = "filesystem";
+        report.checked(alias, filesystem::on_check(client, opt).await);
+    }
     if let Some(opt) = &stage.mount {
         let alias = "mount";
-        match mount::on_check(client, opt).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.checked(alias, mount::on_check(client, opt).await);
     }
     if let Some(opt) = &stage.mkdir {
         let alias = "mkdir";
-        match mkdir::on_check(client, opt).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.checked(alias, mkdir::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.apt_mirror {
+        let alias = "apt-mirror";
+        report.checked(alias, apt_mirror::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.backports {
+        let alias = "backports";
+        report.checked(alias, backports::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.dpkg_architecture {
+        let alias = "dpkg-architecture";
+        report.checked(alias, dpkg_architecture::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.debconf {
+        let alias = "debconf";
+        report.checked(alias, debconf::on_check(client, opt).await);
     }
     if let Some(opt) = &stage.apt {
         let alias = "apt";
-        match apt::on_check(client, opt).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.checked(alias, apt::on_check(client, opt).await);
     }
     if let Some(opt) = &stage.keys {
         let alias = "keys";
-        match keys::on_check(client, opt).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.checked(alias, keys::on_check(client, opt).await);
     }
     if let Some(opt) = &stage.git {
         let alias = "git";
-        match git::on_check(client, opt).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.checked(alias, git::on_check(client, opt).await);
     }
     if let Some(opt) = &stage.docker {
         let alias = "docker";
-        match docker::on_check(client, opt).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.checked(alias, docker::on_check(client, opt).await);
     }
     if let Some(opt) = &stage.terraform {
         let alias = "terraform";
-        match terraform::on_check(client, opt).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.checked(alias, terraform::on_check(client, opt).await);
     }
     if let Some(opt) = &stage.aws {
         let alias = "aws";
-        match aws::on_check(client, opt).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.checked(alias, aws::on_check(client, opt).await);
     }
     if let Some(opt) = &stage.node_exporter {
         let alias = "node-exporter";
-        match node_exporter::on_check(client, opt).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.checked(alias, node_exporter::on_check(client, opt).await);
     }
     if let Some(opt) = &stage.docker_stats {
         let alias = "docker-stats";
-        match docker_stats::on_check(client, opt).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
+        report.checked(alias, docker_stats::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.alternatives {
+        let alias = "alternatives";
+        report.checked(alias, alternatives::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.etc_default {
+        let alias = "etc-default";
+        report.checked(alias, etc_default::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.journald {
+        let alias = "journald";
+        report.checked(alias, journald::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.dns {
+        let alias = "dns";
+        report.checked(alias, dns::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.fetch {
+        let alias = "fetch";
+        report.checked(alias, fetch::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.sudoers {
+        let alias = "sudoers";
+        report.checked(alias, sudoers::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.netplan {
+        let alias = "netplan";
+        report.checked(alias, netplan::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.grub_cmdline {
+        let alias = "grub-cmdline";
+        report.checked(alias, grub_cmdline::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.tcp_wrappers {
+        let alias = "tcp-wrappers";
+        report.checked(alias, tcp_wrappers::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.logrotate {
+        let alias = "logrotate";
+        report.checked(alias, logrotate::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.resolved {
+        let alias = "resolved";
+        report.checked(alias, resolved::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.dpkg_selections {
+        let alias = "dpkg-selections";
+        report.checked(alias, dpkg_selections::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.periodic_job {
+        let alias = "periodic-job";
+        report.checked(alias, periodic_job::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.virtualenv {
+        let alias = "virtualenv";
+        report.checked(alias, virtualenv::on_check(client, opt).await);
+    }
+    if let Some(opt) = &stage.cron_d {
+        let alias = "cron-d";
+        report.checked(alias, cron_d::on_check(client, opt).await);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_reset_connection() {
+        let stage: Stage = toml::from_str(
+            "reset-connection = true\nmkdir = { sudo = true, folders = [\"/data\"] }",
+        )
+        .unwrap();
+        assert!(stage.resets_connection());
+        assert!(stage.mkdir.is_some());
+        let stage: Stage = toml::from_str("mkdir = { sudo = true, folders = [] }").unwrap();
+        assert!(!stage.resets_connection());
+    }
+
+    #[test]
+    fn it_needs_sudo_for_some_modules() {
+        let stage: Stage = toml::from_str("mkdir = { sudo = true, folders = [] }").unwrap();
+        assert!(stage.needs_sudo());
+        let stage: Stage = toml::from_str("mkdir = { sudo = false, folders = [] }").unwrap();
+        assert!(!stage.needs_sudo());
+        let stage: Stage = toml::from_str("virtualenv = { path = \"~/venv\" }").unwrap();
+        assert!(!stage.needs_sudo());
+        assert_eq!(stage.modules().len(), 1);
+        let stage: Stage =
+            toml::from_str("virtualenv = { path = \"~/venv\" }\napt = { install = [\"git\"] }")
+                .unwrap();
+        assert!(stage.needs_sudo());
+        let stage: Stage = toml::from_str("").unwrap();
+        assert!(!stage.needs_sudo());
+    }
+
+    #[test]
+    fn it_lists_modules() {
+        let out = list_modules();
+        for name in [
+            "mount",
+            "mkdir",
+            "apt",
+            "keys",
+            "git",
+            "aws",
+            "docker",
+            "terraform",
+            "node-exporter",
+            "docker-stats",
+        ] {
+            assert!(
+                out.lines().any(|l| l.starts_with(name)),
+                "{} is missing",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn it_shows_module_help() {
+        let out = module_help("mkdir").unwrap();
+        assert!(out.contains("folders (list of strings)"));
+        assert!(out.contains("perm (string, optional, default \"0777\")"));
+
+        assert!(module_help("node_exporter")
+            .unwrap()
+            .contains("no parameters"));
+        assert!(module_help("unknown").is_err());
+
+        // descriptions are the doc comments of the fields, joined into one line
+        let out = module_help("mount").unwrap();
+        assert!(
+            out.contains("i.e. /data, the biggest free device that is not mounted is selected\n")
+        );
+        let out = module_help("sudoers").unwrap();
+        assert!(out.contains("state (string, optional, default \"present\") - present or absent\n"));
+    }
+
+    #[test]
+    fn it_documents_every_param() {
+        for module in MODULES {
+            for param in module.params {
+                assert!(
+                    !param.description.trim().is_empty(),
+                    "{}.{} has no doc comment",
+                    module.name,
+                    param.name
+                );
+            }
         }
     }
-    Ok(())
 }
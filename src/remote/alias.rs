@@ -1,28 +1,27 @@
 use crate::prelude::*;
+use crate::report::StageReport;
 use base64::{engine::general_purpose, Engine as _};
 
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, key: &str, value: &str) -> anyhow::Result<()> {
+pub async fn on_install(client: &Client, key: &str, value: &str) -> anyhow::Result<bool> {
     let cmd_check = format!("cat ~/.bashrc | grep {}= 2>&1", key);
-    if let Err(_) = which(client, &cmd_check).await {
-        let orig = format!("alias {}='{}'\n", key, value);
+    if which(client, &cmd_check).await.is_err() {
+        let orig = format!("alias {}={}\n", key, shell_quote(value));
         let encoded = general_purpose::STANDARD_NO_PAD.encode(orig.as_bytes());
         let cmd_set = format!("echo {} | base64 -d - >> ~/.bashrc", encoded);
         run(client, &cmd_set).await?;
+        return Ok(true);
     };
-    Ok(())
+    Ok(false)
 }
 
 #[instrument(skip(client))]
-pub async fn install(client: &Client, items: &Map<String, String>) -> anyhow::Result<()> {
-    println!("= {}", "aliases".yellow());
+pub async fn install(client: &Client, items: &Map<String, String>) -> anyhow::Result<StageReport> {
+    let mut report = StageReport::start("aliases");
     for (alias, value) in items {
-        match on_install(client, alias, value).await {
-            Ok(_) => println!("+ {}: {}", alias.green(), "OK".green()),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+        report.installed(alias, on_install(client, alias, value).await);
     }
-    Ok(())
+    Ok(report)
 }
 
 #[instrument(skip(client))]
@@ -39,15 +38,10 @@ pub async fn on_check(client: &Client, key: &str) -> anyhow::Result<Status> {
 }
 
 #[instrument(skip(client, items))]
-pub async fn check(client: &Client, items: &Map<String, String>) -> anyhow::Result<()> {
-    println!("= {}", "aliases".yellow());
-
-    for (alias, _) in items {
-        match on_check(client, alias).await {
-            Ok(status) => status.print(alias),
-            Err(e) => println!("- {}: {} {}", alias.red(), "FAILURE".red(), e),
-        }
+pub async fn check(client: &Client, items: &Map<String, String>) -> anyhow::Result<StageReport> {
+    let mut report = StageReport::start("aliases");
+    for alias in items.keys() {
+        report.checked(alias, on_check(client, alias).await);
     }
-
-    Ok(())
+    Ok(report)
 }
@@ -0,0 +1,184 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct AlternativesOptions {
+        /// name of the alternative, i.e. editor, java
+        pub name: String,
+        /// path of the alternative that should become the default
+        pub path: String,
+        /// priority used when the alternative is not registered yet
+        #[param(default = "50")]
+        pub priority: Option<i32>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "alternatives",
+    description: "select the default alternative with update-alternatives",
+    params: AlternativesOptions::PARAMS,
+    sudo: true,
+};
+
+const DEFAULT_PRIORITY: i32 = 50;
+
+/// parsed output of `update-alternatives --query <name>`
+#[derive(Debug, Default, PartialEq)]
+pub struct AlternativesQuery {
+    pub link: Option<String>,
+    pub status: Option<String>,
+    pub value: Option<String>,
+    pub alternatives: Vec<String>,
+}
+
+impl AlternativesQuery {
+    pub fn parse(output: &str) -> Self {
+        let mut query = AlternativesQuery::default();
+        for line in output.lines() {
+            // slave links are indented, they are not relevant here
+            if line.starts_with(' ') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+            match key {
+                "Link" => query.link = Some(value),
+                "Status" => query.status = Some(value),
+                "Value" => query.value = Some(value),
+                "Alternative" => query.alternatives.push(value),
+                _ => {}
+            }
+        }
+        query
+    }
+
+    pub fn has(&self, path: &str) -> bool {
+        self.alternatives.iter().any(|x| x == path)
+    }
+
+    /// the path is set manually, in auto mode a package with a higher priority
+    /// would take the link over on its installation
+    pub fn is_selected(&self, path: &str) -> bool {
+        self.value.as_deref() == Some(path) && self.status.as_deref() == Some("manual")
+    }
+}
+
+async fn query(client: &Client, name: &str) -> anyhow::Result<AlternativesQuery> {
+    let cmd = format!("update-alternatives --query {} 2>&1", shell_quote(name));
+    let output = silent(client, &cmd).await?;
+    Ok(AlternativesQuery::parse(&output.output))
+}
+
+/// sets `path` as the default for `name`, returns true if anything was changed
+#[instrument(skip(client))]
+pub async fn alternatives_ensure(
+    client: &Client,
+    name: &str,
+    path: &str,
+    priority: i32,
+) -> anyhow::Result<bool> {
+    let current = query(client, name).await?;
+    if current.is_selected(path) {
+        debug!("{} already points to {}", name, path);
+        return Ok(false);
+    }
+    if !current.has(path) {
+        let link = current
+            .link
+            .clone()
+            .unwrap_or_else(|| format!("/usr/bin/{}", name));
+        let cmd = format!(
+            "sudo update-alternatives --install {} {} {} {} 2>&1",
+            shell_quote(&link),
+            shell_quote(name),
+            shell_quote(path),
+            priority
+        );
+        run(client, &cmd).await?;
+    }
+    let cmd = format!(
+        "sudo update-alternatives --set {} {} 2>&1",
+        shell_quote(name),
+        shell_quote(path)
+    );
+    run(client, &cmd).await?;
+    Ok(true)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &AlternativesOptions) -> anyhow::Result<bool> {
+    let priority = opt.priority.unwrap_or(DEFAULT_PRIORITY);
+    let changed = alternatives_ensure(client, &opt.name, &opt.path, priority).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &AlternativesOptions) -> anyhow::Result<Status> {
+    let mut success = vec![];
+    let mut fail = vec![];
+
+    let current = query(client, &opt.name).await?;
+    match &current.value {
+        Some(value) if current.is_selected(&opt.path) => {
+            success.push(format!("{} -> {}", opt.name, value))
+        }
+        Some(value) if value == &opt.path => fail.push(format!(
+            "{} -> {} is selected automatically",
+            opt.name, value
+        )),
+        Some(value) => fail.push(format!("{} -> {}, expected {}", opt.name, value, opt.path)),
+        None => fail.push(format!("{} is not registered", opt.name)),
+    }
+    Ok(Status::new(success, fail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_query() {
+        let output = r#"Name: editor
+Link: /usr/bin/editor
+Slaves:
+ editor.1.gz /usr/share/man/man1/editor.1.gz
+Status: auto
+Best: /bin/nano
+Value: /bin/nano
+
+Alternative: /bin/nano
+Priority: 40
+Slaves:
+ editor.1.gz /usr/share/man/man1/nano.1.gz
+
+Alternative: /usr/bin/vim.basic
+Priority: 30
+Slaves:
+ editor.1.gz /usr/share/man/man1/vim.1.gz
+"#;
+        let query = AlternativesQuery::parse(output);
+        assert_eq!(query.link.as_deref(), Some("/usr/bin/editor"));
+        assert_eq!(query.status.as_deref(), Some("auto"));
+        assert_eq!(query.value.as_deref(), Some("/bin/nano"));
+        assert_eq!(query.alternatives, vec!["/bin/nano", "/usr/bin/vim.basic"]);
+        assert!(query.has("/usr/bin/vim.basic"));
+        assert!(!query.has("/usr/bin/emacs"));
+        // the best alternative in auto mode is still set with --set
+        assert!(!query.is_selected("/bin/nano"));
+        let manual = AlternativesQuery::parse(&output.replace("Status: auto", "Status: manual"));
+        assert!(manual.is_selected("/bin/nano"));
+        assert!(!manual.is_selected("/usr/bin/vim.basic"));
+    }
+
+    #[test]
+    fn it_parses_missing_name() {
+        let output = "update-alternatives: error: no alternatives for java\n";
+        assert_eq!(
+            AlternativesQuery::parse(output),
+            AlternativesQuery::default()
+        );
+    }
+}
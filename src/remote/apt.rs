@@ -1,64 +1,424 @@
 use crate::prelude::*;
+use crate::remote::ModuleInfo;
 
-#[derive(Debug, Default, Deserialize)]
-pub struct AptOptions {
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct AptOptions {
+        /// packages to be installed, optionally qualified as name:arch
+        pub install: Vec<String>,
+        /// architecture for packages without `:arch` qualifier, i.e. i386
+        pub arch: Option<String>,
+        /// packages to be marked as manually installed, to keep them from autoremove
+        pub manual: Option<Vec<String>>,
+        /// skip apt-get update if the package lists are newer than this
+        pub cache_valid_secs: Option<u64>,
+        /// fail early if /var/cache/apt has less free space, in megabytes
+        pub min_free_mb: Option<u64>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "apt",
+    description: "install apt packages",
+    params: AptOptions::PARAMS,
+    sudo: true,
+};
+
+/// package as reported by dpkg-query
+#[derive(Debug, PartialEq)]
+pub struct DpkgPackage {
+    pub name: String,
+    pub arch: String,
+    pub installed: bool,
+}
+
+const DPKG_QUERY_FORMAT: &str = "'${Package} ${Architecture} ${Status}\\n'";
+
+/// qualifies the package with the architecture, unless it is qualified already
+pub fn qualified(package: &str, arch: Option<&str>) -> String {
+    match arch {
+        Some(arch) if !package.contains(':') => format!("{}:{}", package, arch),
+        _ => package.to_string(),
+    }
+}
+
+/// parses `dpkg-query -W -f='${Package} ${Architecture} ${Status}\n'` output
+pub fn parse_dpkg_query(output: &str) -> Vec<DpkgPackage> {
+    let mut packages = vec![];
+    for line in output.lines() {
+        let mut columns = line.split_whitespace();
+        let (Some(name), Some(arch)) = (columns.next(), columns.next()) else {
+            continue;
+        };
+        let status: Vec<&str> = columns.collect();
+        packages.push(DpkgPackage {
+            name: name.to_string(),
+            arch: arch.to_string(),
+            installed: status == ["install", "ok", "installed"],
+        });
+    }
+    packages
+}
+
+/// checks if the package (optionally qualified as name:arch) is installed
+pub fn is_installed(packages: &[DpkgPackage], spec: &str) -> bool {
+    let (name, arch) = match spec.split_once(':') {
+        Some((name, arch)) => (name, Some(arch)),
+        None => (spec, None),
+    };
+    packages.iter().any(|p| {
+        p.installed && p.name == name && arch.is_none_or(|a| p.arch == a || p.arch == "all")
+    })
+}
+
+/// parses `date +%s` and mtime of the package lists into their age in seconds
+pub fn parse_cache_age(output: &str) -> Option<u64> {
+    let mut columns = output.split_whitespace().map(|x| x.parse::<u64>());
+    let (Some(Ok(now)), Some(Ok(mtime))) = (columns.next(), columns.next()) else {
+        return None;
+    };
+    Some(now.saturating_sub(mtime))
+}
+
+pub fn is_cache_fresh(age: Option<u64>, cache_valid_secs: Option<u64>) -> bool {
+    matches!((age, cache_valid_secs), (Some(age), Some(valid)) if age < valid)
+}
+
+/// runs apt-get update unless the cache is fresh, returns true if it was updated
+#[instrument(skip(client))]
+pub async fn apt_update(client: &Client, cache_valid_secs: Option<u64>) -> anyhow::Result<bool> {
+    if cache_valid_secs.is_some() {
+        let cmd = "echo $(date +%s) $(stat -c %Y /var/lib/apt/lists/partial 2>/dev/null)";
+        let age = parse_cache_age(&silent(client, cmd).await?.output);
+        if is_cache_fresh(age, cache_valid_secs) {
+            debug!("apt cache is {:?} seconds old, not updating", age);
+            return Ok(false);
+        }
+    }
+    run(client, "sudo apt-get update 2>&1").await?;
+    Ok(true)
+}
+
+/// true unless the `N upgraded, N newly installed, N to remove` summary of apt-get is all zeros
+pub fn has_package_changes(output: &str) -> bool {
+    output
+        .lines()
+        .filter(|line| line.contains(" newly installed"))
+        .any(|line| {
+            line.split(',').take(3).any(|part| {
+                part.split_whitespace()
+                    .next()
+                    .and_then(|n| n.parse::<u64>().ok())
+                    .is_some_and(|n| n > 0)
+            })
+        })
+}
+
+/// name of the package as `apt-mark showmanual` prints it: without the
+/// `=version` or `/release` of the spec, and with `:arch` only if it is foreign
+pub fn manual_name(spec: &str, native_arch: &str) -> String {
+    let name = spec.split(['=', '/']).next().unwrap_or(spec);
+    match name.split_once(':') {
+        Some((name, arch)) if arch == native_arch || arch == "all" => name.to_string(),
+        _ => name.to_string(),
+    }
+}
+
+/// returns names of packages that are not in `apt-mark showmanual` output yet
+pub fn not_manual(showmanual: &str, packages: &[String], native_arch: &str) -> Vec<String> {
+    let marked: Vec<&str> = showmanual.lines().map(|x| x.trim()).collect();
+    let mut missing: Vec<String> = vec![];
+    for name in packages.iter().map(|p| manual_name(p, native_arch)) {
+        if !marked.contains(&name.as_str()) && !missing.contains(&name) {
+            missing.push(name);
+        }
+    }
+    missing
+}
+
+async fn not_marked_manual(client: &Client, packages: &[String]) -> anyhow::Result<Vec<String>> {
+    let native_arch = crate::facts::dpkg_architecture(client).await?;
+    let showmanual = run(client, "apt-mark showmanual 2>&1").await?;
+    Ok(not_manual(&showmanual.output, packages, &native_arch))
+}
+
+/// marks packages as manually installed, returns true if any was changed
+#[instrument(skip(client))]
+pub async fn apt_mark_manual(client: &Client, packages: &[String]) -> anyhow::Result<bool> {
+    let missing = not_marked_manual(client, packages).await?;
+    if missing.is_empty() {
+        return Ok(false);
+    }
+    let missing: Vec<&str> = missing.iter().map(|x| x.as_str()).collect();
+    let cmd = format!("sudo apt-mark manual {} 2>&1", shell_join(&missing));
+    run(client, &cmd).await?;
+    Ok(true)
+}
+
+/// changes apt would make, parsed from `apt-get -s` simulation
+#[derive(Debug, Default, PartialEq)]
+pub struct PackageChanges {
     pub install: Vec<String>,
+    pub upgrade: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+impl PackageChanges {
+    /// parses `Inst`/`Remv` rows of the simulation,
+    /// `Inst` with the current version in brackets is an upgrade
+    pub fn parse(output: &str) -> Self {
+        let mut changes = PackageChanges::default();
+        for line in output.lines() {
+            let mut columns = line.split_whitespace();
+            let (Some(action), Some(name)) = (columns.next(), columns.next()) else {
+                continue;
+            };
+            let upgrade = columns.next().is_some_and(|x| x.starts_with('['));
+            match action {
+                "Inst" if upgrade => changes.upgrade.push(name.to_string()),
+                "Inst" => changes.install.push(name.to_string()),
+                "Remv" => changes.remove.push(name.to_string()),
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    /// preview of the check report
+    pub fn preview(&self) -> Vec<Preview> {
+        [
+            ("install", &self.install),
+            ("upgrade", &self.upgrade),
+            ("remove", &self.remove),
+        ]
+        .into_iter()
+        .filter(|(_, list)| !list.is_empty())
+        .map(|(action, list)| Preview {
+            action: action.to_string(),
+            items: list.clone(),
+        })
+        .collect()
+    }
 }
 
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, opt: &AptOptions) -> anyhow::Result<()> {
-    let packages = opt.install.join(" ");
+pub async fn on_install(client: &Client, opt: &AptOptions) -> anyhow::Result<bool> {
+    let packages = opt
+        .install
+        .iter()
+        .map(|p| qualified(p, opt.arch.as_deref()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if let Some(min_free_mb) = opt.min_free_mb {
+        require_free_space(client, "/var/cache/apt", min_free_mb * 1_000_000).await?;
+    }
     run(client, "export DEBIAN_FRONTEND=noninteractive").await?;
-    run(client, "sudo apt-get update 2>&1").await?;
-    if let Err(e) = run(
+    let updated = apt_update(client, opt.cache_valid_secs).await?;
+    info!("apt cache updated: {}", updated);
+    let installed = match run(
         client,
         &format!("sudo apt-get install -yq {} 2>&1", packages),
     )
     .await
     {
-        // parse each "Unable to locate package" row to return better error
-        let mut missing = vec![];
-        for line in e.to_string().lines() {
-            if line.contains("Unable to locate package") {
-                let package = line
-                    .replace("Unable to locate package ", "")
-                    .replace("E: ", "");
-                missing.push(package);
+        Ok(out) => out,
+        Err(e) => {
+            // parse each "Unable to locate package" row to return better error
+            let mut missing = vec![];
+            for line in e.to_string().lines() {
+                if line.contains("Unable to locate package") {
+                    let package = line
+                        .replace("Unable to locate package ", "")
+                        .replace("E: ", "");
+                    missing.push(package);
+                }
             }
+            if !missing.is_empty() {
+                bail!("Unable to locate: {}", missing.join(", "));
+            }
+            return Err(e);
         }
-        if missing.len() > 0 {
-            bail!("Unable to locate: {}", missing.join(", "));
-        }
-        return Err(e);
+    };
+    let mut changed = has_package_changes(&installed.output);
+    if let Some(manual) = &opt.manual {
+        changed |= apt_mark_manual(client, manual).await?;
     }
 
-    Ok(())
+    Ok(changed)
 }
 
 #[instrument(skip(client))]
 pub async fn on_check(client: &Client, opt: &AptOptions) -> anyhow::Result<Status> {
     let mut success = vec![];
     let mut fail = vec![];
+    let mut missing = vec![];
     for package in &opt.install {
-        let cmd = format!("sudo dpkg -s {} 2>&1", package);
+        let spec = qualified(package, opt.arch.as_deref());
+        let cmd = format!("dpkg-query -W -f={} {} 2>&1", DPKG_QUERY_FORMAT, spec);
         match silent(client, &cmd).await {
             Ok(output) => {
                 if output.exit_status == 0 {
-                    if output.output.contains("Status: install ok installed") {
-                        success.push(format!("{} ok", package));
+                    if is_installed(&parse_dpkg_query(&output.output), &spec) {
+                        success.push(format!("{} ok", spec));
                     } else {
-                        fail.push(format!("{} missing", package));
+                        fail.push(format!("{} missing", spec));
+                        missing.push(spec);
                     }
                 } else {
                     let errmsg = output.output.replace("dpkg-query: ", "").clone();
                     let first_line = errmsg.lines().next().unwrap_or("").to_string();
                     fail.push(first_line);
+                    missing.push(spec);
                 }
             }
             Err(_) => {
-                fail.push(format!("{} missing", package));
+                fail.push(format!("{} missing", spec));
+            }
+        }
+    }
+    if let Some(manual) = &opt.manual {
+        for package in not_marked_manual(client, manual).await? {
+            fail.push(format!("{} is not marked manual", package));
+        }
+    }
+    let mut preview = vec![];
+    if !missing.is_empty() {
+        // simulation doesn't require root and doesn't change anything
+        let cmd = format!("apt-get -s install -yq {} 2>&1", missing.join(" "));
+        match silent(client, &cmd).await {
+            Ok(output) if output.exit_status == 0 => {
+                preview = PackageChanges::parse(&output.output).preview();
             }
+            _ => add_warning(format!("apt simulation of {} failed", missing.join(" "))),
         }
     }
-    Ok(Status::new(success, fail))
+    Ok(Status::new(success, fail).with_preview(preview))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_qualifies_packages() {
+        assert_eq!(qualified("libc6", None), "libc6");
+        assert_eq!(qualified("libc6", Some("i386")), "libc6:i386");
+        assert_eq!(qualified("libc6:amd64", Some("i386")), "libc6:amd64");
+    }
+
+    #[test]
+    fn it_parses_multiarch_dpkg_query() {
+        let output = r#"libc6 amd64 install ok installed
+libc6 i386 install ok installed
+libssl3 i386 deinstall ok config-files
+tzdata all install ok installed
+"#;
+        let packages = parse_dpkg_query(output);
+        assert_eq!(packages.len(), 4);
+        assert_eq!(
+            packages[1],
+            DpkgPackage {
+                name: "libc6".to_string(),
+                arch: "i386".to_string(),
+                installed: true,
+            }
+        );
+        assert!(is_installed(&packages, "libc6"));
+        assert!(is_installed(&packages, "libc6:i386"));
+        assert!(!is_installed(&packages, "libc6:arm64"));
+        assert!(!is_installed(&packages, "libssl3:i386"));
+        assert!(is_installed(&packages, "tzdata:amd64"));
+        assert!(!is_installed(&packages, "curl"));
+    }
+
+    #[test]
+    fn it_previews_package_changes() {
+        let output = r#"NOTE: This is only a simulation!
+Reading package lists...
+The following NEW packages will be installed:
+  jq libjq1 libonig5
+Remv oldjq [1.5]
+Inst libonig5 (6.9.8-1 Debian:12.5/stable [amd64])
+Inst libjq1 (1.6-2.1 Debian:12.5/stable [amd64])
+Inst curl [7.88.1-10] (7.88.1-10+deb12u5 Debian-Security:12/stable-security [amd64])
+Inst jq (1.6-2.1 Debian:12.5/stable [amd64])
+Conf libonig5 (6.9.8-1 Debian:12.5/stable [amd64])
+"#;
+        let changes = PackageChanges::parse(output);
+        assert_eq!(changes.install, vec!["libonig5", "libjq1", "jq"]);
+        assert_eq!(changes.upgrade, vec!["curl"]);
+        assert_eq!(changes.remove, vec!["oldjq"]);
+        let preview = changes.preview();
+        assert_eq!(
+            preview[0],
+            Preview {
+                action: "install".to_string(),
+                items: vec![
+                    "libonig5".to_string(),
+                    "libjq1".to_string(),
+                    "jq".to_string()
+                ],
+            }
+        );
+        assert_eq!(
+            preview.iter().map(|x| x.to_string()).collect::<Vec<_>>(),
+            vec![
+                "would install: libonig5 libjq1 jq",
+                "would upgrade: curl",
+                "would remove: oldjq"
+            ]
+        );
+        assert!(PackageChanges::parse("").preview().is_empty());
+    }
+
+    #[test]
+    fn it_detects_package_changes() {
+        let output = "Setting up jq (1.6-2.1) ...\n0 upgraded, 2 newly installed, 0 to remove and 5 not upgraded.\n";
+        assert!(has_package_changes(output));
+        let output = "curl is already the newest version (7.88.1-10+deb12u5).\n0 upgraded, 0 newly installed, 0 to remove and 5 not upgraded.\n";
+        assert!(!has_package_changes(output));
+        assert!(!has_package_changes(""));
+    }
+
+    #[test]
+    fn it_parses_showmanual() {
+        let output = "curl\nlibc6:i386\nvim\n";
+        let packages = vec![
+            "curl".to_string(),
+            "jq".to_string(),
+            "libc6:i386".to_string(),
+        ];
+        assert_eq!(not_manual(output, &packages, "amd64"), vec!["jq"]);
+        assert!(not_manual(output, &packages[..1], "amd64").is_empty());
+        assert_eq!(not_manual("", &packages[..1], "amd64"), vec!["curl"]);
+    }
+
+    #[test]
+    fn it_normalizes_specs_for_showmanual() {
+        assert_eq!(manual_name("jq=1.6-2.1", "amd64"), "jq");
+        assert_eq!(manual_name("curl/bookworm-backports", "amd64"), "curl");
+        assert_eq!(manual_name("libc6:amd64", "amd64"), "libc6");
+        assert_eq!(manual_name("tzdata:all", "amd64"), "tzdata");
+        assert_eq!(manual_name("libc6:i386=2.36-9", "amd64"), "libc6:i386");
+        let output = "curl\njq\nlibc6\nlibc6:i386\n";
+        let packages = vec![
+            "jq=1.6-2.1".to_string(),
+            "curl/bookworm-backports".to_string(),
+            "libc6:amd64".to_string(),
+            "libc6:i386".to_string(),
+        ];
+        assert!(not_manual(output, &packages, "amd64").is_empty());
+        let packages = vec!["vim=2:9.0".to_string(), "vim/bookworm".to_string()];
+        assert_eq!(not_manual(output, &packages, "amd64"), vec!["vim"]);
+    }
+
+    #[test]
+    fn it_skips_fresh_cache() {
+        assert_eq!(parse_cache_age("1700003600 1700000000\n"), Some(3600));
+        assert_eq!(parse_cache_age("1700003600 \n"), None);
+        assert!(is_cache_fresh(Some(3600), Some(7200)));
+        assert!(!is_cache_fresh(Some(3600), Some(600)));
+        assert!(!is_cache_fresh(None, Some(600)));
+        assert!(!is_cache_fresh(Some(0), None));
+    }
 }
@@ -0,0 +1,276 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct AptMirrorOptions {
+        /// mirror URL, i.e. http://mirror.hetzner.com/debian/packages
+        pub mirror: String,
+        /// archive URIs to be replaced, the Debian and Ubuntu archives by default
+        pub from: Option<Vec<String>>,
+        /// keep the original files with .orig suffix
+        #[param(default = "true")]
+        pub backup: Option<bool>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "apt-mirror",
+    description: "point the distribution archive in apt sources to a mirror",
+    params: AptMirrorOptions::PARAMS,
+    sudo: true,
+};
+
+const SOURCES_LIST: &str = "/etc/apt/sources.list";
+const SOURCES_LIST_D: &str = "/etc/apt/sources.list.d";
+
+/// the main archives of Debian and Ubuntu, while security and third-party repositories are kept
+pub fn is_distribution_archive(uri: &str) -> bool {
+    let Some((_, rest)) = uri.split_once("://") else {
+        return false;
+    };
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    match path.trim_end_matches('/') {
+        "debian" => {
+            host == "deb.debian.org"
+                || host == "httpredir.debian.org"
+                || (host.starts_with("ftp.") && host.ends_with(".debian.org"))
+        }
+        "ubuntu" => host == "archive.ubuntu.com" || host.ends_with(".archive.ubuntu.com"),
+        _ => false,
+    }
+}
+
+fn is_replaced(uri: &str, from: Option<&[String]>) -> bool {
+    match from {
+        Some(from) => from
+            .iter()
+            .any(|f| f.trim_end_matches('/') == uri.trim_end_matches('/')),
+        None => is_distribution_archive(uri),
+    }
+}
+
+// byte ranges of the whitespace separated words, so the line is edited in place
+fn word_spans(line: &str) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    let mut start = None;
+    for (i, c) in line.char_indices() {
+        if c.is_whitespace() {
+            if let Some(s) = start.take() {
+                spans.push((s, i));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, line.len()));
+    }
+    spans
+}
+
+/// replaces archive URIs of deb and deb-src lines, keeping options, suites, components and spacing
+pub fn rewrite_sources(content: &str, mirror: &str, from: Option<&[String]>) -> String {
+    rewrite_lines(content, |line| rewrite_line(line, mirror, from))
+}
+
+/// replaces archive URIs of `URIs:` fields in deb822 .sources files
+pub fn rewrite_deb822(content: &str, mirror: &str, from: Option<&[String]>) -> String {
+    rewrite_lines(content, |line| rewrite_uris_field(line, mirror, from))
+}
+
+fn rewrite_lines(content: &str, rewrite: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(content.len());
+    for line in content.lines() {
+        out.push_str(&rewrite(line).unwrap_or_else(|| line.to_string()));
+        out.push('\n');
+    }
+    out
+}
+
+fn replace_span(line: &str, (start, end): (usize, usize), mirror: &str) -> String {
+    format!("{}{}{}", &line[..start], mirror, &line[end..])
+}
+
+fn rewrite_line(line: &str, mirror: &str, from: Option<&[String]>) -> Option<String> {
+    let spans = word_spans(line);
+    let words: Vec<&str> = spans.iter().map(|&(s, e)| &line[s..e]).collect();
+    if !matches!(words.first(), Some(&"deb") | Some(&"deb-src")) {
+        return None;
+    }
+    // options are in brackets, i.e. [arch=amd64 signed-by=/usr/share/keyrings/x.gpg]
+    let mut uri = 1;
+    if words.get(1)?.starts_with('[') {
+        uri = 1 + words[1..].iter().position(|w| w.ends_with(']'))? + 1;
+    }
+    if !is_replaced(words.get(uri)?, from) {
+        return None;
+    }
+    Some(replace_span(line, spans[uri], mirror))
+}
+
+fn rewrite_uris_field(line: &str, mirror: &str, from: Option<&[String]>) -> Option<String> {
+    let (field, _) = line.split_once(':')?;
+    if !field.eq_ignore_ascii_case("URIs") {
+        return None;
+    }
+    let spans = word_spans(line);
+    let mut out = line.to_string();
+    let mut changed = false;
+    // from the end, so the earlier spans stay valid
+    for &(start, end) in spans.iter().skip(1).rev() {
+        if is_replaced(&line[start..end], from) {
+            out = replace_span(&out, (start, end), mirror);
+            changed = true;
+        }
+    }
+    changed.then_some(out)
+}
+
+/// rewritten content of the apt sources file, by its format
+pub fn rewrite(path: &str, content: &str, mirror: &str, from: Option<&[String]>) -> String {
+    if path.ends_with(".sources") {
+        rewrite_deb822(content, mirror, from)
+    } else {
+        rewrite_sources(content, mirror, from)
+    }
+}
+
+/// sources.list and the files in sources.list.d, deb822 only systems have no sources.list
+async fn sources_files(client: &Client) -> anyhow::Result<Vec<String>> {
+    let cmd = format!("ls -1 {0}/*.list {0}/*.sources 2>/dev/null", SOURCES_LIST_D);
+    let mut files = vec![SOURCES_LIST.to_string()];
+    files.extend(
+        silent(client, &cmd)
+            .await?
+            .output
+            .lines()
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty()),
+    );
+    Ok(files)
+}
+
+/// rewrites apt sources to the mirror and updates package lists,
+/// returns true if any file was changed or the package lists were updated
+#[instrument(skip(client))]
+pub async fn apt_sources_mirror(
+    client: &Client,
+    mirror_url: &str,
+    from: Option<&[String]>,
+    backup: bool,
+) -> anyhow::Result<bool> {
+    let mut changed = false;
+    for path in sources_files(client).await? {
+        let Some(content) = read_file(client, &path).await? else {
+            continue;
+        };
+        let rewritten = rewrite(&path, &content, mirror_url, from);
+        if rewritten == content {
+            continue;
+        }
+        if backup {
+            // the first backup holds the distribution file, it is never overwritten
+            let cmd = format!(
+                "sudo cp -n {} {} 2>&1",
+                shell_quote(&path),
+                shell_quote(&format!("{}.orig", path))
+            );
+            run(client, &cmd).await?;
+        }
+        write_file(client, &path, &rewritten).await?;
+        changed = true;
+    }
+    // package lists are updated on the next run as well, if apt-get update fails
+    let pending = mark_pending(client, "apt-mirror-update", changed).await?;
+    if pending {
+        run(client, "sudo apt-get update 2>&1").await?;
+        clear_pending(client, "apt-mirror-update").await?;
+    }
+    Ok(pending)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &AptMirrorOptions) -> anyhow::Result<bool> {
+    let backup = opt.backup.unwrap_or(true);
+    let changed = apt_sources_mirror(client, &opt.mirror, opt.from.as_deref(), backup).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &AptMirrorOptions) -> anyhow::Result<Status> {
+    let mut success = vec![];
+    let mut fail = vec![];
+    for path in sources_files(client).await? {
+        if let Some(content) = read_file(client, &path).await? {
+            if rewrite(&path, &content, &opt.mirror, opt.from.as_deref()) == content {
+                success.push(format!("{} ok", path));
+            } else {
+                fail.push(format!("{} differs", path));
+            }
+        }
+    }
+    if success.is_empty() && fail.is_empty() {
+        fail.push("no apt sources found".to_string());
+    }
+    Ok(Status::new(success, fail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_rewrites_sources_to_mirror() {
+        let content = "# main\ndeb http://deb.debian.org/debian bookworm main contrib\ndeb-src http://deb.debian.org/debian bookworm main\ndeb [arch=amd64 signed-by=/usr/share/keyrings/x.gpg] http://deb.debian.org/debian bookworm-updates main\n";
+        let mirror = "http://mirror.example.com/debian";
+        let out = rewrite_sources(content, mirror, None);
+        assert_eq!(
+            out,
+            "# main\ndeb http://mirror.example.com/debian bookworm main contrib\ndeb-src http://mirror.example.com/debian bookworm main\ndeb [arch=amd64 signed-by=/usr/share/keyrings/x.gpg] http://mirror.example.com/debian bookworm-updates main\n"
+        );
+        assert_eq!(rewrite_sources(&out, mirror, None), out);
+    }
+
+    #[test]
+    fn it_keeps_security_and_third_party_lines() {
+        let content = "deb  http://archive.ubuntu.com/ubuntu/   jammy main\ndeb http://security.ubuntu.com/ubuntu jammy-security main\ndeb http://deb.debian.org/debian-security bookworm-security main\ndeb [signed-by=/etc/apt/keyrings/docker.gpg] https://download.docker.com/linux/ubuntu jammy stable\n";
+        let out = rewrite_sources(content, "http://mirror.example.com/ubuntu", None);
+        assert_eq!(
+            out,
+            "deb  http://mirror.example.com/ubuntu   jammy main\ndeb http://security.ubuntu.com/ubuntu jammy-security main\ndeb http://deb.debian.org/debian-security bookworm-security main\ndeb [signed-by=/etc/apt/keyrings/docker.gpg] https://download.docker.com/linux/ubuntu jammy stable\n"
+        );
+
+        let from = vec!["http://de.archive.ubuntu.com/ubuntu/".to_string()];
+        let content = "deb http://de.archive.ubuntu.com/ubuntu jammy main\ndeb http://archive.ubuntu.com/ubuntu jammy main\n";
+        assert_eq!(
+            rewrite_sources(content, "http://m/ubuntu", Some(&from)),
+            "deb http://m/ubuntu jammy main\ndeb http://archive.ubuntu.com/ubuntu jammy main\n"
+        );
+    }
+
+    #[test]
+    fn it_rewrites_deb822_sources() {
+        let content = "Types: deb\nURIs: http://deb.debian.org/debian\nSuites: bookworm bookworm-updates\nComponents: main\n\nTypes: deb\nURIs: http://deb.debian.org/debian-security\nSuites: bookworm-security\n";
+        let out = rewrite(
+            "/etc/apt/sources.list.d/debian.sources",
+            content,
+            "http://m/debian",
+            None,
+        );
+        assert_eq!(
+            out,
+            "Types: deb\nURIs: http://m/debian\nSuites: bookworm bookworm-updates\nComponents: main\n\nTypes: deb\nURIs: http://deb.debian.org/debian-security\nSuites: bookworm-security\n"
+        );
+        assert_eq!(rewrite_deb822(&out, "http://m/debian", None), out);
+    }
+
+    #[test]
+    fn it_keeps_broken_lines() {
+        assert_eq!(rewrite_sources("deb\n", "http://m", None), "deb\n");
+        assert_eq!(
+            rewrite_sources("deb [arch=amd64\n", "http://m", None),
+            "deb [arch=amd64\n"
+        );
+    }
+}
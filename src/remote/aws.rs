@@ -1,14 +1,26 @@
 use crate::prelude::*;
-
-#[derive(Debug, Default, Deserialize)]
-pub struct AwsOptions {
-    /// profile to be uploaded
-    pub profile: Option<String>,
-    /// if profile is uploaded, it can be renamed into a different name
-    /// typically used to rename the default profile
-    pub rename: Option<String>,
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct AwsOptions {
+        /// profile to be uploaded
+        pub profile: Option<String>,
+        /// if profile is uploaded, it can be renamed into a different name
+        /// typically used to rename the default profile
+        pub rename: Option<String>,
+        /// fail early if the home folder has less free space for the installer, in megabytes
+        pub min_free_mb: Option<u64>,
+    }
 }
 
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "aws",
+    description: "install AWS CLI and upload local profile",
+    params: AwsOptions::PARAMS,
+    sudo: true,
+};
+
 fn read_aws_profile_region(profile: &str) -> anyhow::Result<String> {
     let local_path = crate::connect::tilde_with_context("~/.aws/config", dirs::home_dir);
     let aws_config = std::fs::read_to_string(&local_path)?;
@@ -17,7 +29,7 @@ fn read_aws_profile_region(profile: &str) -> anyhow::Result<String> {
     let mut found = false;
     let mut reading_profile = false;
     for line in aws_config.lines() {
-        if line.trim().len() == 0 || line.trim().starts_with('#') {
+        if line.trim().is_empty() || line.trim().starts_with('#') {
             // skip empty lines
             continue;
         }
@@ -61,7 +73,7 @@ fn read_aws_profile_keys(profile: &str) -> anyhow::Result<(String, String)> {
     let mut found = false;
     let mut reading_profile = false;
     for line in aws_config.lines() {
-        if line.trim().len() == 0 || line.trim().starts_with('#') {
+        if line.trim().is_empty() || line.trim().starts_with('#') {
             // skip empty lines
             continue;
         }
@@ -98,46 +110,56 @@ fn read_aws_profile_keys(profile: &str) -> anyhow::Result<(String, String)> {
 }
 
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, opt: &AwsOptions) -> anyhow::Result<()> {
+pub async fn on_install(client: &Client, opt: &AwsOptions) -> anyhow::Result<bool> {
+    let mut changed = false;
     // install aws2 CLI
-    if let Err(_) = which(client, "aws --version 2>&1").await {
-        let cmd =
-            "curl https://awscli.amazonaws.com/awscli-exe-linux-x86_64.zip -o awscliv2.zip 2>&1";
-        run(&client, cmd).await?;
+    if which(client, "aws --version 2>&1").await.is_err() {
+        if let Some(min_free_mb) = opt.min_free_mb {
+            require_free_space(client, ".", min_free_mb * 1_000_000).await?;
+        }
+        let url = "https://awscli.amazonaws.com/awscli-exe-linux-x86_64.zip";
+        let cmd = match which_any(client, &["curl", "wget"]).await?.0.as_str() {
+            "wget" => format!("wget -q {} -O awscliv2.zip 2>&1", url),
+            _ => format!("curl {} -o awscliv2.zip 2>&1", url),
+        };
+        run(client, &cmd).await?;
 
         let cmd = "unzip -qo awscliv2.zip 2>&1";
-        run(&client, cmd).await?;
+        run(client, cmd).await?;
 
         let cmd = "sudo ./aws/install 2>&1";
-        run(&client, cmd).await?;
+        run(client, cmd).await?;
 
         let cmd = "rm -rf awscliv2.zip ./aws 2>&1";
-        run(&client, cmd).await?;
+        run(client, cmd).await?;
+        changed = true;
     }
     // set up aws profile
     if let Some(p) = &opt.profile {
         let profile = opt.rename.as_ref().unwrap_or(p);
 
-        let (access_key, secret_key) = read_aws_profile_keys(&p)?;
-        let region = read_aws_profile_region(&p)?;
-        let cmd = format!(
-            "aws configure set aws_access_key_id {} --profile {} 2>&1",
-            access_key, profile
-        );
-        run(&client, &cmd).await?;
-        let cmd = format!(
-            "aws configure set aws_secret_access_key {} --profile {} 2>&1",
-            secret_key, profile
-        );
-        run(&client, &cmd).await?;
-        let cmd = format!(
-            "aws configure set region {} --profile {} 2>&1",
-            region, profile
-        );
-        run(&client, &cmd).await?;
+        let (access_key, secret_key) = read_aws_profile_keys(p)?;
+        add_secret(&secret_key);
+        let region = read_aws_profile_region(p)?;
+        for (key, value) in [
+            ("aws_access_key_id", &access_key),
+            ("aws_secret_access_key", &secret_key),
+            ("region", &region),
+        ] {
+            let cmd = format!("aws configure get {} --profile {} 2>&1", key, profile);
+            if silent(client, &cmd).await?.output.trim() == value {
+                continue;
+            }
+            let cmd = format!(
+                "aws configure set {} {} --profile {} 2>&1",
+                key, value, profile
+            );
+            run(client, &cmd).await?;
+            changed = true;
+        }
     }
 
-    Ok(())
+    Ok(changed)
 }
 
 #[instrument(skip(client))]
@@ -153,7 +175,7 @@ pub async fn on_check(client: &Client, opt: &AwsOptions) -> anyhow::Result<Statu
         let profile = opt.rename.as_ref().unwrap_or(p);
 
         let cmd = format!("aws configure --profile {} list", profile);
-        match silent(&client, &cmd).await {
+        match silent(client, &cmd).await {
             Ok(output) => {
                 if output.exit_status == 0 {
                     success.push(format!("profile {} ok", profile));
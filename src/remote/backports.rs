@@ -0,0 +1,166 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct BackportsOptions {
+        /// suite to be enabled, i.e. bookworm-backports
+        pub suite: String,
+        /// pin priority of the suite, 100 keeps packages from being upgraded automatically
+        #[param(default = "100")]
+        pub priority: Option<i32>,
+        /// mirror of the distribution, official one is used by default
+        pub mirror: Option<String>,
+        /// components of the suite, all free components by default
+        pub components: Option<Vec<String>>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "backports",
+    description: "enable backports or another suite with apt pinning",
+    params: BackportsOptions::PARAMS,
+    sudo: true,
+};
+
+const DEFAULT_PRIORITY: i32 = 100;
+
+fn sources_path(suite: &str) -> String {
+    format!("/etc/apt/sources.list.d/{}.list", suite)
+}
+
+fn preferences_path(suite: &str) -> String {
+    format!("/etc/apt/preferences.d/{}.pref", suite)
+}
+
+fn default_mirror(os: &Os) -> anyhow::Result<&'static str> {
+    match os {
+        Os::Debian => Ok("http://deb.debian.org/debian"),
+        Os::Ubuntu => Ok("http://archive.ubuntu.com/ubuntu"),
+        _ => bail!("unsupported OS"),
+    }
+}
+
+fn default_components(os: &Os) -> &'static [&'static str] {
+    match os {
+        Os::Ubuntu => &["main", "restricted", "universe", "multiverse"],
+        _ => &["main"],
+    }
+}
+
+pub fn sources_contents(mirror: &str, suite: &str, components: &[String]) -> String {
+    format!("deb {} {} {}\n", mirror, suite, components.join(" "))
+}
+
+pub fn preferences_contents(suite: &str, priority: i32) -> String {
+    format!(
+        "Package: *\nPin: release n={}\nPin-Priority: {}\n",
+        suite, priority
+    )
+}
+
+/// expected contents of sources list and preferences files
+fn expected(os: &Os, opt: &BackportsOptions) -> anyhow::Result<(String, String)> {
+    let mirror = match &opt.mirror {
+        Some(mirror) => mirror.as_str(),
+        None => default_mirror(os)?,
+    };
+    let components = match &opt.components {
+        Some(components) => components.clone(),
+        None => default_components(os)
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+    };
+    Ok((
+        sources_contents(mirror, &opt.suite, &components),
+        preferences_contents(&opt.suite, opt.priority.unwrap_or(DEFAULT_PRIORITY)),
+    ))
+}
+
+/// enables the suite and pins it, returns true if anything was changed
+#[instrument(skip(client))]
+pub async fn apt_backports_ensure(client: &Client, opt: &BackportsOptions) -> anyhow::Result<bool> {
+    let (sources, preferences) = expected(&osinfo(client).await, opt)?;
+    let mut changed = ensure_file(client, &sources_path(&opt.suite), &sources).await?;
+    changed |= ensure_file(client, &preferences_path(&opt.suite), &preferences).await?;
+    // package lists are updated on the next run as well, if apt-get update fails
+    let pending = mark_pending(client, "backports-update", changed).await?;
+    if pending {
+        run(client, "sudo apt-get update 2>&1").await?;
+        clear_pending(client, "backports-update").await?;
+    }
+    Ok(pending)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &BackportsOptions) -> anyhow::Result<bool> {
+    let changed = apt_backports_ensure(client, opt).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &BackportsOptions) -> anyhow::Result<Status> {
+    let mut success = vec![];
+    let mut fail = vec![];
+
+    let (sources, preferences) = expected(&osinfo(client).await, opt)?;
+    for (path, contents) in [
+        (sources_path(&opt.suite), sources),
+        (preferences_path(&opt.suite), preferences),
+    ] {
+        match read_file(client, &path).await? {
+            Some(current) if current == contents => success.push(format!("{} ok", path)),
+            Some(_) => fail.push(format!("{} differs", path)),
+            None => fail.push(format!("missing {}", path)),
+        }
+    }
+    Ok(Status::new(success, fail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_generates_debian_backports() {
+        let opt = BackportsOptions {
+            suite: "bookworm-backports".to_string(),
+            ..Default::default()
+        };
+        let (sources, preferences) = expected(&Os::Debian, &opt).unwrap();
+        assert_eq!(
+            sources,
+            "deb http://deb.debian.org/debian bookworm-backports main\n"
+        );
+        assert_eq!(
+            preferences,
+            "Package: *\nPin: release n=bookworm-backports\nPin-Priority: 100\n"
+        );
+        assert_eq!(
+            sources_path(&opt.suite),
+            "/etc/apt/sources.list.d/bookworm-backports.list"
+        );
+        assert_eq!(
+            preferences_path(&opt.suite),
+            "/etc/apt/preferences.d/bookworm-backports.pref"
+        );
+    }
+
+    #[test]
+    fn it_generates_custom_suite() {
+        let opt = BackportsOptions {
+            suite: "jammy-backports".to_string(),
+            priority: Some(500),
+            mirror: Some("http://mirror.local/ubuntu".to_string()),
+            components: None,
+        };
+        let (sources, preferences) = expected(&Os::Ubuntu, &opt).unwrap();
+        assert_eq!(
+            sources,
+            "deb http://mirror.local/ubuntu jammy-backports main restricted universe multiverse\n"
+        );
+        assert!(preferences.ends_with("Pin-Priority: 500\n"));
+        assert!(expected(&Os::Unsupported, &BackportsOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct CloudInitOptions {
+        /// how long to wait for cloud-init to finish
+        #[param(default = "600")]
+        pub timeout_secs: Option<u64>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "cloud-init",
+    description: "wait for cloud-init to finish before provisioning",
+    params: CloudInitOptions::PARAMS,
+    sudo: false,
+};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 600;
+
+/// state reported by `cloud-init status`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloudInitStatus {
+    Running,
+    Done,
+    Error,
+    Disabled,
+    NotRun,
+    Other(String),
+}
+
+impl CloudInitStatus {
+    /// parses the `status:` line, progress dots of `--wait` are ignored
+    pub fn parse(output: &str) -> Option<Self> {
+        let status = output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("status:"))?
+            .trim();
+        Some(match status {
+            "running" => CloudInitStatus::Running,
+            "done" => CloudInitStatus::Done,
+            "error" | "degraded" => CloudInitStatus::Error,
+            "disabled" => CloudInitStatus::Disabled,
+            "not run" | "not started" => CloudInitStatus::NotRun,
+            other => CloudInitStatus::Other(other.to_string()),
+        })
+    }
+
+    /// cloud-init won't change the host anymore
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            CloudInitStatus::Done | CloudInitStatus::Error | CloudInitStatus::Disabled
+        )
+    }
+}
+
+/// returns None if cloud-init is not installed
+#[instrument(skip(client))]
+pub async fn cloud_init_status(client: &impl Executor) -> anyhow::Result<Option<CloudInitStatus>> {
+    let out = silent(client, "cloud-init status 2>&1").await?;
+    Ok(CloudInitStatus::parse(&out.output))
+}
+
+/// blocks until cloud-init finishes, i.e. releases the apt lock
+#[instrument(skip(client))]
+pub async fn wait_cloud_init(
+    client: &Client,
+    timeout_secs: u64,
+) -> anyhow::Result<Option<CloudInitStatus>> {
+    if cloud_init_status(client).await?.is_none() {
+        return Ok(None);
+    }
+    let cmd = format!("timeout {} cloud-init status --wait 2>&1", timeout_secs);
+    let out = silent(client, &cmd).await?;
+    if out.exit_status == 124 {
+        bail!("cloud-init didn't finish in {} seconds", timeout_secs);
+    }
+    let status = CloudInitStatus::parse(&out.output);
+    if status == Some(CloudInitStatus::Error) {
+        add_warning("cloud-init finished with errors");
+    }
+    Ok(status)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &CloudInitOptions) -> anyhow::Result<bool> {
+    let timeout = opt.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+    let status = wait_cloud_init(client, timeout).await?;
+    info!("cloud-init status: {:?}", status);
+    Ok(false)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, _opt: &CloudInitOptions) -> anyhow::Result<Status> {
+    Ok(match cloud_init_status(client).await? {
+        None => Status::new(vec!["cloud-init not installed".to_string()], vec![]),
+        Some(status) if status.is_finished() => {
+            Status::new(vec![format!("cloud-init {:?}", status)], vec![])
+        }
+        Some(status) => Status::new(vec![], vec![format!("cloud-init {:?}", status)]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_cloud_init_status() {
+        assert_eq!(
+            CloudInitStatus::parse("status: running\n"),
+            Some(CloudInitStatus::Running)
+        );
+        assert_eq!(
+            CloudInitStatus::parse("..............\nstatus: done\n"),
+            Some(CloudInitStatus::Done)
+        );
+        let error = CloudInitStatus::parse("status: error\n").unwrap();
+        assert_eq!(error, CloudInitStatus::Error);
+        assert!(error.is_finished());
+        assert!(!CloudInitStatus::Running.is_finished());
+        assert_eq!(
+            CloudInitStatus::parse("status: not run\n"),
+            Some(CloudInitStatus::NotRun)
+        );
+        assert_eq!(
+            CloudInitStatus::parse("bash: line 1: cloud-init: command not found\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::prelude::*;
+
+/// how values are written into the config file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KvFormat {
+    /// `KEY=value`, i.e. systemd configuration files
+    Plain,
+    /// `KEY="value"`, for files that are sourced by the shell, i.e. /etc/default/*
+    Shell,
+}
+
+impl KvFormat {
+    pub fn line(&self, key: &str, value: &str) -> String {
+        match self {
+            KvFormat::Plain => format!("{}={}", key, value),
+            KvFormat::Shell => format!("{}=\"{}\"", key, shell_escape_double(value)),
+        }
+    }
+}
+
+/// escape characters that have special meaning inside of double quotes
+fn shell_escape_double(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// returns the key if the line is an assignment (optionally commented out)
+fn assigned_key(line: &str) -> Option<(&str, bool)> {
+    let trimmed = line.trim_start();
+    let (commented, rest) = match trimmed.strip_prefix('#') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, trimmed),
+    };
+    let (key, _) = rest.split_once('=')?;
+    let key = key.trim();
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((key, commented))
+}
+
+/// applies settings to the config file contents:
+/// existing assignments are replaced, new ones are added after the commented
+/// out default or appended to the end of the file
+pub fn kv_apply(content: &str, settings: &Map<String, String>, format: KvFormat) -> String {
+    let mut lines: Vec<String> = content.lines().map(|x| x.to_string()).collect();
+    for (key, value) in settings {
+        let expected = format.line(key, value);
+        let mut found = false;
+        let mut commented_at = None;
+        for (index, line) in lines.iter_mut().enumerate() {
+            match assigned_key(line) {
+                Some((k, false)) if k == key => {
+                    *line = expected.clone();
+                    found = true;
+                }
+                Some((k, true)) if k == key && commented_at.is_none() => {
+                    commented_at = Some(index);
+                }
+                _ => {}
+            }
+        }
+        if !found {
+            match commented_at {
+                Some(index) => lines.insert(index + 1, expected),
+                None => lines.push(expected),
+            }
+        }
+    }
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+/// makes sure the remote config file contains the settings,
+/// returns true if the file was changed
+#[instrument(skip(client))]
+pub async fn config_kv_ensure(
+    client: &impl Executor,
+    path: &str,
+    settings: &Map<String, String>,
+    format: KvFormat,
+) -> anyhow::Result<bool> {
+    let current = read_file(client, path).await?.unwrap_or_default();
+    let expected = kv_apply(&current, settings, format);
+    if expected == current {
+        debug!("{} is up to date", path);
+        return Ok(false);
+    }
+    write_file(client, path, &expected).await?;
+    Ok(true)
+}
+
+/// returns settings that differ from the remote config file
+#[instrument(skip(client))]
+pub async fn config_kv_check(
+    client: &Client,
+    path: &str,
+    settings: &Map<String, String>,
+    format: KvFormat,
+) -> anyhow::Result<Status> {
+    let mut success = vec![];
+    let mut fail = vec![];
+    let Some(current) = read_file(client, path).await? else {
+        fail.push(format!("{} missing", path));
+        return Ok(Status::new(success, fail));
+    };
+    for (key, value) in settings {
+        let expected = format.line(key, value);
+        if current.lines().any(|line| line.trim() == expected) {
+            success.push(format!("{} ok", key));
+        } else {
+            fail.push(format!("{} is not {}", key, value));
+        }
+    }
+    Ok(Status::new(success, fail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(items: &[(&str, &str)]) -> Map<String, String> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn it_quotes_shell_values() {
+        assert_eq!(KvFormat::Shell.line("OPTS", "-a -b"), "OPTS=\"-a -b\"");
+        assert_eq!(
+            KvFormat::Shell.line("OPTS", r#"say "hi" $HOME `id` \n"#),
+            r#"OPTS="say \"hi\" \$HOME \`id\` \\n""#
+        );
+        assert_eq!(
+            KvFormat::Plain.line("SystemMaxUse", "500M"),
+            "SystemMaxUse=500M"
+        );
+    }
+
+    #[test]
+    fn it_replaces_and_appends() {
+        let content = "# options\nOPTS=\"-x\"\n#PORT=\"22\"\nOTHER=1\n";
+        let out = kv_apply(
+            content,
+            &settings(&[("OPTS", "-y"), ("PORT", "2222"), ("NEW", "1")]),
+            KvFormat::Shell,
+        );
+        assert_eq!(
+            out,
+            "# options\nOPTS=\"-y\"\n#PORT=\"22\"\nPORT=\"2222\"\nOTHER=1\nNEW=\"1\"\n"
+        );
+    }
+
+    #[test]
+    fn it_is_idempotent() {
+        let set = settings(&[("OPTS", "-y"), ("PORT", "2222")]);
+        let once = kv_apply("#PORT=\"22\"\n", &set, KvFormat::Shell);
+        let twice = kv_apply(&once, &set, KvFormat::Shell);
+        assert_eq!(once, twice);
+        assert_eq!(kv_apply("", &set, KvFormat::Plain), "OPTS=-y\nPORT=2222\n");
+    }
+}
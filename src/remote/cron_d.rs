@@ -0,0 +1,152 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct CronDOptions {
+        /// name of the file in /etc/cron.d
+        pub name: String,
+        /// cron expression, i.e. "*/5 * * * *" or "@daily", required when present
+        pub schedule: Option<String>,
+        /// user to run the command as
+        #[param(default = "root")]
+        pub user: Option<String>,
+        /// command to run, required when present
+        pub command: Option<String>,
+        /// present or absent
+        #[param(default = "present")]
+        pub state: Option<String>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "cron-d",
+    description: "manage system cron entries in /etc/cron.d",
+    params: CronDOptions::PARAMS,
+    sudo: true,
+};
+
+/// cron ignores files in /etc/cron.d with dots in the name
+pub fn cron_d_path(name: &str) -> anyhow::Result<String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(
+            "invalid cron.d name {}, only letters, digits, - and _",
+            name
+        );
+    }
+    Ok(format!("/etc/cron.d/{}", name))
+}
+
+/// contents of the cron.d file, unlike user crontabs it has the user column
+pub fn cron_d_contents(schedule: &str, user: &str, command: &str) -> anyhow::Result<String> {
+    if schedule.split_whitespace().count() != 5 && !schedule.starts_with('@') {
+        bail!("invalid cron schedule {}, expected 5 fields", schedule);
+    }
+    if command.contains('\n') {
+        bail!("cron command must be a single line");
+    }
+    Ok(format!("{} {} {}\n", schedule, user, command))
+}
+
+fn expected(opt: &CronDOptions) -> anyhow::Result<String> {
+    let schedule = opt.schedule.as_deref().context("schedule is required")?;
+    let command = opt.command.as_deref().context("command is required")?;
+    cron_d_contents(schedule, opt.user.as_deref().unwrap_or("root"), command)
+}
+
+/// writes or removes /etc/cron.d/<name>, returns true if it was changed
+#[instrument(skip(client))]
+pub async fn cron_d_ensure(
+    client: &Client,
+    name: &str,
+    schedule: &str,
+    user: &str,
+    command: &str,
+    state: Option<&str>,
+) -> anyhow::Result<bool> {
+    let path = cron_d_path(name)?;
+    if !parse_state(state)? {
+        if !file_exists(client, &path).await {
+            return Ok(false);
+        }
+        run(client, &format!("sudo rm -f {} 2>&1", shell_quote(&path))).await?;
+        return Ok(true);
+    }
+    ensure_file(client, &path, &cron_d_contents(schedule, user, command)?).await
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &CronDOptions) -> anyhow::Result<bool> {
+    if parse_state(opt.state.as_deref())? {
+        // schedule and command are required
+        expected(opt)?;
+    }
+    let changed = cron_d_ensure(
+        client,
+        &opt.name,
+        opt.schedule.as_deref().unwrap_or_default(),
+        opt.user.as_deref().unwrap_or("root"),
+        opt.command.as_deref().unwrap_or_default(),
+        opt.state.as_deref(),
+    )
+    .await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &CronDOptions) -> anyhow::Result<Status> {
+    let path = cron_d_path(&opt.name)?;
+    let current = read_file(client, &path).await?;
+    Ok(match (current, parse_state(opt.state.as_deref())?) {
+        (Some(contents), true) if contents == expected(opt)? => {
+            Status::new(vec![format!("{} ok", path)], vec![])
+        }
+        (Some(_), true) => Status::new(vec![], vec![format!("{} differs", path)]),
+        (None, true) => Status::new(vec![], vec![format!("{} missing", path)]),
+        (None, false) => Status::new(vec![format!("{} absent", path)], vec![]),
+        (Some(_), false) => Status::new(vec![], vec![format!("{} still exists", path)]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_formats_cron_d_file() {
+        assert_eq!(
+            cron_d_contents("*/5 * * * *", "www-data", "php /var/www/cron.php").unwrap(),
+            "*/5 * * * * www-data php /var/www/cron.php\n"
+        );
+        assert_eq!(
+            cron_d_contents("@daily", "root", "/usr/local/bin/backup").unwrap(),
+            "@daily root /usr/local/bin/backup\n"
+        );
+        assert!(cron_d_contents("daily", "root", "true").is_err());
+        assert!(cron_d_contents("@daily", "root", "true\nfalse").is_err());
+        assert_eq!(cron_d_path("backup").unwrap(), "/etc/cron.d/backup");
+        assert!(cron_d_path("backup.sh").is_err());
+    }
+
+    #[test]
+    fn it_is_idempotent() {
+        let opt: CronDOptions = toml::from_str(
+            r#"
+name = "backup"
+schedule = "0 3 * * *"
+command = "/usr/local/bin/backup"
+"#,
+        )
+        .unwrap();
+        let contents = expected(&opt).unwrap();
+        assert_eq!(contents, "0 3 * * * root /usr/local/bin/backup\n");
+        assert_eq!(expected(&opt).unwrap(), contents);
+        let opt: CronDOptions = toml::from_str("name = \"backup\"\nstate = \"absent\"").unwrap();
+        assert!(expected(&opt).is_err());
+        assert!(!parse_state(opt.state.as_deref()).unwrap());
+    }
+}
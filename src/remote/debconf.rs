@@ -0,0 +1,202 @@
+use crate::prelude::*;
+use crate::remote::{ModuleInfo, ParamKind};
+use base64::{engine::general_purpose, Engine as _};
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct DebconfOptions {
+        /// answers to be preset before the packages are installed
+        pub set: Vec<DebconfSelection>,
+    }
+}
+
+#[derive(Default, Deserialize)]
+pub struct DebconfSelection {
+    /// package owning the question, i.e. iptables-persistent
+    pub package: String,
+    /// i.e. iptables-persistent/autosave_v4
+    pub question: String,
+    /// boolean, string, select, password and other debconf types
+    pub vtype: String,
+    pub value: String,
+}
+
+// the selection ends up in tracing spans of the module, passwords must not
+impl std::fmt::Debug for DebconfSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = if self.vtype == "password" {
+            "<redacted>"
+        } else {
+            self.value.as_str()
+        };
+        f.debug_struct("DebconfSelection")
+            .field("package", &self.package)
+            .field("question", &self.question)
+            .field("vtype", &self.vtype)
+            .field("value", &value)
+            .finish()
+    }
+}
+
+impl ParamKind for Vec<DebconfSelection> {
+    const KIND: &'static str = "list of tables";
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "debconf",
+    description: "preset debconf answers so packages install without prompts",
+    params: DebconfOptions::PARAMS,
+    sudo: true,
+};
+
+/// line of `debconf-set-selections` input
+pub fn selection_line(package: &str, question: &str, vtype: &str, value: &str) -> String {
+    format!("{} {} {} {}\n", package, question, vtype, value)
+}
+
+/// parses the reply of debconf-communicate to `GET question`, i.e. "0 true"
+pub fn parse_get(output: &str) -> Option<String> {
+    let line = output.lines().next()?.trim();
+    match line.split_once(' ') {
+        Some(("0", value)) => Some(value.to_string()),
+        None if line == "0" => Some(String::new()),
+        _ => None,
+    }
+}
+
+async fn current_value(
+    client: &impl Executor,
+    package: &str,
+    question: &str,
+) -> anyhow::Result<Option<String>> {
+    let cmd = format!(
+        "echo {} | sudo debconf-communicate {} 2>&1",
+        shell_quote(&format!("GET {}", question)),
+        shell_quote(package)
+    );
+    Ok(parse_get(&silent(client, &cmd).await?.output))
+}
+
+/// presets the answer unless it is set already, returns true if it was changed
+#[instrument(skip(client, value))]
+pub async fn debconf_ensure(
+    client: &impl Executor,
+    package: &str,
+    question: &str,
+    vtype: &str,
+    value: &str,
+) -> anyhow::Result<bool> {
+    let line = selection_line(package, question, vtype, value);
+    if vtype == "password" {
+        // the upload still carries the line base64-encoded
+        add_secret(value);
+        add_secret(&general_purpose::STANDARD.encode(line.as_bytes()));
+    }
+    if current_value(client, package, question).await?.as_deref() == Some(value) {
+        return Ok(false);
+    }
+    // mktemp creates the file readable only by the SSH user,
+    // the selection is not a part of the debconf-set-selections command line
+    let tmp = upload_temp(client, "mktemp", &line, false).await?;
+    let cmd = format!("sudo debconf-set-selections < {} 2>&1", shell_quote(&tmp));
+    let set = run(client, &cmd).await;
+    remove_temp(client, &tmp, false).await?;
+    set?;
+    Ok(true)
+}
+
+#[instrument(skip(client, opt))]
+pub async fn on_install(client: &Client, opt: &DebconfOptions) -> anyhow::Result<bool> {
+    let mut changed = false;
+    for s in &opt.set {
+        changed |= debconf_ensure(client, &s.package, &s.question, &s.vtype, &s.value).await?;
+    }
+    Ok(changed)
+}
+
+#[instrument(skip(client, opt))]
+pub async fn on_check(client: &Client, opt: &DebconfOptions) -> anyhow::Result<Status> {
+    let mut success = vec![];
+    let mut fail = vec![];
+    for s in &opt.set {
+        match current_value(client, &s.package, &s.question).await? {
+            Some(value) if value == s.value => success.push(format!("{} set", s.question)),
+            Some(_) => fail.push(format!("{} differs", s.question)),
+            None => fail.push(format!("{} missing", s.question)),
+        }
+    }
+    Ok(Status::new(success, fail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_selection_line() {
+        assert_eq!(
+            selection_line(
+                "iptables-persistent",
+                "iptables-persistent/autosave_v4",
+                "boolean",
+                "true"
+            ),
+            "iptables-persistent iptables-persistent/autosave_v4 boolean true\n"
+        );
+        assert_eq!(parse_get("0 true\n"), Some("true".to_string()));
+        assert_eq!(parse_get("0\n"), Some(String::new()));
+        assert_eq!(parse_get("10 mysql/root doesn't exist\n"), None);
+    }
+
+    #[tokio::test]
+    async fn it_sets_selection_from_temporary_file() {
+        let host = ScriptedHost::new(vec![("debconf-communicate", 0, "10 doesn't exist\n")]);
+        let changed = debconf_ensure(
+            &host,
+            "mysql-server",
+            "mysql-server/root_password",
+            "password",
+            "s3cr'et",
+        )
+        .await
+        .unwrap();
+        assert!(changed);
+        let executed = host.executed();
+        assert!(!executed.iter().any(|c| c.contains("s3cr")));
+        let line = selection_line(
+            "mysql-server",
+            "mysql-server/root_password",
+            "password",
+            "s3cr'et",
+        );
+        let encoded = general_purpose::STANDARD.encode(line.as_bytes());
+        let upload = executed.iter().position(|c| c.contains(&encoded)).unwrap();
+        assert!(executed[upload].contains("tee -a '/tmp/tmp.scripted'"));
+        assert_eq!(
+            executed[upload + 1],
+            "sudo debconf-set-selections < '/tmp/tmp.scripted' 2>&1"
+        );
+        assert_eq!(executed[upload + 2], "rm -f '/tmp/tmp.scripted'");
+
+        let host = ScriptedHost::new(vec![("debconf-communicate", 0, "0 s3cr'et\n")]);
+        let changed = debconf_ensure(
+            &host,
+            "mysql-server",
+            "mysql-server/root_password",
+            "password",
+            "s3cr'et",
+        )
+        .await
+        .unwrap();
+        assert!(!changed);
+        assert!(!host.executed().iter().any(|c| c.contains("mktemp")));
+
+        let selection = DebconfSelection {
+            package: "mysql-server".to_string(),
+            question: "mysql-server/root_password".to_string(),
+            vtype: "password".to_string(),
+            value: "s3cr'et".to_string(),
+        };
+        assert!(!format!("{:?}", selection).contains("s3cr"));
+    }
+}
@@ -0,0 +1,358 @@
+use crate::prelude::*;
+use crate::remote::config_kv::{config_kv_check, config_kv_ensure, KvFormat};
+use crate::remote::netplan::{self, netplan_ensure, to_yaml};
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct DnsOptions {
+        /// addresses of the name servers
+        pub nameservers: Vec<String>,
+        /// search domains
+        pub search: Option<Vec<String>>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "dns",
+    description: "configure name servers with resolved, netplan or /etc/resolv.conf",
+    params: DnsOptions::PARAMS,
+    sudo: true,
+};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const RESOLVED_CONF: &str = "/etc/systemd/resolved.conf";
+const NETPLAN_DNS: &str = "99-remote-playbook-dns";
+
+/// device types of netplan configuration that can hold name servers
+const NETPLAN_DEVICE_TYPES: [&str; 5] = ["ethernets", "bonds", "bridges", "vlans", "wifis"];
+
+/// device of the default route in the netplan configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetplanDevice {
+    /// ethernets, bonds, bridges, vlans or wifis
+    pub device_type: &'static str,
+    pub interface: String,
+}
+
+/// how the name servers are managed on the host
+#[derive(Debug, PartialEq)]
+pub enum DnsMethod {
+    /// systemd-resolved owns /etc/resolv.conf
+    Resolved,
+    /// netplan configures the device of the default route,
+    /// its name servers are passed to systemd-resolved
+    Netplan(NetplanDevice),
+    /// /etc/resolv.conf is a regular file
+    Plain,
+}
+
+impl DnsMethod {
+    /// `resolv_link` is the target of /etc/resolv.conf if it is a symlink,
+    /// `netplan` is the device of the default route if netplan configures it.
+    /// The name servers of netplan go to resolved, so netplan is preferred
+    /// over resolved.conf, which would be overridden per link
+    pub fn detect(
+        resolv_link: Option<&str>,
+        resolved_active: bool,
+        netplan: Option<NetplanDevice>,
+    ) -> anyhow::Result<Self> {
+        match resolv_link {
+            None => Ok(DnsMethod::Plain),
+            Some(link) if link.contains("/run/systemd/resolve/") && resolved_active => {
+                Ok(match netplan {
+                    Some(device) => DnsMethod::Netplan(device),
+                    None => DnsMethod::Resolved,
+                })
+            }
+            Some(link) => bail!(
+                "can't determine how DNS is managed, {} links to {}",
+                RESOLV_CONF,
+                link
+            ),
+        }
+    }
+}
+
+/// finds the device type that configures the interface in netplan files,
+/// i.e. ethernets or bonds
+pub fn netplan_device_type(yaml: &str, interface: &str) -> Option<&'static str> {
+    let mut device_type = None;
+    let mut type_indent = 0;
+    let mut device_indent = None;
+    for line in yaml.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        let key = trimmed
+            .split_once(':')
+            .map(|(key, _)| key.trim().trim_matches(|c| c == '"' || c == '\''));
+        if let Some(found) = NETPLAN_DEVICE_TYPES.iter().find(|t| key == Some(**t)) {
+            device_type = Some(*found);
+            type_indent = indent;
+            device_indent = None;
+            continue;
+        }
+        if indent <= type_indent {
+            device_type = None;
+            continue;
+        }
+        let Some(found) = device_type else {
+            continue;
+        };
+        // devices are the first level below the type
+        if *device_indent.get_or_insert(indent) == indent && key == Some(interface) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+pub fn resolv_conf(nameservers: &[String], search: &[String]) -> String {
+    let mut out = "# managed by remote-playbook\n".to_string();
+    if !search.is_empty() {
+        out.push_str(&format!("search {}\n", search.join(" ")));
+    }
+    for ns in nameservers {
+        out.push_str(&format!("nameserver {}\n", ns));
+    }
+    out
+}
+
+pub fn resolved_settings(nameservers: &[String], search: &[String]) -> Map<String, String> {
+    let mut settings = Map::new();
+    settings.insert("DNS".to_string(), nameservers.join(" "));
+    if !search.is_empty() {
+        settings.insert("Domains".to_string(), search.join(" "));
+    }
+    settings
+}
+
+fn string_list(items: &[String]) -> toml::Value {
+    toml::Value::Array(items.iter().cloned().map(toml::Value::String).collect())
+}
+
+/// netplan configuration with the name servers of the device
+pub fn netplan_config(
+    device: &NetplanDevice,
+    nameservers: &[String],
+    search: &[String],
+) -> Map<String, toml::Value> {
+    let mut dns = toml::map::Map::new();
+    dns.insert("addresses".to_string(), string_list(nameservers));
+    if !search.is_empty() {
+        dns.insert("search".to_string(), string_list(search));
+    }
+    let mut settings = toml::map::Map::new();
+    settings.insert("nameservers".to_string(), toml::Value::Table(dns));
+    let mut devices = toml::map::Map::new();
+    devices.insert(device.interface.clone(), toml::Value::Table(settings));
+    let mut network = toml::map::Map::new();
+    network.insert("version".to_string(), toml::Value::Integer(2));
+    network.insert(device.device_type.to_string(), toml::Value::Table(devices));
+    let mut config = Map::new();
+    config.insert("network".to_string(), toml::Value::Table(network));
+    config
+}
+
+/// parses the interface from `ip -o route show default`
+pub fn default_interface(output: &str) -> Option<&str> {
+    let mut columns = output.split_whitespace();
+    columns.find(|x| *x == "dev")?;
+    columns.next()
+}
+
+/// the device of the default route, if netplan configures it
+async fn netplan_device(client: &Client) -> anyhow::Result<Option<NetplanDevice>> {
+    let route = silent(client, "ip -o route show default 2>&1").await?;
+    let Some(interface) = default_interface(&route.output) else {
+        return Ok(None);
+    };
+    let files = silent(client, "sudo cat /etc/netplan/*.yaml 2>/dev/null").await?;
+    if files.exit_status != 0 {
+        return Ok(None);
+    }
+    Ok(
+        netplan_device_type(&files.output, interface).map(|device_type| NetplanDevice {
+            device_type,
+            interface: interface.to_string(),
+        }),
+    )
+}
+
+#[instrument(skip(client))]
+pub async fn dns_method(client: &Client) -> anyhow::Result<DnsMethod> {
+    let link = silent(client, &format!("readlink -f {}", RESOLV_CONF)).await?;
+    let link = link.output.trim();
+    let is_link = silent(client, &format!("test -L {}", RESOLV_CONF)).await?;
+    let resolved = silent(client, "systemctl is-active systemd-resolved").await?;
+    DnsMethod::detect(
+        (is_link.exit_status == 0).then_some(link),
+        resolved.exit_status == 0,
+        netplan_device(client).await?,
+    )
+}
+
+/// configures the name servers, returns true if anything was changed
+#[instrument(skip(client))]
+pub async fn dns_ensure(
+    client: &Client,
+    nameservers: &[String],
+    search: &[String],
+) -> anyhow::Result<bool> {
+    let method = dns_method(client).await?;
+    info!("DNS is managed by {:?}", method);
+    match method {
+        DnsMethod::Resolved => {
+            let settings = resolved_settings(nameservers, search);
+            let changed =
+                config_kv_ensure(client, RESOLVED_CONF, &settings, KvFormat::Plain).await?;
+            // the restart is retried on the next run if it fails
+            let pending = mark_pending(client, "resolved-restart", changed).await?;
+            if pending {
+                run(client, "sudo systemctl restart systemd-resolved 2>&1").await?;
+                clear_pending(client, "resolved-restart").await?;
+            }
+            Ok(pending)
+        }
+        DnsMethod::Netplan(device) => {
+            // validated with netplan generate and rolled back like the netplan module
+            let config = netplan_config(&device, nameservers, search);
+            netplan_ensure(client, NETPLAN_DNS, &config, true).await
+        }
+        DnsMethod::Plain => {
+            ensure_file(client, RESOLV_CONF, &resolv_conf(nameservers, search)).await
+        }
+    }
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &DnsOptions) -> anyhow::Result<bool> {
+    let search = opt.search.clone().unwrap_or_default();
+    let changed = dns_ensure(client, &opt.nameservers, &search).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &DnsOptions) -> anyhow::Result<Status> {
+    let search = opt.search.clone().unwrap_or_default();
+    let (path, expected) = match dns_method(client).await? {
+        DnsMethod::Resolved => {
+            let settings = resolved_settings(&opt.nameservers, &search);
+            return config_kv_check(client, RESOLVED_CONF, &settings, KvFormat::Plain).await;
+        }
+        DnsMethod::Netplan(device) => (
+            netplan::path(NETPLAN_DNS),
+            to_yaml(&netplan_config(&device, &opt.nameservers, &search)),
+        ),
+        DnsMethod::Plain => (
+            RESOLV_CONF.to_string(),
+            resolv_conf(&opt.nameservers, &search),
+        ),
+    };
+    if read_file(client, &path).await?.as_deref() == Some(expected.as_str()) {
+        Ok(Status::new(vec![format!("{} ok", path)], vec![]))
+    } else {
+        Ok(Status::new(vec![], vec![format!("{} differs", path)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn bond0() -> NetplanDevice {
+        NetplanDevice {
+            device_type: "bonds",
+            interface: "bond0".to_string(),
+        }
+    }
+
+    #[test]
+    fn it_detects_dns_method() {
+        let stub = Some("/run/systemd/resolve/stub-resolv.conf");
+        assert_eq!(
+            DnsMethod::detect(stub, true, Some(bond0())).unwrap(),
+            DnsMethod::Netplan(bond0())
+        );
+        assert_eq!(
+            DnsMethod::detect(stub, true, None).unwrap(),
+            DnsMethod::Resolved
+        );
+        assert_eq!(
+            DnsMethod::detect(None, true, Some(bond0())).unwrap(),
+            DnsMethod::Plain
+        );
+        let err = DnsMethod::detect(Some("/run/resolvconf/resolv.conf"), false, None);
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "can't determine how DNS is managed, /etc/resolv.conf links to /run/resolvconf/resolv.conf"
+        );
+        assert!(DnsMethod::detect(stub, false, Some(bond0())).is_err());
+    }
+
+    #[test]
+    fn it_finds_the_netplan_device_type() {
+        let yaml = r#"# written by cloud-init
+network:
+  version: 2
+  ethernets:
+    eno1: {}
+    eno2:
+      dhcp4: false
+  bonds:
+    "bond0":
+      interfaces: [eno1, eno2]
+      parameters:
+        eno1: ignored
+network:
+  wifis:
+    wlan0:
+      access-points: {}
+"#;
+        assert_eq!(netplan_device_type(yaml, "bond0"), Some("bonds"));
+        assert_eq!(netplan_device_type(yaml, "eno2"), Some("ethernets"));
+        assert_eq!(netplan_device_type(yaml, "wlan0"), Some("wifis"));
+        assert_eq!(netplan_device_type(yaml, "dhcp4"), None);
+        assert_eq!(netplan_device_type(yaml, "eth0"), None);
+    }
+
+    #[test]
+    fn it_renders_dns_config() {
+        let ns = strings(&["1.1.1.1", "8.8.8.8"]);
+        let search = strings(&["corp.example.com"]);
+        assert_eq!(
+            resolv_conf(&ns, &search),
+            "# managed by remote-playbook\nsearch corp.example.com\nnameserver 1.1.1.1\nnameserver 8.8.8.8\n"
+        );
+        let settings = resolved_settings(&ns, &[]);
+        assert_eq!(settings["DNS"], "1.1.1.1 8.8.8.8");
+        assert!(!settings.contains_key("Domains"));
+        assert_eq!(
+            to_yaml(&netplan_config(&bond0(), &ns, &search)),
+            r#""network":
+  "bonds":
+    "bond0":
+      "nameservers":
+        "addresses":
+          - "1.1.1.1"
+          - "8.8.8.8"
+        "search":
+          - "corp.example.com"
+  "version": 2
+"#
+        );
+        assert!(!to_yaml(&netplan_config(&bond0(), &ns, &[])).contains("search"));
+        assert_eq!(
+            default_interface("default via 10.0.0.1 dev ens3 proto dhcp src 10.0.0.5 metric 100"),
+            Some("ens3")
+        );
+        assert_eq!(default_interface(""), None);
+    }
+}
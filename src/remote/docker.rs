@@ -1,10 +1,21 @@
 use crate::prelude::*;
+use crate::remote::ModuleInfo;
 
-#[derive(Debug, Default, Deserialize)]
-pub struct DockerOptions {
-    pub path: Option<String>,
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct DockerOptions {
+        /// data root folder, docker data is stored in <path>/docker
+        pub path: Option<String>,
+    }
 }
 
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "docker",
+    description: "install docker engine from the official repository",
+    params: DockerOptions::PARAMS,
+    sudo: true,
+};
+
 #[derive(Serialize)]
 pub struct DockerConfig {
     #[serde(rename = "data-root")]
@@ -27,12 +38,13 @@ const SOURCES_LIST_PATH: &str = "/etc/apt/sources.list.d/docker.list";
 const DAEMON_CONFIG_PATH: &str = "/etc/docker/daemon.json";
 
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, opt: &DockerOptions) -> anyhow::Result<()> {
+pub async fn on_install(client: &Client, opt: &DockerOptions) -> anyhow::Result<bool> {
     let os_str = match osinfo(client).await {
         Os::Debian => "debian",
         Os::Ubuntu => "ubuntu",
         _ => bail!("unsupported OS"),
     };
+    let mut changed = false;
 
     if let Some(path) = &opt.path {
         if file_exists(client, DAEMON_CONFIG_PATH).await {
@@ -40,14 +52,11 @@ pub async fn on_install(client: &Client, opt: &DockerOptions) -> anyhow::Result<
         } else {
             // if not, overwrite it
             let cmd1 = "sudo mkdir -p /etc/docker";
-            run(&client, &cmd1).await?;
-
-            let cmd = format!(
-                "sudo bash -c 'echo {:?} > {}'",
-                serde_json::to_string(&DockerConfig::new(&path))?,
-                DAEMON_CONFIG_PATH,
-            );
-            run(client, &cmd).await?;
+            run(client, cmd1).await?;
+
+            let contents = serde_json::to_string(&DockerConfig::new(path))?;
+            write_file(client, DAEMON_CONFIG_PATH, &contents).await?;
+            changed = true;
         }
     }
 
@@ -58,6 +67,7 @@ pub async fn on_install(client: &Client, opt: &DockerOptions) -> anyhow::Result<
         let origin = format!("https://download.docker.com/linux/{}/gpg", os_str);
         let cmd = format!("curl -fsSL {} | sudo gpg --dearmor -o {}", origin, GPG_PATH);
         run(client, &cmd).await?;
+        changed = true;
         // chmod a+r /etc/apt/keyrings/docker.gpg ?
     }
 
@@ -69,6 +79,7 @@ pub async fn on_install(client: &Client, opt: &DockerOptions) -> anyhow::Result<
             GPG_PATH, os_str, SOURCES_LIST_PATH
         );
         run(client, &cmd).await?;
+        changed = true;
     }
 
     run(client, "export DEBIAN_FRONTEND=noninteractive").await?;
@@ -84,9 +95,10 @@ pub async fn on_install(client: &Client, opt: &DockerOptions) -> anyhow::Result<
     if !packages.is_empty() {
         let cmd = format!("sudo apt-get install -y {} 2>&1", packages.join(" "));
         run(client, &cmd).await?;
+        changed = true;
     }
     silent(client, "sudo usermod -aG docker $USER 2>&1").await?;
-    Ok(())
+    Ok(changed)
 }
 
 #[instrument(skip(client))]
@@ -1,13 +1,24 @@
 use crate::prelude::*;
+use crate::remote::ModuleInfo;
 
-#[derive(Debug, Default, Deserialize)]
-pub struct DockerStatsOptions {}
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct DockerStatsOptions {}
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "docker-stats",
+    description: "run docker stats exporter container",
+    params: DockerStatsOptions::PARAMS,
+    sudo: false,
+};
 
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, opt: &DockerStatsOptions) -> anyhow::Result<()> {
+pub async fn on_install(client: &Client, opt: &DockerStatsOptions) -> anyhow::Result<bool> {
     let cmd = "docker run -d --name=docker-stats --restart=always -p 9487:9487 -v /var/run/docker.sock:/var/run/docker.sock wywywywy/docker_stats_exporter:latest";
-    client.execute(cmd).await?;
-    Ok(())
+    // fails when the container already exists
+    let out = client.execute(cmd).await?;
+    Ok(out.exit_status == 0)
 }
 
 // return types: ready for install, installed
@@ -0,0 +1,98 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct DpkgArchitectureOptions {
+        /// foreign architectures, i.e. i386 or arm64
+        pub arch: Vec<String>,
+        /// present or absent
+        #[param(default = "present")]
+        pub state: Option<String>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "dpkg-architecture",
+    description: "add or remove foreign dpkg architectures",
+    params: DpkgArchitectureOptions::PARAMS,
+    sudo: true,
+};
+
+/// parses `dpkg --print-foreign-architectures` output
+pub fn parse_foreign_architectures(output: &str) -> Vec<String> {
+    output.split_whitespace().map(|x| x.to_string()).collect()
+}
+
+async fn foreign_architectures(client: &Client) -> anyhow::Result<Vec<String>> {
+    let out = run(client, "dpkg --print-foreign-architectures 2>&1").await?;
+    Ok(parse_foreign_architectures(&out.output))
+}
+
+/// adds or removes the foreign architecture, returns true if it was changed
+#[instrument(skip(client))]
+pub async fn dpkg_add_architecture(
+    client: &Client,
+    arch: &str,
+    state: Option<&str>,
+) -> anyhow::Result<bool> {
+    let present = parse_state(state)?;
+    let current = foreign_architectures(client).await?;
+    if current.iter().any(|x| x == arch) == present {
+        return Ok(false);
+    }
+    let action = if present { "add" } else { "remove" };
+    let cmd = format!(
+        "sudo dpkg --{}-architecture {} 2>&1",
+        action,
+        shell_quote(arch)
+    );
+    run(client, &cmd).await?;
+    Ok(true)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &DpkgArchitectureOptions) -> anyhow::Result<bool> {
+    let mut changed = false;
+    for arch in &opt.arch {
+        changed |= dpkg_add_architecture(client, arch, opt.state.as_deref()).await?;
+    }
+    // package lists are updated on the next run as well, if apt-get update fails
+    let pending = mark_pending(client, "dpkg-architecture-update", changed).await?;
+    if pending {
+        run(client, "sudo apt-get update 2>&1").await?;
+        clear_pending(client, "dpkg-architecture-update").await?;
+    }
+    Ok(pending)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &DpkgArchitectureOptions) -> anyhow::Result<Status> {
+    let present = parse_state(opt.state.as_deref())?;
+    let current = foreign_architectures(client).await?;
+    let mut success = vec![];
+    let mut fail = vec![];
+    for arch in &opt.arch {
+        match (current.contains(arch), present) {
+            (true, true) => success.push(format!("{} added", arch)),
+            (false, false) => success.push(format!("{} absent", arch)),
+            (false, true) => fail.push(format!("{} missing", arch)),
+            (true, false) => fail.push(format!("{} still added", arch)),
+        }
+    }
+    Ok(Status::new(success, fail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_foreign_architectures() {
+        assert_eq!(
+            parse_foreign_architectures("i386\narm64\n"),
+            vec!["i386", "arm64"]
+        );
+        assert!(parse_foreign_architectures("").is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct DpkgSelectionsOptions {
+        /// desired state by the package name: install, hold, deinstall or purge
+        pub selections: Map<String, String>,
+        /// local file to save current selections into, after they were applied
+        pub export: Option<String>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "dpkg-selections",
+    description: "reconcile package states with dpkg --set-selections",
+    params: DpkgSelectionsOptions::PARAMS,
+    sudo: true,
+};
+
+const STATES: &[&str] = &["install", "hold", "deinstall", "purge"];
+
+/// parses `dpkg --get-selections` output into the state by the package name
+pub fn parse_selections(output: &str) -> Map<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            match (columns.next(), columns.next()) {
+                (Some(package), Some(state)) => Some((package.to_string(), state.to_string())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// selections which differ from the current ones,
+/// removing a package that is not known to dpkg is not a change
+pub fn selections_diff(
+    current: &Map<String, String>,
+    desired: &Map<String, String>,
+) -> anyhow::Result<Map<String, String>> {
+    let mut diff = Map::new();
+    for (package, state) in desired {
+        if !STATES.contains(&state.as_str()) {
+            bail!(
+                "invalid state {} of {}, expected {}",
+                state,
+                package,
+                STATES.join(", ")
+            );
+        }
+        let removed = state == "deinstall" || state == "purge";
+        match current.get(package) {
+            Some(x) if x == state => {}
+            None if removed => {}
+            _ => {
+                diff.insert(package.clone(), state.clone());
+            }
+        }
+    }
+    Ok(diff)
+}
+
+/// input of `dpkg --set-selections`
+pub fn format_selections(selections: &Map<String, String>) -> String {
+    selections
+        .iter()
+        .map(|(package, state)| format!("{} {}\n", package, state))
+        .collect()
+}
+
+/// dpkg --set-selections ignores packages missing from its available database,
+/// which apt doesn't keep up to date, so it is merged from the apt cache first
+const MERGE_AVAIL_CMD: &str = "tmp=$(mktemp) && apt-cache dumpavail > \"$tmp\" && sudo dpkg --merge-avail \"$tmp\" 2>&1; rc=$?; rm -f \"$tmp\"; exit $rc";
+
+/// packages of the diff that dpkg doesn't know yet
+pub fn unknown_packages<'a>(
+    current: &Map<String, String>,
+    diff: &'a Map<String, String>,
+) -> Vec<&'a str> {
+    diff.keys()
+        .filter(|package| !current.contains_key(*package))
+        .map(|package| package.as_str())
+        .collect()
+}
+
+async fn get_selections(client: &Client) -> anyhow::Result<String> {
+    Ok(run(client, "dpkg --get-selections 2>&1").await?.output)
+}
+
+/// applies package states and runs dselect-upgrade, returns true if they were changed
+#[instrument(skip(client))]
+pub async fn dpkg_selections(
+    client: &Client,
+    selections: &Map<String, String>,
+) -> anyhow::Result<bool> {
+    let current = parse_selections(&get_selections(client).await?);
+    let diff = selections_diff(&current, selections)?;
+    if diff.is_empty() {
+        return Ok(false);
+    }
+    if !unknown_packages(&current, &diff).is_empty() {
+        run(client, MERGE_AVAIL_CMD).await?;
+    }
+    let tmp = upload_temp(client, "mktemp", &format_selections(&diff), false).await?;
+    let cmd = format!("sudo dpkg --set-selections < {} 2>&1", shell_quote(&tmp));
+    let set = run(client, &cmd).await;
+    remove_temp(client, &tmp, false).await?;
+    set?;
+    // set-selections only warns about the packages it skipped
+    let applied = parse_selections(&get_selections(client).await?);
+    let ignored = selections_diff(&applied, &diff)?;
+    if !ignored.is_empty() {
+        bail!(
+            "dpkg ignored selections of: {}",
+            ignored.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
+    let cmd = "sudo DEBIAN_FRONTEND=noninteractive apt-get dselect-upgrade -yq 2>&1";
+    run(client, cmd).await?;
+    Ok(true)
+}
+
+/// saves current selections into the local file, returns true if it was written
+#[instrument(skip(client))]
+pub async fn export_selections(client: &Client, path: &str) -> anyhow::Result<bool> {
+    let path = crate::connect::tilde_with_context(path, dirs::home_dir);
+    let output = get_selections(client).await?;
+    if std::fs::read_to_string(&path).ok().as_deref() == Some(output.as_str()) {
+        return Ok(false);
+    }
+    std::fs::write(&path, output).with_context(|| path.to_string())?;
+    Ok(true)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &DpkgSelectionsOptions) -> anyhow::Result<bool> {
+    let mut changed = dpkg_selections(client, &opt.selections).await?;
+    if let Some(path) = &opt.export {
+        changed |= export_selections(client, path).await?;
+    }
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &DpkgSelectionsOptions) -> anyhow::Result<Status> {
+    let current = parse_selections(&get_selections(client).await?);
+    let diff = selections_diff(&current, &opt.selections)?;
+    let mut success = vec![];
+    let mut fail = vec![];
+    for (package, state) in &opt.selections {
+        match diff.get(package) {
+            None => success.push(format!("{} {}", package, state)),
+            Some(_) => fail.push(format!(
+                "{} is {}, expected {}",
+                package,
+                current
+                    .get(package)
+                    .map(|x| x.as_str())
+                    .unwrap_or("unknown"),
+                state
+            )),
+        }
+    }
+    Ok(Status::new(success, fail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selections(items: &[(&str, &str)]) -> Map<String, String> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn it_parses_selections() {
+        let output =
+            "adduser\t\t\t\t\tinstall\nlinux-image-amd64\t\t\t\thold\nnano\t\t\t\t\t\tdeinstall\n";
+        assert_eq!(
+            parse_selections(output),
+            selections(&[
+                ("adduser", "install"),
+                ("linux-image-amd64", "hold"),
+                ("nano", "deinstall")
+            ])
+        );
+    }
+
+    #[test]
+    fn it_computes_selections_diff() {
+        let current = selections(&[
+            ("adduser", "install"),
+            ("linux-image-amd64", "install"),
+            ("nano", "deinstall"),
+        ]);
+        let desired = selections(&[
+            ("adduser", "install"),
+            ("linux-image-amd64", "hold"),
+            ("nano", "purge"),
+            ("htop", "install"),
+            ("emacs", "purge"),
+        ]);
+        assert_eq!(
+            selections_diff(&current, &desired).unwrap(),
+            selections(&[
+                ("htop", "install"),
+                ("linux-image-amd64", "hold"),
+                ("nano", "purge")
+            ])
+        );
+        assert!(selections_diff(&current, &selections(&[("nano", "gone")])).is_err());
+        assert_eq!(
+            format_selections(&selections(&[("htop", "install"), ("nano", "purge")])),
+            "htop install\nnano purge\n"
+        );
+        let diff = selections_diff(&current, &desired).unwrap();
+        assert_eq!(unknown_packages(&current, &diff), vec!["htop"]);
+    }
+}
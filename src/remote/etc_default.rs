@@ -0,0 +1,123 @@
+use crate::prelude::*;
+use crate::remote::config_kv::{config_kv_check, config_kv_ensure, KvFormat};
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct EtcDefaultOptions {
+        /// name of the service, the file is /etc/default/<service>
+        pub service: String,
+        /// variables to be set in the file
+        pub set: Map<String, String>,
+        /// restart the service if the file was changed
+        #[param(default = "true")]
+        pub restart: Option<bool>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "etc-default",
+    description: "set service options in /etc/default/<service>",
+    params: EtcDefaultOptions::PARAMS,
+    sudo: true,
+};
+
+/// the name of the service is a part of the path and of the restart command,
+/// so only letters, digits, `_` and `-` are accepted
+fn path(service: &str) -> anyhow::Result<String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if service.is_empty() || !service.chars().all(valid) {
+        bail!(
+            "invalid service name {:?}, only letters, digits, _ and - are allowed",
+            service
+        );
+    }
+    Ok(format!("/etc/default/{}", service))
+}
+
+/// sets the variables, returns true if the file was changed or the service was restarted
+#[instrument(skip(client))]
+pub async fn etc_default_ensure(
+    client: &impl Executor,
+    opt: &EtcDefaultOptions,
+) -> anyhow::Result<bool> {
+    let path = path(&opt.service)?;
+    let changed = config_kv_ensure(client, &path, &opt.set, KvFormat::Shell).await?;
+    // the restart is retried on the next run if it fails
+    let marker = format!("etc-default-{}-restart", opt.service);
+    let pending = opt.restart.unwrap_or(true) && mark_pending(client, &marker, changed).await?;
+    if pending {
+        let cmd = format!("sudo systemctl restart {} 2>&1", shell_quote(&opt.service));
+        run(client, &cmd).await?;
+        clear_pending(client, &marker).await?;
+    }
+    Ok(changed || pending)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &EtcDefaultOptions) -> anyhow::Result<bool> {
+    let changed = etc_default_ensure(client, opt).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &EtcDefaultOptions) -> anyhow::Result<Status> {
+    config_kv_check(client, &path(&opt.service)?, &opt.set, KvFormat::Shell).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(restart: bool) -> EtcDefaultOptions {
+        EtcDefaultOptions {
+            service: "ssh".to_string(),
+            set: Map::from([("SSHD_OPTS".to_string(), "-4".to_string())]),
+            restart: Some(restart),
+        }
+    }
+
+    fn restarted(host: &ScriptedHost) -> bool {
+        host.executed()
+            .iter()
+            .any(|c| c.contains("sudo systemctl restart 'ssh'"))
+    }
+
+    #[test]
+    fn it_rejects_invalid_service_names() {
+        assert_eq!(path("ssh").unwrap(), "/etc/default/ssh");
+        assert_eq!(path("dbus-broker").unwrap(), "/etc/default/dbus-broker");
+        assert!(path("").is_err());
+        assert!(path("../passwd").is_err());
+        assert!(path("ssh; reboot").is_err());
+    }
+
+    #[tokio::test]
+    async fn it_restarts_the_service_after_a_change() {
+        let host = ScriptedHost::new(vec![("cat '/etc/default/ssh'", 0, "#SSHD_OPTS=\n")]);
+        assert!(etc_default_ensure(&host, &options(true)).await.unwrap());
+        assert!(restarted(&host));
+
+        let host = ScriptedHost::new(vec![("cat '/etc/default/ssh'", 0, "#SSHD_OPTS=\n")]);
+        assert!(etc_default_ensure(&host, &options(false)).await.unwrap());
+        assert!(!restarted(&host));
+    }
+
+    #[tokio::test]
+    async fn it_restarts_once_the_pending_restart() {
+        let content = "#SSHD_OPTS=\nSSHD_OPTS=\"-4\"\n";
+        let host = ScriptedHost::new(vec![
+            ("cat '/etc/default/ssh'", 0, content),
+            ("test -e", 0, ""),
+        ]);
+        assert!(etc_default_ensure(&host, &options(true)).await.unwrap());
+        assert!(restarted(&host));
+
+        let host = ScriptedHost::new(vec![
+            ("cat '/etc/default/ssh'", 0, content),
+            ("test -e", 1, ""),
+        ]);
+        assert!(!etc_default_ensure(&host, &options(true)).await.unwrap());
+        assert!(!restarted(&host));
+    }
+}
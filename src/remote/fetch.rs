@@ -0,0 +1,187 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+use base64::{engine::general_purpose, Engine as _};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct FetchOptions {
+        /// remote files to be downloaded
+        pub src: Vec<String>,
+        /// local directory
+        pub dest: String,
+        /// store files directly in dest instead of dest/<host>/<remote path>
+        #[param(default = "false")]
+        pub flat: Option<bool>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "fetch",
+    description: "download remote files into a local directory",
+    params: FetchOptions::PARAMS,
+    sudo: true,
+};
+
+/// local path of the downloaded file, which always stays inside dest_dir
+pub fn local_path(
+    dest_dir: &Path,
+    host: &str,
+    remote_src: &str,
+    flat: bool,
+) -> anyhow::Result<PathBuf> {
+    if remote_src.ends_with('/') {
+        bail!("{} is not a file name", remote_src);
+    }
+    let mut components = vec![];
+    for component in remote_src.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => bail!("{} must not contain ..", remote_src),
+            _ => components.push(component),
+        }
+    }
+    let Some(name) = components.last() else {
+        bail!("{} is not a file name", remote_src);
+    };
+    if flat {
+        return Ok(dest_dir.join(name));
+    }
+    let mut path = dest_dir.join(host);
+    path.extend(components);
+    Ok(path)
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn host(client: &Client) -> String {
+    client.get_connection_address().ip().to_string()
+}
+
+/// downloads the remote file unless the local copy has the same sha256,
+/// returns true if the local file was written
+#[instrument(skip(client))]
+pub async fn fetch(
+    client: &Client,
+    remote_src: &str,
+    dest_dir: &str,
+    flat: bool,
+) -> anyhow::Result<bool> {
+    let dest_dir = crate::connect::tilde_with_context(dest_dir, dirs::home_dir);
+    let path = local_path(Path::new(&dest_dir), &host(client), remote_src, flat)?;
+    let out = run(
+        client,
+        &format!("sudo sha256sum {} 2>&1", quote_path(remote_src)),
+    )
+    .await?;
+    let remote_sum = out.output.split_whitespace().next().unwrap_or_default();
+    if let Ok(local) = std::fs::read(&path) {
+        if sha256_hex(&local) == remote_sum {
+            debug!("{} is up to date", path.display());
+            return Ok(false);
+        }
+    }
+
+    let out = run(
+        client,
+        &format!("sudo base64 -w0 {} 2>&1", quote_path(remote_src)),
+    )
+    .await?;
+    let contents = general_purpose::STANDARD
+        .decode(out.output.trim())
+        .context("invalid base64 from the remote host")?;
+    if sha256_hex(&contents) != remote_sum {
+        bail!("{} changed while downloading", remote_src);
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, contents).with_context(|| format!("{}", path.display()))?;
+    Ok(true)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &FetchOptions) -> anyhow::Result<bool> {
+    let mut changed = false;
+    for src in &opt.src {
+        changed |= fetch(client, src, &opt.dest, opt.flat.unwrap_or(false)).await?;
+    }
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &FetchOptions) -> anyhow::Result<Status> {
+    let mut success = vec![];
+    let mut fail = vec![];
+    let dest_dir = crate::connect::tilde_with_context(&opt.dest, dirs::home_dir);
+    for src in &opt.src {
+        let path = local_path(
+            Path::new(&dest_dir),
+            &host(client),
+            src,
+            opt.flat.unwrap_or(false),
+        )?;
+        let out = silent(client, &format!("sudo sha256sum {} 2>&1", quote_path(src))).await?;
+        let remote_sum = out.output.split_whitespace().next().unwrap_or_default();
+        match std::fs::read(&path) {
+            Ok(local) if sha256_hex(&local) == remote_sum => {
+                success.push(format!("{} ok", path.display()))
+            }
+            Ok(_) => fail.push(format!("{} differs", path.display())),
+            Err(_) => fail.push(format!("{} missing", path.display())),
+        }
+    }
+    Ok(Status::new(success, fail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_namespaces_by_host() {
+        let dest = Path::new("/tmp/collected");
+        assert_eq!(
+            local_path(dest, "10.0.0.5", "/var/log/syslog", false).unwrap(),
+            PathBuf::from("/tmp/collected/10.0.0.5/var/log/syslog")
+        );
+        assert_eq!(
+            local_path(dest, "10.0.0.5", "/etc/nginx/nginx.conf", true).unwrap(),
+            PathBuf::from("/tmp/collected/nginx.conf")
+        );
+        assert_eq!(
+            local_path(dest, "10.0.0.5", "//etc/./hosts", false).unwrap(),
+            PathBuf::from("/tmp/collected/10.0.0.5/etc/hosts")
+        );
+    }
+
+    #[test]
+    fn it_rejects_paths_escaping_dest() {
+        let dest = Path::new("/tmp/collected");
+        for flat in [false, true] {
+            assert!(local_path(dest, "10.0.0.5", "/var/log/../../../etc/passwd", flat).is_err());
+            assert!(local_path(dest, "10.0.0.5", "../secret", flat).is_err());
+        }
+    }
+
+    #[test]
+    fn it_rejects_empty_file_names() {
+        let dest = Path::new("/tmp/collected");
+        for flat in [false, true] {
+            assert!(local_path(dest, "10.0.0.5", "/var/log/", flat).is_err());
+            assert!(local_path(dest, "10.0.0.5", "/", flat).is_err());
+            assert!(local_path(dest, "10.0.0.5", "", flat).is_err());
+        }
+    }
+
+    #[test]
+    fn it_hashes_like_sha256sum() {
+        assert_eq!(
+            sha256_hex(b"hello\n"),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+        );
+    }
+}
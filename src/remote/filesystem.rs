@@ -0,0 +1,175 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct FilesystemOptions {
+        /// block device, i.e. /dev/sdb1
+        pub device: String,
+        /// filesystem type, i.e. ext4 or xfs
+        pub fstype: String,
+        /// create the filesystem even if the device has another one
+        #[param(default = "false")]
+        pub force: Option<bool>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "filesystem",
+    description: "create a filesystem on a block device that has none",
+    params: FilesystemOptions::PARAMS,
+    sudo: true,
+};
+
+/// parses `blkid -p -o export` output into the tags
+pub fn parse_blkid(output: &str) -> Map<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// filesystem or partition table found on the device
+pub fn signature(tags: &Map<String, String>) -> Option<&str> {
+    tags.get("TYPE")
+        .or_else(|| tags.get("PTTYPE"))
+        .map(|x| x.as_str())
+}
+
+/// returns true if mkfs has to run, refuses to overwrite another signature without force
+pub fn needs_mkfs(
+    device: &str,
+    existing: Option<&str>,
+    fstype: &str,
+    force: bool,
+) -> anyhow::Result<bool> {
+    match existing {
+        None => Ok(true),
+        Some(existing) if existing == fstype => Ok(false),
+        Some(existing) if force => {
+            warn!("{} has {}, it will be wiped", device, existing);
+            Ok(true)
+        }
+        Some(existing) => bail!(
+            "refusing to create {} on {}: it has {}, set force = true to wipe it",
+            fstype,
+            device,
+            existing
+        ),
+    }
+}
+
+pub fn mkfs_cmd(device: &str, fstype: &str, force: bool) -> String {
+    let flag = match fstype {
+        _ if !force => "",
+        "ext2" | "ext3" | "ext4" => " -F",
+        "xfs" | "btrfs" => " -f",
+        _ => "",
+    };
+    format!(
+        "sudo mkfs -t {}{} {} 2>&1",
+        shell_quote(fstype),
+        flag,
+        shell_quote(device)
+    )
+}
+
+/// probes the device directly, so a stale blkid cache doesn't hide a filesystem
+async fn probe(client: &Client, device: &str) -> anyhow::Result<Map<String, String>> {
+    let out = silent(client, &format!("test -b {}", shell_quote(device))).await?;
+    if out.exit_status != 0 {
+        bail!("{} is not a block device", device);
+    }
+    let cmd = format!("sudo blkid -p -o export {} 2>&1", shell_quote(device));
+    let out = silent(client, &cmd).await?;
+    match out.exit_status {
+        0 => Ok(parse_blkid(&out.output)),
+        // no signature was found
+        2 => Ok(Map::new()),
+        _ => bail!("blkid {} failed: {}", device, out.output.trim()),
+    }
+}
+
+/// creates the filesystem unless the device has it already, returns true if it was created
+#[instrument(skip(client))]
+pub async fn filesystem_ensure(
+    client: &Client,
+    device: &str,
+    fstype: &str,
+    force: bool,
+) -> anyhow::Result<bool> {
+    let tags = probe(client, device).await?;
+    if !needs_mkfs(device, signature(&tags), fstype, force)? {
+        return Ok(false);
+    }
+    // findmnt exits with 1 when nothing is mounted, anything else is not a clear answer
+    let out = silent(
+        client,
+        &format!("findmnt -n -S {} 2>&1", shell_quote(device)),
+    )
+    .await?;
+    match out.exit_status {
+        0 => bail!("refusing to create {} on {}: it is mounted", fstype, device),
+        1 => {}
+        _ => bail!("findmnt {} failed: {}", device, out.output.trim()),
+    }
+    run(client, &mkfs_cmd(device, fstype, force)).await?;
+    Ok(true)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &FilesystemOptions) -> anyhow::Result<bool> {
+    let force = opt.force.unwrap_or(false);
+    let changed = filesystem_ensure(client, &opt.device, &opt.fstype, force).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &FilesystemOptions) -> anyhow::Result<Status> {
+    let tags = probe(client, &opt.device).await?;
+    Ok(match signature(&tags) {
+        Some(existing) if existing == opt.fstype => {
+            Status::new(vec![format!("{} has {}", opt.device, existing)], vec![])
+        }
+        Some(existing) => Status::new(vec![], vec![format!("{} has {}", opt.device, existing)]),
+        None => Status::new(vec![], vec![format!("{} has no filesystem", opt.device)]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_blkid() {
+        let output = "DEVNAME=/dev/sdb1\nUUID=5b1b2f4e-1c1d-4c31-9a6e-0d1e2f3a4b5c\nVERSION=1.0\nBLOCK_SIZE=4096\nTYPE=ext4\nUSAGE=filesystem\n";
+        let tags = parse_blkid(output);
+        assert_eq!(signature(&tags), Some("ext4"));
+        let tags = parse_blkid("DEVNAME=/dev/sdc\nPTUUID=1f2e\nPTTYPE=gpt\n");
+        assert_eq!(signature(&tags), Some("gpt"));
+        assert_eq!(signature(&parse_blkid("")), None);
+    }
+
+    #[test]
+    fn it_refuses_without_force() {
+        assert!(needs_mkfs("/dev/sdb1", None, "ext4", false).unwrap());
+        assert!(!needs_mkfs("/dev/sdb1", Some("ext4"), "ext4", false).unwrap());
+        assert!(!needs_mkfs("/dev/sdb1", Some("ext4"), "ext4", true).unwrap());
+        let err = needs_mkfs("/dev/sdb1", Some("xfs"), "ext4", false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refusing to create ext4 on /dev/sdb1: it has xfs, set force = true to wipe it"
+        );
+        assert!(needs_mkfs("/dev/sdc", Some("gpt"), "ext4", false).is_err());
+        assert!(needs_mkfs("/dev/sdb1", Some("xfs"), "ext4", true).unwrap());
+        assert_eq!(
+            mkfs_cmd("/dev/sdb1", "ext4", true),
+            "sudo mkfs -t 'ext4' -F '/dev/sdb1' 2>&1"
+        );
+        assert_eq!(
+            mkfs_cmd("/dev/sdb1", "xfs", false),
+            "sudo mkfs -t 'xfs' '/dev/sdb1' 2>&1"
+        );
+    }
+}
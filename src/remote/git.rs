@@ -1,15 +1,84 @@
 use crate::prelude::*;
+use crate::remote::ModuleInfo;
+use crate::report::Installed;
 
-#[derive(Debug, Default, Deserialize)]
-pub struct GitOptions {
-    pub to: String,
-    pub clone: String,
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct GitOptions {
+        /// destination folder
+        pub to: String,
+        /// repository to be cloned
+        pub clone: String,
+        /// fast-forward an existing checkout to its upstream
+        #[param(default = "false")]
+        pub update: Option<bool>,
+    }
 }
 
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "git",
+    description: "clone git repository",
+    params: GitOptions::PARAMS,
+    sudo: false,
+};
+
 use std::path::Path;
 
+const GIT_ENV: &str = "GIT_SSH_COMMAND=\"ssh -o UserKnownHostsFile=/dev/null -o StrictHostKeyChecking=no\" GIT_TERMINAL_PROMPT=0";
+
+/// commit as printed by `git log --oneline`
+#[derive(Debug, PartialEq)]
+pub struct Commit {
+    pub sha: String,
+    pub subject: String,
+}
+
+/// parses `git log --oneline old..new` output
+pub fn parse_oneline(output: &str) -> Vec<Commit> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (sha, subject) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            if sha.is_empty() || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            Some(Commit {
+                sha: sha.to_string(),
+                subject: subject.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// fetches the upstream and returns commits which are not checked out yet
+#[instrument(skip(client))]
+pub async fn incoming_commits(client: &Client, to: &str) -> anyhow::Result<Vec<Commit>> {
+    run(
+        client,
+        &format!("{} git -C {} fetch --quiet 2>&1", GIT_ENV, quote_path(to)),
+    )
+    .await?;
+    let cmd = format!("git -C {} log --oneline HEAD..@{{u}} 2>&1", quote_path(to));
+    Ok(parse_oneline(&run(client, &cmd).await?.output))
+}
+
+/// fast-forwards the checkout, returns the pulled commits
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, opt: &GitOptions) -> anyhow::Result<()> {
+pub async fn git_update(client: &Client, to: &str) -> anyhow::Result<Vec<Commit>> {
+    let incoming = incoming_commits(client, to).await?;
+    if incoming.is_empty() {
+        return Ok(incoming);
+    }
+    run(
+        client,
+        &format!("git -C {} merge --ff-only @{{u}} 2>&1", quote_path(to)),
+    )
+    .await?;
+    Ok(incoming)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &GitOptions) -> anyhow::Result<Installed> {
     // syncing each local key with the remote location
     if !file_exists(client, &opt.to).await {
         let base_name = Path::new(&opt.to)
@@ -30,21 +99,32 @@ pub async fn on_install(client: &Client, opt: &GitOptions) -> anyhow::Result<()>
             None => "".to_string(),
         };
         if parent != home_dir {
-            run(&client, &format!("mkdir -p {} 2>&1", parent)).await?;
+            run(client, &format!("mkdir -p {} 2>&1", quote_path(parent))).await?;
         } else {
             // we are cloning to home dir, so we need to use relative path
             dest = base_name.to_string();
         }
 
-        let ssh_opts = "ssh -o UserKnownHostsFile=/dev/null -o StrictHostKeyChecking=no";
         let cmd = format!(
-            "GIT_SSH_COMMAND=\"{}\" GIT_TERMINAL_PROMPT=0 git clone {} {} 2>&1",
-            ssh_opts, opt.clone, dest,
+            "{} git clone {} {} 2>&1",
+            GIT_ENV,
+            shell_quote(&opt.clone),
+            quote_path(&dest)
         );
-        run(&client, &cmd).await?;
+        run(client, &cmd).await?;
+        return Ok(true.into());
+    } else if opt.update.unwrap_or(false) {
+        let pulled = git_update(client, &opt.to).await?;
+        return Ok(Installed {
+            changed: !pulled.is_empty(),
+            detail: pulled
+                .iter()
+                .map(|commit| format!("{} {}", commit.sha, commit.subject))
+                .collect(),
+        });
     }
 
-    Ok(())
+    Ok(false.into())
 }
 
 #[instrument(skip(client))]
@@ -54,10 +134,22 @@ pub async fn on_check(client: &Client, opt: &GitOptions) -> anyhow::Result<Statu
 
     if file_exists(client, &opt.to).await {
         let git_config = format!("{}/.git/config", opt.to);
-        if file_exists(client, &git_config).await {
-            success.push(format!("{} ok", opt.to));
-        } else {
+        if !file_exists(client, &git_config).await {
             fail.push(format!("{} missing", opt.to));
+        } else if opt.update.unwrap_or(false) {
+            // only refs are fetched, the checkout is not changed
+            let incoming = incoming_commits(client, &opt.to).await?;
+            if incoming.is_empty() {
+                success.push(format!("{} up to date", opt.to));
+            }
+            for commit in incoming {
+                fail.push(format!(
+                    "{} would pull {} {}",
+                    opt.to, commit.sha, commit.subject
+                ));
+            }
+        } else {
+            success.push(format!("{} ok", opt.to));
         }
     } else {
         fail.push(format!("{} missing", opt.to));
@@ -65,3 +157,28 @@ pub async fn on_check(client: &Client, opt: &GitOptions) -> anyhow::Result<Statu
 
     Ok(Status::new(success, fail))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_incoming_commits() {
+        let output = "3f2a1bc Bump tokio to 1.28\n9e0d4f1 Fix reconnect after reboot\n";
+        assert_eq!(
+            parse_oneline(output),
+            vec![
+                Commit {
+                    sha: "3f2a1bc".to_string(),
+                    subject: "Bump tokio to 1.28".to_string()
+                },
+                Commit {
+                    sha: "9e0d4f1".to_string(),
+                    subject: "Fix reconnect after reboot".to_string()
+                },
+            ]
+        );
+        assert!(parse_oneline("").is_empty());
+        assert!(parse_oneline("fatal: no upstream configured for branch 'main'\n").is_empty());
+    }
+}
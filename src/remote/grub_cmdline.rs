@@ -0,0 +1,200 @@
+use crate::prelude::*;
+use crate::remote::config_kv::{config_kv_ensure, KvFormat};
+use crate::remote::{ModuleInfo, ParamKind};
+
+/// value of a kernel parameter, a list for the parameters given several times
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum CmdlineValue {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl CmdlineValue {
+    pub fn values(&self) -> &[String] {
+        match self {
+            CmdlineValue::One(value) => std::slice::from_ref(value),
+            CmdlineValue::Many(values) => values,
+        }
+    }
+}
+
+impl ParamKind for Map<String, CmdlineValue> {
+    const KIND: &'static str = "table of strings or lists of strings";
+}
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct GrubCmdlineOptions {
+        /// kernel parameters, empty value adds a flag without `=`,
+        /// a list repeats the parameter, i.e. console = ["tty0", "ttyS0,115200"]
+        pub params: Map<String, CmdlineValue>,
+        /// run update-grub when the file was changed
+        #[param(default = "true")]
+        pub update: Option<bool>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "grub-cmdline",
+    description: "set kernel parameters in GRUB_CMDLINE_LINUX",
+    params: GrubCmdlineOptions::PARAMS,
+    sudo: true,
+};
+
+const GRUB_DEFAULT: &str = "/etc/default/grub";
+const CMDLINE_KEY: &str = "GRUB_CMDLINE_LINUX";
+
+/// value of the last active GRUB_CMDLINE_LINUX assignment, without quotes
+pub fn current_cmdline(content: &str) -> String {
+    let prefix = format!("{}=", CMDLINE_KEY);
+    content
+        .lines()
+        .rev()
+        .find_map(|line| line.trim().strip_prefix(&prefix))
+        .map(|value| value.trim().trim_matches(['"', '\'']).to_string())
+        .unwrap_or_default()
+}
+
+fn format_param(key: &str, value: &str) -> String {
+    if value.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}={}", key, value)
+    }
+}
+
+/// updates the parameters in place and appends the new ones,
+/// all values of a repeated parameter are put where its first one was
+pub fn merge_cmdline(cmdline: &str, params: &Map<String, CmdlineValue>) -> String {
+    let mut seen = vec![];
+    let mut out: Vec<String> = vec![];
+    for token in cmdline.split_whitespace() {
+        let key = token.split_once('=').map(|(k, _)| k).unwrap_or(token);
+        match params.get(key) {
+            Some(_) if seen.iter().any(|x| x == key) => {}
+            Some(value) => {
+                seen.push(key.to_string());
+                out.extend(value.values().iter().map(|v| format_param(key, v)));
+            }
+            None => out.push(token.to_string()),
+        }
+    }
+    for (key, value) in params {
+        if !seen.contains(key) {
+            out.extend(value.values().iter().map(|v| format_param(key, v)));
+        }
+    }
+    out.join(" ")
+}
+
+/// returns true if the kernel command line was changed
+#[instrument(skip(client))]
+pub async fn grub_cmdline_ensure(
+    client: &Client,
+    params: &Map<String, CmdlineValue>,
+    update: bool,
+) -> anyhow::Result<bool> {
+    let content = read_file(client, GRUB_DEFAULT)
+        .await?
+        .with_context(|| format!("{} is missing", GRUB_DEFAULT))?;
+    let mut settings = Map::new();
+    settings.insert(
+        CMDLINE_KEY.to_string(),
+        merge_cmdline(&current_cmdline(&content), params),
+    );
+    let changed = config_kv_ensure(client, GRUB_DEFAULT, &settings, KvFormat::Shell).await?;
+    if changed {
+        add_warning("kernel parameters were changed, reboot is required");
+    }
+    // update-grub is retried on the next run if it fails, the file is up to date by then
+    let pending = update && mark_pending(client, "update-grub", changed).await?;
+    if pending {
+        run(client, "sudo update-grub 2>&1").await?;
+        clear_pending(client, "update-grub").await?;
+    }
+    Ok(changed || pending)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &GrubCmdlineOptions) -> anyhow::Result<bool> {
+    let changed = grub_cmdline_ensure(client, &opt.params, opt.update.unwrap_or(true)).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &GrubCmdlineOptions) -> anyhow::Result<Status> {
+    let content = read_file(client, GRUB_DEFAULT).await?.unwrap_or_default();
+    let cmdline = current_cmdline(&content);
+    let tokens: Vec<&str> = cmdline.split_whitespace().collect();
+    let mut success = vec![];
+    let mut fail = vec![];
+    for (key, value) in &opt.params {
+        for expected in value.values().iter().map(|v| format_param(key, v)) {
+            if tokens.contains(&expected.as_str()) {
+                success.push(format!("{} ok", expected));
+            } else {
+                fail.push(format!("{} missing", expected));
+            }
+        }
+    }
+    Ok(Status::new(success, fail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(items: &[(&str, &str)]) -> Map<String, CmdlineValue> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), CmdlineValue::One(v.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn it_reads_cmdline() {
+        let content = "GRUB_DEFAULT=0\n#GRUB_CMDLINE_LINUX=\"old\"\nGRUB_CMDLINE_LINUX=\"console=tty0 quiet\"\nGRUB_CMDLINE_LINUX_DEFAULT=\"splash\"\n";
+        assert_eq!(current_cmdline(content), "console=tty0 quiet");
+        assert_eq!(current_cmdline("GRUB_DEFAULT=0\n"), "");
+    }
+
+    #[test]
+    fn it_merges_cmdline_params() {
+        let set = params(&[
+            ("cgroup_enable", "memory"),
+            ("swapaccount", "1"),
+            ("console", "ttyS0"),
+            ("nomodeset", ""),
+        ]);
+        let merged = merge_cmdline("console=tty0 quiet swapaccount=0", &set);
+        assert_eq!(
+            merged,
+            "console=ttyS0 quiet swapaccount=1 cgroup_enable=memory nomodeset"
+        );
+        assert_eq!(merge_cmdline(&merged, &set), merged);
+        assert_eq!(
+            merge_cmdline("", &params(&[("swapaccount", "1")])),
+            "swapaccount=1"
+        );
+    }
+
+    #[test]
+    fn it_keeps_repeated_params() {
+        let cmdline = "console=tty0 quiet console=ttyS0,115200";
+        // other parameters leave all of the values in place
+        assert_eq!(
+            merge_cmdline(cmdline, &params(&[("nomodeset", "")])),
+            "console=tty0 quiet console=ttyS0,115200 nomodeset"
+        );
+        let set: Map<String, CmdlineValue> =
+            toml::from_str(r#"console = ["ttyS0,115200", "tty1"]"#).unwrap();
+        let merged = merge_cmdline(cmdline, &set);
+        assert_eq!(merged, "console=ttyS0,115200 console=tty1 quiet");
+        assert_eq!(merge_cmdline(&merged, &set), merged);
+        assert_eq!(
+            merge_cmdline(cmdline, &params(&[("console", "ttyS1")])),
+            "console=ttyS1 quiet"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use crate::prelude::*;
+use crate::remote::config_kv::{config_kv_check, config_kv_ensure, KvFormat};
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct JournaldOptions {
+        /// settings of the [Journal] section, i.e. SystemMaxUse = "500M"
+        pub set: Map<String, String>,
+        /// restart systemd-journald if the file was changed
+        #[param(default = "true")]
+        pub restart: Option<bool>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "journald",
+    description: "configure systemd-journald in /etc/systemd/journald.conf",
+    params: JournaldOptions::PARAMS,
+    sudo: true,
+};
+
+const JOURNALD_CONF: &str = "/etc/systemd/journald.conf";
+
+/// makes sure journald.conf contains the settings,
+/// returns true if it was changed or systemd-journald was restarted
+#[instrument(skip(client))]
+pub async fn journald_ensure(
+    client: &impl Executor,
+    settings: &Map<String, String>,
+    restart: bool,
+) -> anyhow::Result<bool> {
+    let changed = config_kv_ensure(client, JOURNALD_CONF, settings, KvFormat::Plain).await?;
+    // the restart is retried on the next run if it fails
+    let pending = restart && mark_pending(client, "journald-restart", changed).await?;
+    if pending {
+        run(client, "sudo systemctl restart systemd-journald 2>&1").await?;
+        clear_pending(client, "journald-restart").await?;
+    }
+    Ok(changed || pending)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &JournaldOptions) -> anyhow::Result<bool> {
+    let changed = journald_ensure(client, &opt.set, opt.restart.unwrap_or(true)).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &JournaldOptions) -> anyhow::Result<Status> {
+    config_kv_check(client, JOURNALD_CONF, &opt.set, KvFormat::Plain).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::config_kv::kv_apply;
+    use base64::{engine::general_purpose, Engine as _};
+
+    #[test]
+    fn it_edits_journald_conf() {
+        let content = "[Journal]\n#Storage=auto\n#Compress=yes\n#SystemMaxUse=\n";
+        let mut set = Map::new();
+        set.insert("SystemMaxUse".to_string(), "500M".to_string());
+        set.insert("Storage".to_string(), "persistent".to_string());
+        let out = kv_apply(content, &set, KvFormat::Plain);
+        assert_eq!(
+            out,
+            "[Journal]\n#Storage=auto\nStorage=persistent\n#Compress=yes\n#SystemMaxUse=\nSystemMaxUse=500M\n"
+        );
+        assert_eq!(kv_apply(&out, &set, KvFormat::Plain), out);
+    }
+
+    fn restarted(host: &ScriptedHost) -> bool {
+        host.executed()
+            .iter()
+            .any(|c| c.contains("systemctl restart systemd-journald"))
+    }
+
+    #[tokio::test]
+    async fn it_restarts_journald_after_a_change() {
+        let set = Map::from([("Storage".to_string(), "persistent".to_string())]);
+        let host = ScriptedHost::new(vec![("cat '/etc/systemd/journald.conf'", 0, "[Journal]\n")]);
+        assert!(journald_ensure(&host, &set, true).await.unwrap());
+        let expected = general_purpose::STANDARD.encode("[Journal]\nStorage=persistent\n");
+        assert!(host.executed().iter().any(|c| c.contains(&expected)));
+        assert!(restarted(&host));
+
+        let host = ScriptedHost::new(vec![
+            (
+                "cat '/etc/systemd/journald.conf'",
+                0,
+                "[Journal]\nStorage=persistent\n",
+            ),
+            ("test -e", 1, ""),
+        ]);
+        assert!(!journald_ensure(&host, &set, true).await.unwrap());
+        assert!(!restarted(&host));
+
+        let host = ScriptedHost::new(vec![("cat '/etc/systemd/journald.conf'", 0, "[Journal]\n")]);
+        assert!(journald_ensure(&host, &set, false).await.unwrap());
+        assert!(!restarted(&host));
+    }
+}
@@ -1,30 +1,46 @@
 use crate::prelude::*;
+use crate::remote::ModuleInfo;
 
-#[derive(Debug, Default, Deserialize)]
-pub struct KeysOptions {
-    pub sync: Vec<String>,
-    pub perm: Option<String>,
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct KeysOptions {
+        /// key files to be uploaded
+        pub sync: Vec<String>,
+        /// permissions of the uploaded files
+        pub perm: Option<String>,
+    }
 }
 
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "keys",
+    description: "upload local key files to the same remote location",
+    params: KeysOptions::PARAMS,
+    sudo: false,
+};
+
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, opt: &KeysOptions) -> anyhow::Result<()> {
+pub async fn on_install(client: &Client, opt: &KeysOptions) -> anyhow::Result<bool> {
     // syncing each local key with the remote location
-    // such approach works only for small base64-encoded key files
+    let mut changed = false;
     for file in &opt.sync {
         let local_path = crate::connect::tilde_with_context(&file, dirs::home_dir);
         let contents = std::fs::read_to_string(&local_path)?;
 
-        if !file_exists(client, &file).await {
-            let cmd = format!("echo '{}' > {}", contents, file);
-            run(&client, &cmd).await?;
+        if !file_exists(client, file).await {
+            write_user_file(client, file, &contents).await?;
+            changed = true;
         }
 
         if let Some(perm) = &opt.perm {
-            let cmd = format!("chmod {} {}", perm, file);
-            run(&client, &cmd).await?;
+            let before = try_stat(client, file).await?;
+            let cmd = format!("chmod {} {}", shell_quote(perm), quote_path(file));
+            run(client, &cmd).await?;
+            let after = stat(client, file).await?;
+            after.verify(file, Some(perm), None)?;
+            changed |= before.as_ref() != Some(&after);
         }
     }
-    Ok(())
+    Ok(changed)
 }
 
 #[instrument(skip(client))]
@@ -32,8 +48,8 @@ pub async fn on_check(client: &Client, opt: &KeysOptions) -> anyhow::Result<Stat
     let mut success = vec![];
     let mut fail = vec![];
     for file in &opt.sync {
-        let cmd = format!("ls -1 {}", file);
-        match silent(&client, &cmd).await {
+        let cmd = format!("ls -1 {}", quote_path(file));
+        match silent(client, &cmd).await {
             Ok(output) => {
                 if output.exit_status == 0 {
                     success.push(format!("{} found", file));
@@ -0,0 +1,198 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct LogrotateOptions {
+        /// name of the file in /etc/logrotate.d
+        pub name: String,
+        /// log files, globs are allowed
+        pub paths: Vec<String>,
+        /// directives, i.e. rotate = 7, frequency = "daily", compress = true, postrotate = "..."
+        pub options: Map<String, toml::Value>,
+        /// present or absent
+        #[param(default = "present")]
+        pub state: Option<String>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "logrotate",
+    description: "manage /etc/logrotate.d entries validated with logrotate -d",
+    params: LogrotateOptions::PARAMS,
+    sudo: true,
+};
+
+/// directives that are followed by a script and `endscript`
+const SCRIPTS: &[&str] = &[
+    "postrotate",
+    "prerotate",
+    "firstaction",
+    "lastaction",
+    "preremove",
+];
+
+fn path(name: &str) -> String {
+    format!("/etc/logrotate.d/{}", name)
+}
+
+/// renders the config, `true` is a flag, `false` omits the directive
+pub fn logrotate_config(paths: &[String], options: &Map<String, toml::Value>) -> String {
+    let mut out = format!("{} {{\n", paths.join(" "));
+    for (key, value) in options {
+        let value = match value {
+            toml::Value::Boolean(false) => continue,
+            toml::Value::Boolean(true) => {
+                out.push_str(&format!("    {}\n", key));
+                continue;
+            }
+            toml::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        if SCRIPTS.contains(&key.as_str()) {
+            out.push_str(&format!("    {}\n", key));
+            for line in value.lines() {
+                out.push_str(&format!("        {}\n", line.trim()));
+            }
+            out.push_str("    endscript\n");
+        } else if key == "frequency" {
+            out.push_str(&format!("    {}\n", value));
+        } else {
+            out.push_str(&format!("    {} {}\n", key, value));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// returns true if the entry was changed
+#[instrument(skip(client))]
+pub async fn logrotate_ensure(
+    client: &impl Executor,
+    name: &str,
+    paths: &[String],
+    options: &Map<String, toml::Value>,
+    state: Option<&str>,
+) -> anyhow::Result<bool> {
+    let path = path(name);
+    let current = read_file(client, &path).await?;
+    if !parse_state(state)? {
+        if current.is_none() {
+            return Ok(false);
+        }
+        run(client, &format!("sudo rm -f {}", shell_quote(&path))).await?;
+        return Ok(true);
+    }
+    let expected = logrotate_config(paths, options);
+    if current.as_deref() == Some(expected.as_str()) {
+        return Ok(false);
+    }
+    // dry run of the config in a temporary file owned by root
+    let tmp = upload_temp(client, "sudo mktemp", &expected, true).await?;
+    let validated = run(
+        client,
+        &format!("sudo logrotate -d {} 2>&1", shell_quote(&tmp)),
+    )
+    .await;
+    remove_temp(client, &tmp, true).await?;
+    validated.with_context(|| format!("{} failed logrotate validation", name))?;
+    write_file(client, &path, &expected).await?;
+    Ok(true)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &LogrotateOptions) -> anyhow::Result<bool> {
+    let changed = logrotate_ensure(
+        client,
+        &opt.name,
+        &opt.paths,
+        &opt.options,
+        opt.state.as_deref(),
+    )
+    .await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &LogrotateOptions) -> anyhow::Result<Status> {
+    let path = path(&opt.name);
+    let current = read_file(client, &path).await?;
+    let ok = if parse_state(opt.state.as_deref())? {
+        current.as_deref() == Some(logrotate_config(&opt.paths, &opt.options).as_str())
+    } else {
+        current.is_none()
+    };
+    if ok {
+        Ok(Status::new(vec![format!("{} ok", path)], vec![]))
+    } else {
+        Ok(Status::new(vec![], vec![format!("{} differs", path)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_logrotate_config() {
+        let opt: LogrotateOptions = toml::from_str(
+            r#"
+name = "app"
+paths = ["/var/log/app/*.log", "/var/log/app.log"]
+[options]
+rotate = 7
+frequency = "daily"
+compress = true
+delaycompress = false
+su = "app adm"
+postrotate = """
+systemctl reload app
+"""
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            logrotate_config(&opt.paths, &opt.options),
+            r#"/var/log/app/*.log /var/log/app.log {
+    compress
+    daily
+    postrotate
+        systemctl reload app
+    endscript
+    rotate 7
+    su app adm
+}
+"#
+        );
+    }
+
+    #[tokio::test]
+    async fn it_validates_in_temporary_file() {
+        let paths = vec!["/var/log/app/*.log".to_string()];
+        let host = ScriptedHost::new(vec![
+            ("cat '/etc/logrotate.d/app'", 1, ""),
+            ("logrotate -d", 1, "error: bad line"),
+        ]);
+        let err = logrotate_ensure(&host, "app", &paths, &Map::new(), None)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("app failed logrotate validation"));
+        let executed = host.executed();
+        let validate = executed
+            .iter()
+            .position(|c| c == "sudo logrotate -d '/tmp/tmp.scripted' 2>&1")
+            .unwrap();
+        assert_eq!(executed[validate + 1], "sudo rm -f '/tmp/tmp.scripted'");
+        // the entry is not written
+        assert_eq!(executed.len(), validate + 2);
+
+        let host = ScriptedHost::new(vec![("cat '/etc/logrotate.d/app'", 1, "")]);
+        assert!(logrotate_ensure(&host, "app", &paths, &Map::new(), None)
+            .await
+            .unwrap());
+        assert!(host
+            .executed()
+            .iter()
+            .any(|c| c.contains("mv -f '/tmp/tmp.scripted' '/etc/logrotate.d/app'")));
+    }
+}
@@ -1,13 +1,28 @@
 use crate::prelude::*;
+use crate::remote::ModuleInfo;
 
-#[derive(Debug, Default, Deserialize)]
-pub struct MkdirOptions {
-    #[serde(deserialize_with = "deserialize_bool_from_anything")]
-    pub sudo: bool,
-    pub folders: Vec<String>,
-    pub perm: Option<String>,
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct MkdirOptions {
+        /// create folders with sudo
+        #[serde(deserialize_with = "deserialize_bool_from_anything")]
+        pub sudo: bool,
+        /// folders to be created
+        pub folders: Vec<String>,
+        /// permissions applied recursively
+        #[param(default = "0777")]
+        pub perm: Option<String>,
+    }
 }
 
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "mkdir",
+    description: "create remote directories with permissions",
+    params: MkdirOptions::PARAMS,
+    // only with the sudo option, see Stage::needs_sudo
+    sudo: false,
+};
+
 impl MkdirOptions {
     pub fn writtable(input: impl Iterator<Item = impl Into<String>>) -> Self {
         let folders = input.map(|x| x.into()).collect();
@@ -19,17 +34,28 @@ impl MkdirOptions {
     }
 }
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, opt: &MkdirOptions) -> anyhow::Result<()> {
+pub async fn on_install(client: &Client, opt: &MkdirOptions) -> anyhow::Result<bool> {
     let sudo_prefix = if opt.sudo { "sudo " } else { "" };
     let list = opt.folders.join(" ");
-    let cmd1 = format!("{} mkdir -p {}", sudo_prefix, list);
-    run(&client, &cmd1).await?;
-
     let default = "0777".to_string();
     let perm = opt.perm.as_ref().unwrap_or(&default);
+    let mut before = Vec::with_capacity(opt.folders.len());
+    for folder in &opt.folders {
+        before.push(try_stat(client, folder).await?);
+    }
+
+    let cmd1 = format!("{} mkdir -p {}", sudo_prefix, list);
+    run(client, &cmd1).await?;
+
     let cmd2 = format!("{} chmod -R {} {}", sudo_prefix, perm, list);
-    run(&client, &cmd2).await?;
-    Ok(())
+    run(client, &cmd2).await?;
+    let mut changed = false;
+    for (folder, before) in opt.folders.iter().zip(before) {
+        let after = stat(client, folder).await?;
+        after.verify(folder, Some(perm), None)?;
+        changed |= before.as_ref() != Some(&after);
+    }
+    Ok(changed)
 }
 
 #[instrument(skip(client))]
@@ -38,7 +64,7 @@ pub async fn on_check(client: &Client, opt: &MkdirOptions) -> anyhow::Result<Sta
     let mut fail = vec![];
     for folder in &opt.folders {
         let cmd = format!("ls -d {}", folder);
-        match silent(&client, &cmd).await {
+        match silent(client, &cmd).await {
             Ok(output) => {
                 if output.exit_status == 0 {
                     success.push(format!("{} found", folder));
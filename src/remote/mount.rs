@@ -1,11 +1,21 @@
 use crate::prelude::*;
-
-#[derive(Debug, Default, Deserialize)]
-pub struct MountOptions {
-    /// Destination folder to be mounted, i.e. /data
-    /// the biggest free device that is not mounted is selected
-    pub to: String,
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct MountOptions {
+        /// destination folder to be mounted, i.e. /data,
+        /// the biggest free device that is not mounted is selected
+        pub to: String,
+    }
 }
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "mount",
+    description: "mount the biggest free block device to a folder and register it in fstab",
+    params: MountOptions::PARAMS,
+    sudo: true,
+};
 impl MountOptions {
     pub fn new(to: &str) -> Self {
         MountOptions { to: to.to_string() }
@@ -51,7 +61,7 @@ impl BlockDevice {
 
     fn has_children(&self) -> bool {
         match &self.children {
-            Some(children) => children.len() > 0,
+            Some(children) => !children.is_empty(),
             None => false,
         }
     }
@@ -60,7 +70,7 @@ impl BlockDevice {
         if self.blocktype != "disk" || self.has_children() {
             return true;
         }
-        if self.mountpoints.len() == 0 {
+        if self.mountpoints.is_empty() {
             return false;
         }
         if self.mountpoints.len() == 1 {
@@ -69,7 +79,7 @@ impl BlockDevice {
                 return false;
             }
         }
-        return true;
+        true
     }
 }
 
@@ -126,15 +136,15 @@ async fn mounting(
 ) -> anyhow::Result<()> {
     // mkfs -t ext4 /dev/sdc
     let cmd_mkfs = format!("sudo mkfs -t {} /dev/{} 2>&1", fs_type, name);
-    run(&client, &cmd_mkfs).await?;
+    run(client, &cmd_mkfs).await?;
 
     // mkdir -p /data2
     let cmd_mkdir = format!("sudo mkdir -p {} 2>&1", to);
-    run(&client, &cmd_mkdir).await?;
+    run(client, &cmd_mkdir).await?;
 
     // mount /dev/sdc /data2
     let cmd_mount = format!("sudo mount /dev/{} {} 2>&1", name, to);
-    run(&client, &cmd_mount).await?;
+    run(client, &cmd_mount).await?;
     Ok(())
 }
 
@@ -142,26 +152,26 @@ async fn mounting(
 async fn update_fstab(client: &Client, name: &str, to: &str, fs_type: &str) -> anyhow::Result<()> {
     // cp /etc/fstab /etc/fstab.bak
     let cmd_fstab_b = "sudo cp /etc/fstab /etc/fstab.bak";
-    run(&client, cmd_fstab_b).await?;
+    run(client, cmd_fstab_b).await?;
 
     // echo "/dev/sdc       /data   ext4    defaults,nofail        0       0" >> /etc/fstab
     let cmd_fstab = format!(
         "sudo sh -c 'echo \"/dev/{}       {}   {}    defaults,nofail        0       0\" >> /etc/fstab'",
         name, to, fs_type
     );
-    run(&client, &cmd_fstab).await?;
+    run(client, &cmd_fstab).await?;
     Ok(())
 }
 
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, opt: &MountOptions) -> anyhow::Result<()> {
-    let result = client.execute("lsblk -J").await?;
-    let devices: LsBlkOutput = serde_json::from_str::<LsBlkOutput>(&result.output)?;
+pub async fn on_install(client: &Client, opt: &MountOptions) -> anyhow::Result<bool> {
+    let devices: LsBlkOutput = run_json(client, "lsblk -J").await?;
     for x in &devices.blockdevices {
         if !x.is_busy() {
             debug!("{} {} is not busy\n", x.name, x.size);
         }
     }
+    let mut changed = false;
     let found = match devices.get_mounted_to(opt.to.clone()) {
         Some(device) => {
             info!("folder {} is already used by {}\n", opt.to, device.name);
@@ -172,23 +182,25 @@ pub async fn on_install(client: &Client, opt: &MountOptions) -> anyhow::Result<(
                 .get_biggest_unmounted()
                 .context("failed to find target block device")?;
             mounting(client, &found.name, &opt.to, &found.blocktype, "ext4").await?;
+            changed = true;
             found
         }
     };
 
     // check if the device is already mounted in fstab, and update it
     let device_name = format!("/dev/{}", found.name);
-    let is_in_fstab = run(&client, "cat /etc/fstab")
+    let is_in_fstab = run(client, "cat /etc/fstab")
         .await?
         .output
         .contains(&device_name);
     if !is_in_fstab {
         update_fstab(client, &found.name, &opt.to, "ext4").await?;
+        changed = true;
     } else {
         info!("{} is already in fstab", device_name);
     }
 
-    Ok(())
+    Ok(changed)
 }
 
 // return types: ready for install, installed
@@ -196,8 +208,7 @@ pub async fn on_install(client: &Client, opt: &MountOptions) -> anyhow::Result<(
 pub async fn on_check(client: &Client, opt: &MountOptions) -> anyhow::Result<Status> {
     let mut success = vec![];
     let mut fail = vec![];
-    let result = client.execute("lsblk -J").await?;
-    let devices: LsBlkOutput = serde_json::from_str::<LsBlkOutput>(&result.output)?;
+    let devices: LsBlkOutput = run_json(client, "lsblk -J").await?;
     for x in &devices.blockdevices {
         if !x.is_busy() {
             info!("{} {} is not busy\n", x.name, x.size);
@@ -211,12 +222,12 @@ pub async fn on_check(client: &Client, opt: &MountOptions) -> anyhow::Result<Sta
         None => {
             let Some(found) = devices.get_biggest_unmounted() else {
                 fail.push("failed to find target block device".to_string());
-                return Ok(Status::NotInstalled { success, fail });
+                return Ok(Status::new(success, fail));
             };
             format!("/dev/{}", found.name)
         }
     };
-    let is_in_fstab = run(&client, "cat /etc/fstab")
+    let is_in_fstab = run(client, "cat /etc/fstab")
         .await?
         .output
         .contains(&device_name);
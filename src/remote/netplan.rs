@@ -0,0 +1,189 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct NetplanOptions {
+        /// name of the file, the path is /etc/netplan/<name>.yaml
+        pub name: String,
+        /// netplan configuration, i.e. { network = { version = 2, ethernets = { ... } } }
+        pub config: Map<String, toml::Value>,
+        /// run `netplan apply` when the file was changed
+        #[param(default = "true")]
+        pub apply: Option<bool>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "netplan",
+    description: "write netplan configuration validated with netplan generate",
+    params: NetplanOptions::PARAMS,
+    sudo: true,
+};
+
+pub fn path(name: &str) -> String {
+    format!("/etc/netplan/{}.yaml", name)
+}
+
+/// serializes the configuration as YAML
+pub fn to_yaml(config: &Map<String, toml::Value>) -> String {
+    let table: toml::map::Map<String, toml::Value> =
+        config.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    toml_to_yaml(&toml::Value::Table(table))
+}
+
+/// writes the configuration, validated with netplan generate,
+/// returns true if the file was changed or netplan apply was run
+#[instrument(skip(client))]
+pub async fn netplan_ensure(
+    client: &impl Executor,
+    name: &str,
+    config: &Map<String, toml::Value>,
+    apply: bool,
+) -> anyhow::Result<bool> {
+    let path = path(name);
+    let expected = to_yaml(config);
+    let previous = read_file(client, &path).await?;
+    let changed = previous.as_deref() != Some(expected.as_str());
+    if changed {
+        write_file(client, &path, &expected).await?;
+        run(client, &format!("sudo chmod 600 {}", shell_quote(&path))).await?;
+        if let Err(e) = run(client, "sudo netplan generate 2>&1").await {
+            // restore the previous configuration not to break the network on reboot
+            match &previous {
+                Some(contents) => write_file(client, &path, contents).await?,
+                None => {
+                    run(client, &format!("sudo rm -f {}", shell_quote(&path))).await?;
+                }
+            }
+            return Err(e.context(format!("{} is invalid", path)));
+        }
+    }
+    // netplan apply is retried on the next run if it fails
+    let pending = apply && mark_pending(client, "netplan-apply", changed).await?;
+    if pending {
+        run(client, "sudo netplan apply 2>&1").await?;
+        clear_pending(client, "netplan-apply").await?;
+    }
+    Ok(changed || pending)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &NetplanOptions) -> anyhow::Result<bool> {
+    let changed = netplan_ensure(client, &opt.name, &opt.config, opt.apply.unwrap_or(true)).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &NetplanOptions) -> anyhow::Result<Status> {
+    let path = path(&opt.name);
+    match read_file(client, &path).await? {
+        Some(current) if current == to_yaml(&opt.config) => {
+            Ok(Status::new(vec![format!("{} ok", path)], vec![]))
+        }
+        Some(_) => Ok(Status::new(vec![], vec![format!("{} differs", path)])),
+        None => Ok(Status::new(vec![], vec![format!("{} missing", path)])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose, Engine as _};
+
+    #[test]
+    fn it_serializes_yaml() {
+        let opt: NetplanOptions = toml::from_str(
+            r#"
+name = "60-static"
+[config.network]
+version = 2
+[config.network.ethernets.eth0]
+dhcp4 = false
+addresses = ["10.0.0.5/24"]
+routes = [{ to = "default", via = "10.0.0.1" }]
+nameservers = { addresses = ["1.1.1.1"], search = [] }
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            to_yaml(&opt.config),
+            r#""network":
+  "ethernets":
+    "eth0":
+      "addresses":
+        - "10.0.0.5/24"
+      "dhcp4": false
+      "nameservers":
+        "addresses":
+          - "1.1.1.1"
+        "search": []
+      "routes":
+        - "to": "default"
+          "via": "10.0.0.1"
+  "version": 2
+"#
+        );
+    }
+
+    fn applied(host: &ScriptedHost) -> bool {
+        host.executed().iter().any(|c| c.contains("netplan apply"))
+    }
+
+    #[tokio::test]
+    async fn it_restores_the_previous_file_when_generate_fails() {
+        let host = ScriptedHost::new(vec![
+            ("cat '/etc/netplan/60-static.yaml'", 0, "network: {}\n"),
+            ("netplan generate", 1, "Error in network definition"),
+        ]);
+        let config = Map::from([("network".to_string(), toml::Value::Integer(1))]);
+        let err = netplan_ensure(&host, "60-static", &config, true)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("/etc/netplan/60-static.yaml is invalid"));
+        let restored = general_purpose::STANDARD.encode("network: {}\n");
+        let last_write = host.executed().into_iter().rfind(|c| c.contains("base64"));
+        assert!(last_write.unwrap().contains(&restored));
+        assert!(!applied(&host));
+    }
+
+    #[tokio::test]
+    async fn it_removes_the_new_file_when_generate_fails() {
+        let host = ScriptedHost::new(vec![
+            ("cat '/etc/netplan/60-static.yaml'", 1, ""),
+            ("netplan generate", 1, "Error in network definition"),
+        ]);
+        let config = Map::from([("network".to_string(), toml::Value::Integer(1))]);
+        assert!(netplan_ensure(&host, "60-static", &config, true)
+            .await
+            .is_err());
+        let executed = host.executed();
+        assert!(executed
+            .iter()
+            .any(|c| c.contains("rm -f '/etc/netplan/60-static.yaml'")));
+        assert!(!applied(&host));
+    }
+
+    #[tokio::test]
+    async fn it_applies_a_pending_configuration() {
+        let config = Map::from([("network".to_string(), toml::Value::Integer(1))]);
+        let host = ScriptedHost::new(vec![
+            ("cat '/etc/netplan/60-static.yaml'", 0, "\"network\": 1\n"),
+            ("test -e", 0, ""),
+        ]);
+        assert!(netplan_ensure(&host, "60-static", &config, true)
+            .await
+            .unwrap());
+        assert!(applied(&host));
+        assert!(!host.executed().iter().any(|c| c.contains("base64")));
+
+        let host = ScriptedHost::new(vec![
+            ("cat '/etc/netplan/60-static.yaml'", 0, "\"network\": 1\n"),
+            ("test -e", 1, ""),
+        ]);
+        assert!(!netplan_ensure(&host, "60-static", &config, true)
+            .await
+            .unwrap());
+        assert!(!applied(&host));
+    }
+}
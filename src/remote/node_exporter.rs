@@ -1,13 +1,24 @@
 use crate::prelude::*;
+use crate::remote::ModuleInfo;
 
-#[derive(Debug, Default, Deserialize)]
-pub struct NodeExporterOptions {}
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct NodeExporterOptions {}
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "node-exporter",
+    description: "run prometheus node-exporter container",
+    params: NodeExporterOptions::PARAMS,
+    sudo: false,
+};
 
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, opt: &NodeExporterOptions) -> anyhow::Result<()> {
+pub async fn on_install(client: &Client, opt: &NodeExporterOptions) -> anyhow::Result<bool> {
     let cmd = "docker run --name node-exporter --restart=always -d --net=\"host\" --pid=\"host\" -v \"/:/host:ro,rslave\" quay.io/prometheus/node-exporter:latest --path.rootfs=/host";
-    client.execute(cmd).await?;
-    Ok(())
+    // fails when the container already exists
+    let out = client.execute(cmd).await?;
+    Ok(out.exit_status == 0)
 }
 
 // return types: ready for install, installed
@@ -0,0 +1,320 @@
+use crate::prelude::*;
+use crate::remote::cron_d::{cron_d_contents, cron_d_path};
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct PeriodicJobOptions {
+        /// name of the job, the script is installed as /usr/local/bin/<name>
+        pub name: String,
+        /// contents of the script, with the shebang line
+        pub script: String,
+        /// cron expression, i.e. "*/5 * * * *", or OnCalendar= of the timer, i.e. "hourly"
+        pub schedule: String,
+        /// cron or systemd-timer
+        #[param(default = "cron")]
+        pub method: Option<String>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "periodic-job",
+    description: "deploy a script and run it on schedule with cron or a systemd timer",
+    params: PeriodicJobOptions::PARAMS,
+    sudo: true,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobMethod {
+    Cron,
+    SystemdTimer,
+}
+
+impl JobMethod {
+    pub fn parse(method: Option<&str>) -> anyhow::Result<Self> {
+        match method.unwrap_or("cron") {
+            "cron" => Ok(JobMethod::Cron),
+            "systemd-timer" => Ok(JobMethod::SystemdTimer),
+            other => bail!("invalid method {}, expected cron or systemd-timer", other),
+        }
+    }
+}
+
+/// remote file with its contents
+#[derive(Debug, PartialEq)]
+pub struct JobFile {
+    pub path: String,
+    pub contents: String,
+}
+
+pub fn script_path(name: &str) -> String {
+    format!("/usr/local/bin/{}", name)
+}
+
+fn unit_path(name: &str, kind: &str) -> String {
+    format!("/etc/systemd/system/{}.{}", name, kind)
+}
+
+/// files of the other method, left behind when the method is switched
+pub fn stale_paths(name: &str, method: JobMethod) -> anyhow::Result<Vec<String>> {
+    Ok(match method {
+        JobMethod::Cron => vec![unit_path(name, "timer"), unit_path(name, "service")],
+        JobMethod::SystemdTimer => vec![cron_d_path(name)?],
+    })
+}
+
+/// files that schedule the script, besides the script itself
+pub fn schedule_files(
+    name: &str,
+    schedule: &str,
+    method: JobMethod,
+) -> anyhow::Result<Vec<JobFile>> {
+    // the name is also used for the timer units, it is validated for both methods
+    let cron_path = cron_d_path(name)?;
+    let script = script_path(name);
+    Ok(match method {
+        JobMethod::Cron => vec![JobFile {
+            path: cron_path,
+            contents: cron_d_contents(schedule, "root", &script)?,
+        }],
+        JobMethod::SystemdTimer => vec![
+            JobFile {
+                path: unit_path(name, "service"),
+                contents: format!(
+                    "[Unit]\nDescription={}\n\n[Service]\nType=oneshot\nExecStart={}\n",
+                    name, script
+                ),
+            },
+            JobFile {
+                path: unit_path(name, "timer"),
+                contents: format!(
+                    "[Unit]\nDescription={}\n\n[Timer]\nOnCalendar={}\nPersistent=true\n\n[Install]\nWantedBy=timers.target\n",
+                    name, schedule
+                ),
+            },
+        ],
+    })
+}
+
+async fn is_timer_enabled(client: &impl Executor, name: &str) -> anyhow::Result<bool> {
+    let timer = shell_quote(&format!("{}.timer", name));
+    let cmd = format!("systemctl is-enabled {} 2>&1", timer);
+    Ok(silent(client, &cmd).await?.exit_status == 0)
+}
+
+/// removes the schedule of the other method, returns true if anything was removed
+async fn remove_stale(
+    client: &impl Executor,
+    name: &str,
+    method: JobMethod,
+) -> anyhow::Result<bool> {
+    let mut removed = false;
+    for path in stale_paths(name, method)? {
+        if try_stat(client, &path).await?.is_none() {
+            continue;
+        }
+        if path.ends_with(".timer") {
+            let timer = shell_quote(&format!("{}.timer", name));
+            run(
+                client,
+                &format!("sudo systemctl disable --now {} 2>&1", timer),
+            )
+            .await?;
+        }
+        run(client, &format!("sudo rm -f {}", shell_quote(&path))).await?;
+        removed = true;
+    }
+    if removed && method == JobMethod::Cron {
+        run(client, "sudo systemctl daemon-reload 2>&1").await?;
+    }
+    Ok(removed)
+}
+
+/// deploys the script and schedules it, returns true if anything was changed
+#[instrument(skip(client, script))]
+pub async fn periodic_job(
+    client: &impl Executor,
+    name: &str,
+    script: &str,
+    schedule: &str,
+    method: JobMethod,
+) -> anyhow::Result<bool> {
+    let files = schedule_files(name, schedule, method)?;
+    let path = script_path(name);
+    let mut changed = ensure_file(client, &path, script).await?;
+    if stat(client, &path).await?.mode != "755" {
+        run(
+            client,
+            &format!("sudo chmod 0755 {} 2>&1", shell_quote(&path)),
+        )
+        .await?;
+        changed = true;
+    }
+    changed |= remove_stale(client, name, method).await?;
+    let mut scheduled = false;
+    for file in &files {
+        scheduled |= ensure_file(client, &file.path, &file.contents).await?;
+    }
+    if method == JobMethod::SystemdTimer {
+        if scheduled {
+            run(client, "sudo systemctl daemon-reload 2>&1").await?;
+        }
+        if scheduled || !is_timer_enabled(client, name).await? {
+            let timer = shell_quote(&format!("{}.timer", name));
+            let cmd = format!("sudo systemctl enable --now {} 2>&1", timer);
+            run(client, &cmd).await?;
+            changed = true;
+        }
+    }
+    changed |= scheduled;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &PeriodicJobOptions) -> anyhow::Result<bool> {
+    let method = JobMethod::parse(opt.method.as_deref())?;
+    let changed = periodic_job(client, &opt.name, &opt.script, &opt.schedule, method).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &PeriodicJobOptions) -> anyhow::Result<Status> {
+    let method = JobMethod::parse(opt.method.as_deref())?;
+    let mut files = vec![JobFile {
+        path: script_path(&opt.name),
+        contents: opt.script.clone(),
+    }];
+    files.extend(schedule_files(&opt.name, &opt.schedule, method)?);
+    let mut success = vec![];
+    let mut fail = vec![];
+    for file in &files {
+        match read_file(client, &file.path).await? {
+            Some(contents) if contents == file.contents => {
+                success.push(format!("{} ok", file.path))
+            }
+            Some(_) => fail.push(format!("{} differs", file.path)),
+            None => fail.push(format!("{} missing", file.path)),
+        }
+    }
+    let script = script_path(&opt.name);
+    if try_stat(client, &script)
+        .await?
+        .is_some_and(|x| x.mode != "755")
+    {
+        fail.push(format!("{} is not executable", script));
+    }
+    for path in stale_paths(&opt.name, method)? {
+        if try_stat(client, &path).await?.is_some() {
+            fail.push(format!("{} is left from the other method", path));
+        }
+    }
+    if method == JobMethod::SystemdTimer {
+        if is_timer_enabled(client, &opt.name).await? {
+            success.push(format!("{}.timer enabled", opt.name));
+        } else {
+            fail.push(format!("{}.timer is not enabled", opt.name));
+        }
+    }
+    Ok(Status::new(success, fail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_schedules_with_cron() {
+        let files = schedule_files("sa1", "*/10 * * * *", JobMethod::Cron).unwrap();
+        assert_eq!(
+            files,
+            vec![JobFile {
+                path: "/etc/cron.d/sa1".to_string(),
+                contents: "*/10 * * * * root /usr/local/bin/sa1\n".to_string(),
+            }]
+        );
+        assert!(schedule_files("sa1", "hourly", JobMethod::Cron).is_err());
+        assert!(schedule_files("sa1.sh", "@hourly", JobMethod::Cron).is_err());
+        assert!(schedule_files("sa1", "@hourly", JobMethod::Cron).is_ok());
+    }
+
+    #[test]
+    fn it_schedules_with_systemd_timer() {
+        let files = schedule_files("sa1", "*:0/10", JobMethod::SystemdTimer).unwrap();
+        assert_eq!(files[0].path, "/etc/systemd/system/sa1.service");
+        assert!(files[0]
+            .contents
+            .contains("Type=oneshot\nExecStart=/usr/local/bin/sa1\n"));
+        assert_eq!(files[1].path, "/etc/systemd/system/sa1.timer");
+        assert!(files[1].contents.contains("OnCalendar=*:0/10\n"));
+        assert!(files[1].contents.ends_with("WantedBy=timers.target\n"));
+        assert_eq!(
+            JobMethod::parse(Some("systemd-timer")).unwrap(),
+            JobMethod::SystemdTimer
+        );
+        assert!(JobMethod::parse(Some("at")).is_err());
+    }
+
+    fn options() -> (String, String) {
+        ("#!/bin/sh\nsar -A\n".to_string(), "*:0/10".to_string())
+    }
+
+    #[tokio::test]
+    async fn it_switches_from_cron_to_timer() {
+        let (script, schedule) = options();
+        let host = ScriptedHost::new(vec![
+            ("cat '/usr/local/bin/sa1'", 0, "#!/bin/sh\nsar -A\n"),
+            (
+                "stat -c '%a %U %G' '/usr/local/bin/sa1'",
+                0,
+                "644 root root\n",
+            ),
+            ("stat -c '%a %U %G' '/etc/cron.d/sa1'", 0, "644 root root\n"),
+            ("is-enabled", 1, "disabled\n"),
+        ]);
+        let changed = periodic_job(&host, "sa1", &script, &schedule, JobMethod::SystemdTimer)
+            .await
+            .unwrap();
+        assert!(changed);
+        let executed = host.executed();
+        assert!(executed.contains(&"sudo chmod 0755 '/usr/local/bin/sa1' 2>&1".to_string()));
+        assert!(executed.contains(&"sudo rm -f '/etc/cron.d/sa1'".to_string()));
+        assert!(executed.contains(&"sudo systemctl enable --now 'sa1.timer' 2>&1".to_string()));
+    }
+
+    #[tokio::test]
+    async fn it_enables_the_timer_without_changes() {
+        let (script, schedule) = options();
+        let files = schedule_files("sa1", &schedule, JobMethod::SystemdTimer).unwrap();
+        let host = ScriptedHost::new(vec![
+            ("cat '/usr/local/bin/sa1'", 0, "#!/bin/sh\nsar -A\n"),
+            (
+                "cat '/etc/systemd/system/sa1.service'",
+                0,
+                &files[0].contents,
+            ),
+            ("cat '/etc/systemd/system/sa1.timer'", 0, &files[1].contents),
+            (
+                "stat -c '%a %U %G' '/usr/local/bin/sa1'",
+                0,
+                "755 root root\n",
+            ),
+            ("stat -c", 1, ""),
+            ("is-enabled", 1, "disabled\n"),
+        ]);
+        assert!(
+            periodic_job(&host, "sa1", &script, &schedule, JobMethod::SystemdTimer)
+                .await
+                .unwrap()
+        );
+        let executed = host.executed();
+        assert!(executed.contains(&"sudo systemctl enable --now 'sa1.timer' 2>&1".to_string()));
+        assert!(!executed.iter().any(|c| c.contains("daemon-reload")));
+        assert_eq!(
+            stale_paths("sa1", JobMethod::Cron).unwrap(),
+            vec![
+                "/etc/systemd/system/sa1.timer",
+                "/etc/systemd/system/sa1.service"
+            ]
+        );
+    }
+}
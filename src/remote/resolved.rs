@@ -0,0 +1,144 @@
+use crate::prelude::*;
+use crate::remote::config_kv::{config_kv_check, config_kv_ensure, KvFormat};
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct ResolvedOptions {
+        /// name servers
+        pub dns: Option<Vec<String>>,
+        /// name servers used when no other is known
+        pub fallback_dns: Option<Vec<String>>,
+        /// yes, no or allow-downgrade
+        pub dnssec: Option<String>,
+        /// listen on 127.0.0.53, disable to free port 53
+        pub stub_listener: Option<bool>,
+        /// cache the answers
+        pub cache: Option<bool>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "resolved",
+    description: "configure systemd-resolved in /etc/systemd/resolved.conf",
+    params: ResolvedOptions::PARAMS,
+    sudo: true,
+};
+
+const RESOLVED_CONF: &str = "/etc/systemd/resolved.conf";
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+/// settings of the [Resolve] section, only for the given options
+pub fn resolved_settings(opt: &ResolvedOptions) -> Map<String, String> {
+    let mut settings = Map::new();
+    if let Some(dns) = &opt.dns {
+        settings.insert("DNS".to_string(), dns.join(" "));
+    }
+    if let Some(fallback) = &opt.fallback_dns {
+        settings.insert("FallbackDNS".to_string(), fallback.join(" "));
+    }
+    if let Some(dnssec) = &opt.dnssec {
+        settings.insert("DNSSEC".to_string(), dnssec.clone());
+    }
+    if let Some(stub) = opt.stub_listener {
+        settings.insert("DNSStubListener".to_string(), yes_no(stub));
+    }
+    if let Some(cache) = opt.cache {
+        settings.insert("Cache".to_string(), yes_no(cache));
+    }
+    settings
+}
+
+/// returns true if resolved.conf was changed or systemd-resolved was restarted
+#[instrument(skip(client))]
+pub async fn resolved_ensure(
+    client: &impl Executor,
+    opt: &ResolvedOptions,
+) -> anyhow::Result<bool> {
+    let settings = resolved_settings(opt);
+    let changed = config_kv_ensure(client, RESOLVED_CONF, &settings, KvFormat::Plain).await?;
+    // the restart is retried on the next run if it fails
+    let pending = mark_pending(client, "resolved-restart", changed).await?;
+    if pending {
+        run(client, "sudo systemctl restart systemd-resolved 2>&1").await?;
+        clear_pending(client, "resolved-restart").await?;
+    }
+    Ok(pending)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &ResolvedOptions) -> anyhow::Result<bool> {
+    let changed = resolved_ensure(client, opt).await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &ResolvedOptions) -> anyhow::Result<Status> {
+    config_kv_check(
+        client,
+        RESOLVED_CONF,
+        &resolved_settings(opt),
+        KvFormat::Plain,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::config_kv::kv_apply;
+    use base64::{engine::general_purpose, Engine as _};
+
+    #[test]
+    fn it_edits_resolved_conf() {
+        let opt = ResolvedOptions {
+            dns: Some(vec!["1.1.1.1".to_string(), "9.9.9.9".to_string()]),
+            stub_listener: Some(false),
+            cache: Some(true),
+            ..Default::default()
+        };
+        let content = "[Resolve]\n#DNS=\n#FallbackDNS=\n#Cache=no-negative\n#DNSStubListener=yes\n";
+        let out = kv_apply(content, &resolved_settings(&opt), KvFormat::Plain);
+        assert_eq!(
+            out,
+            "[Resolve]\n#DNS=\nDNS=1.1.1.1 9.9.9.9\n#FallbackDNS=\n#Cache=no-negative\nCache=yes\n#DNSStubListener=yes\nDNSStubListener=no\n"
+        );
+        assert_eq!(
+            kv_apply(&out, &resolved_settings(&opt), KvFormat::Plain),
+            out
+        );
+    }
+
+    fn restarted(host: &ScriptedHost) -> bool {
+        host.executed()
+            .iter()
+            .any(|c| c.contains("systemctl restart systemd-resolved"))
+    }
+
+    #[tokio::test]
+    async fn it_restarts_resolved_after_a_change() {
+        let opt = ResolvedOptions {
+            stub_listener: Some(false),
+            ..Default::default()
+        };
+        let host = ScriptedHost::new(vec![("cat '/etc/systemd/resolved.conf'", 0, "[Resolve]\n")]);
+        assert!(resolved_ensure(&host, &opt).await.unwrap());
+        let expected = general_purpose::STANDARD.encode("[Resolve]\nDNSStubListener=no\n");
+        assert!(host.executed().iter().any(|c| c.contains(&expected)));
+        assert!(restarted(&host));
+
+        let host = ScriptedHost::new(vec![
+            (
+                "cat '/etc/systemd/resolved.conf'",
+                0,
+                "[Resolve]\nDNSStubListener=no\n",
+            ),
+            ("test -e", 1, ""),
+        ]);
+        assert!(!resolved_ensure(&host, &opt).await.unwrap());
+        assert!(!restarted(&host));
+    }
+}
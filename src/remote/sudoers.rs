@@ -0,0 +1,158 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct SudoersOptions {
+        /// name of the file in /etc/sudoers.d, only letters, digits, _ and -
+        pub name: String,
+        /// sudoers rules, required unless the state is absent
+        pub content: Option<String>,
+        /// present or absent
+        #[param(default = "present")]
+        pub state: Option<String>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "sudoers",
+    description: "manage /etc/sudoers.d entries validated with visudo",
+    params: SudoersOptions::PARAMS,
+    sudo: true,
+};
+
+const SUDOERS_MODE: &str = "0440";
+
+/// sudo skips files in sudoers.d with dots or `~` in their names,
+/// so only letters, digits, `_` and `-` are accepted
+pub fn sudoers_path(name: &str) -> anyhow::Result<String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if name.is_empty() || !name.chars().all(valid) {
+        bail!(
+            "invalid sudoers name {:?}, only letters, digits, _ and - are allowed",
+            name
+        );
+    }
+    Ok(format!("/etc/sudoers.d/{}", name))
+}
+
+/// the uploaded content is validated before it replaces the entry
+fn install_cmd(path: &str, tmp: &str) -> String {
+    let tmp = shell_quote(tmp);
+    [
+        format!("sudo visudo -cqf {}", tmp),
+        format!("sudo chmod {} {}", SUDOERS_MODE, tmp),
+        format!("sudo chown root:root {}", tmp),
+        format!("sudo mv -f {} {}", tmp, shell_quote(path)),
+    ]
+    .join(" && ")
+}
+
+fn with_newline(content: &str) -> String {
+    // visudo rejects files without the trailing newline
+    if content.ends_with('\n') {
+        content.to_string()
+    } else {
+        format!("{}\n", content)
+    }
+}
+
+/// returns true if the entry was changed
+#[instrument(skip(client))]
+pub async fn sudoers_ensure(
+    client: &Client,
+    name: &str,
+    content: Option<&str>,
+    state: Option<&str>,
+) -> anyhow::Result<bool> {
+    let path = sudoers_path(name)?;
+    let current = read_file(client, &path).await?;
+    if !parse_state(state)? {
+        if current.is_none() {
+            return Ok(false);
+        }
+        run(client, &format!("sudo rm -f {}", shell_quote(&path))).await?;
+        return Ok(true);
+    }
+
+    let content = with_newline(content.context("content is required")?);
+    if current.as_deref() == Some(content.as_str()) && stat(client, &path).await?.mode == "440" {
+        return Ok(false);
+    }
+    // the temporary file is in sudoers.d, its name with a dot is ignored by sudo
+    let mktemp = "sudo mktemp -p /etc/sudoers.d .sudoers.XXXXXX";
+    let tmp = upload_temp(client, mktemp, &content, true).await?;
+    if let Err(e) = run(client, &install_cmd(&path, &tmp)).await {
+        remove_temp(client, &tmp, true).await.ok();
+        return Err(e.context(format!("{} failed visudo validation", name)));
+    }
+    verify_stat(client, &path, Some(SUDOERS_MODE), Some("root:root")).await?;
+    Ok(true)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &SudoersOptions) -> anyhow::Result<bool> {
+    let changed = sudoers_ensure(
+        client,
+        &opt.name,
+        opt.content.as_deref(),
+        opt.state.as_deref(),
+    )
+    .await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &SudoersOptions) -> anyhow::Result<Status> {
+    let path = sudoers_path(&opt.name)?;
+    let current = read_file(client, &path).await?;
+    if !parse_state(opt.state.as_deref())? {
+        return Ok(match current {
+            None => Status::new(vec![format!("{} absent", path)], vec![]),
+            Some(_) => Status::new(vec![], vec![format!("{} exists", path)]),
+        });
+    }
+    let content = with_newline(opt.content.as_deref().context("content is required")?);
+    if current.as_deref() != Some(content.as_str()) {
+        return Ok(Status::new(vec![], vec![format!("{} differs", path)]));
+    }
+    match stat(client, &path)
+        .await?
+        .verify(&path, Some(SUDOERS_MODE), Some("root:root"))
+    {
+        Ok(_) => Ok(Status::new(vec![format!("{} ok", path)], vec![])),
+        Err(e) => Ok(Status::new(vec![], vec![e.to_string()])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_validates_before_install() {
+        let cmd = install_cmd("/etc/sudoers.d/deploy", "/etc/sudoers.d/.sudoers.x");
+        let validate = cmd.find("visudo -cqf '/etc/sudoers.d/.sudoers.x'").unwrap();
+        let chmod = cmd.find("chmod 0440 '/etc/sudoers.d/.sudoers.x'").unwrap();
+        let install = cmd
+            .find("mv -f '/etc/sudoers.d/.sudoers.x' '/etc/sudoers.d/deploy'")
+            .unwrap();
+        assert!(validate < chmod && chmod < install);
+        assert!(cmd.contains("chown root:root '/etc/sudoers.d/.sudoers.x'"));
+    }
+
+    #[test]
+    fn it_checks_sudoers_names() {
+        assert_eq!(sudoers_path("deploy").unwrap(), "/etc/sudoers.d/deploy");
+        assert!(sudoers_path("deploy.conf").is_err());
+        assert!(sudoers_path("deploy~").is_err());
+        assert!(sudoers_path("../sudoers").is_err());
+        assert!(sudoers_path("deploy ops").is_err());
+        assert!(sudoers_path("deploy;id").is_err());
+        assert_eq!(
+            sudoers_path("ci_runner-2").unwrap(),
+            "/etc/sudoers.d/ci_runner-2"
+        );
+        assert_eq!(with_newline("a ALL=(ALL) ALL"), "a ALL=(ALL) ALL\n");
+    }
+}
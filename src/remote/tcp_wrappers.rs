@@ -0,0 +1,189 @@
+use crate::prelude::*;
+use crate::remote::{ModuleInfo, ParamKind};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TcpAction {
+    /// entry in /etc/hosts.allow
+    #[default]
+    Allow,
+    /// entry in /etc/hosts.deny
+    Deny,
+}
+
+impl ParamKind for TcpAction {
+    const KIND: &'static str = "string";
+}
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct TcpWrappersOptions {
+        /// daemon process name, i.e. sshd or ALL
+        pub daemon: String,
+        /// client patterns, i.e. 10.0.0.0/255.255.255.0 or LOCAL
+        pub clients: Vec<String>,
+        /// allow or deny
+        #[param(default = "allow")]
+        pub action: Option<TcpAction>,
+        /// present or absent
+        #[param(default = "present")]
+        pub state: Option<String>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "tcp-wrappers",
+    description: "manage entries of /etc/hosts.allow and /etc/hosts.deny",
+    params: TcpWrappersOptions::PARAMS,
+    sudo: true,
+};
+
+fn path(action: TcpAction) -> &'static str {
+    match action {
+        TcpAction::Allow => "/etc/hosts.allow",
+        TcpAction::Deny => "/etc/hosts.deny",
+    }
+}
+
+/// returns true if the line is an active entry of the daemon
+fn is_entry(line: &str, daemon: &str) -> bool {
+    let line = line.trim_start();
+    if line.starts_with('#') {
+        return false;
+    }
+    match line.split_once(':') {
+        Some((daemons, _)) => daemons.trim() == daemon,
+        None => false,
+    }
+}
+
+pub fn entry(daemon: &str, clients: &[String]) -> String {
+    format!("{}: {}", daemon, clients.join(", "))
+}
+
+/// sets or removes the entry of the daemon, other lines are kept as they are
+pub fn tcp_wrappers_apply(
+    content: &str,
+    daemon: &str,
+    clients: &[String],
+    present: bool,
+) -> String {
+    let expected = entry(daemon, clients);
+    let mut lines = vec![];
+    let mut found = false;
+    for line in content.lines() {
+        if !is_entry(line, daemon) {
+            lines.push(line.to_string());
+        } else if present && !found {
+            lines.push(expected.clone());
+            found = true;
+        }
+    }
+    if present && !found {
+        lines.push(expected);
+    }
+    let mut out = lines.join("\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+/// returns true if the file was changed
+#[instrument(skip(client))]
+pub async fn tcp_wrappers_ensure(
+    client: &Client,
+    daemon: &str,
+    clients: &[String],
+    action: TcpAction,
+    state: Option<&str>,
+) -> anyhow::Result<bool> {
+    let present = parse_state(state)?;
+    let current = read_file(client, path(action)).await?.unwrap_or_default();
+    let expected = tcp_wrappers_apply(&current, daemon, clients, present);
+    if expected == current {
+        return Ok(false);
+    }
+    write_file(client, path(action), &expected).await?;
+    Ok(true)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &TcpWrappersOptions) -> anyhow::Result<bool> {
+    let action = opt.action.unwrap_or_default();
+    let changed = tcp_wrappers_ensure(
+        client,
+        &opt.daemon,
+        &opt.clients,
+        action,
+        opt.state.as_deref(),
+    )
+    .await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &TcpWrappersOptions) -> anyhow::Result<Status> {
+    let action = opt.action.unwrap_or_default();
+    let present = parse_state(opt.state.as_deref())?;
+    let current = read_file(client, path(action)).await?.unwrap_or_default();
+    let expected = tcp_wrappers_apply(&current, &opt.daemon, &opt.clients, present);
+    let label = format!("{} in {}", opt.daemon, path(action));
+    if expected == current {
+        Ok(Status::new(vec![format!("{} ok", label)], vec![]))
+    } else {
+        Ok(Status::new(vec![], vec![format!("{} differs", label)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clients(items: &[&str]) -> Vec<String> {
+        items.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn it_manages_hosts_allow() {
+        let content = "# /etc/hosts.allow\n#sshd: ALL\nvsftpd: LOCAL\n";
+        let added = tcp_wrappers_apply(content, "sshd", &clients(&["10.0.0.0/24", "LOCAL"]), true);
+        assert_eq!(
+            added,
+            "# /etc/hosts.allow\n#sshd: ALL\nvsftpd: LOCAL\nsshd: 10.0.0.0/24, LOCAL\n"
+        );
+        let updated = tcp_wrappers_apply(&added, "sshd", &clients(&["192.168.1.0/24"]), true);
+        assert_eq!(
+            updated,
+            "# /etc/hosts.allow\n#sshd: ALL\nvsftpd: LOCAL\nsshd: 192.168.1.0/24\n"
+        );
+        assert_eq!(
+            tcp_wrappers_apply(&updated, "sshd", &clients(&["192.168.1.0/24"]), true),
+            updated
+        );
+        let removed = tcp_wrappers_apply(&updated, "sshd", &[], false);
+        assert_eq!(removed, content);
+    }
+
+    #[test]
+    fn it_manages_hosts_deny() {
+        assert_eq!(path(TcpAction::Deny), "/etc/hosts.deny");
+        let content = "ALL: ALL\nsshd: 1.2.3.4\nsshd : 5.6.7.8\n";
+        // duplicate entries of the daemon are collapsed into one
+        assert_eq!(
+            tcp_wrappers_apply(content, "sshd", &clients(&["PARANOID"]), true),
+            "ALL: ALL\nsshd: PARANOID\n"
+        );
+        assert_eq!(
+            tcp_wrappers_apply(content, "sshd", &[], false),
+            "ALL: ALL\n"
+        );
+        assert_eq!(
+            tcp_wrappers_apply("", "ALL", &clients(&["ALL"]), true),
+            "ALL: ALL\n"
+        );
+        let opt: TcpWrappersOptions =
+            toml::from_str("daemon = \"sshd\"\nclients = []\naction = \"deny\"").unwrap();
+        assert_eq!(opt.action, Some(TcpAction::Deny));
+    }
+}
@@ -1,20 +1,33 @@
 use crate::prelude::*;
+use crate::remote::ModuleInfo;
 
-#[derive(Debug, Default, Deserialize)]
-pub struct TerraformOptions {}
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct TerraformOptions {}
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "terraform",
+    description: "install terraform from the hashicorp repository",
+    params: TerraformOptions::PARAMS,
+    sudo: true,
+};
 
 const GPG_PATH: &str = "/usr/share/keyrings/hashicorp-archive-keyring.gpg";
 const SOURCES_LIST_PATH: &str = "/etc/apt/sources.list.d/hashicorp.list";
 
 #[instrument(skip(client))]
-pub async fn on_install(client: &Client, opt: &TerraformOptions) -> anyhow::Result<()> {
-    if !file_exists(client, GPG_PATH).await { // TODO: file could be empty
+pub async fn on_install(client: &Client, opt: &TerraformOptions) -> anyhow::Result<bool> {
+    let mut changed = false;
+    if !file_exists(client, GPG_PATH).await {
+        // TODO: file could be empty
         // install GPG key
         run(client, "sudo mkdir -m 0755 -p /usr/share/keyrings").await?;
 
         let origin = "https://apt.releases.hashicorp.com/gpg";
         let cmd = format!("curl -s {} | sudo gpg --dearmor -o {}", origin, GPG_PATH);
         run(client, &cmd).await?;
+        changed = true;
     }
 
     // verify GPG key
@@ -25,15 +38,23 @@ pub async fn on_install(client: &Client, opt: &TerraformOptions) -> anyhow::Resu
     run(client, &cmd).await?;
 
     if !file_exists(client, SOURCES_LIST_PATH).await {
-        let lsb_release = run(&client, "lsb_release -cs").await?.output.trim().lines().next().unwrap_or("").to_string();
+        let lsb_release = run(client, "lsb_release -cs")
+            .await?
+            .output
+            .trim()
+            .lines()
+            .next()
+            .unwrap_or("")
+            .to_string();
         // setup apt repo
         let cmd = format!(
             "echo \"deb [signed-by={}] https://apt.releases.hashicorp.com {} main\" | sudo tee {} > /dev/null",
             GPG_PATH,
-            lsb_release, 
+            lsb_release,
             SOURCES_LIST_PATH,
         );
         run(client, &cmd).await?;
+        changed = true;
     }
     run(client, "export DEBIAN_FRONTEND=noninteractive").await?;
     run(client, "sudo apt-get update 2>&1").await?;
@@ -46,9 +67,10 @@ pub async fn on_install(client: &Client, opt: &TerraformOptions) -> anyhow::Resu
     if !packages.is_empty() {
         let cmd = format!("sudo apt-get install -y {} 2>&1", packages.join(" "));
         run(client, &cmd).await?;
+        changed = true;
     }
 
-    Ok(())
+    Ok(changed)
 }
 
 #[instrument(skip(client))]
@@ -0,0 +1,149 @@
+use crate::prelude::*;
+use crate::remote::ModuleInfo;
+
+module_options! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct VirtualenvOptions {
+        /// folder of the virtualenv
+        pub path: String,
+        /// python interpreter to create it with, python_interpreter of the playbook by default
+        pub python: Option<String>,
+        /// give the virtualenv access to the system site-packages
+        #[param(default = "false")]
+        pub system_site_packages: Option<bool>,
+        /// present or absent
+        #[param(default = "present")]
+        pub state: Option<String>,
+    }
+}
+
+pub const MODULE: ModuleInfo = ModuleInfo {
+    name: "virtualenv",
+    description: "create or remove a python virtualenv",
+    params: VirtualenvOptions::PARAMS,
+    sudo: false,
+};
+
+/// the virtualenv exists if it can be activated
+fn activate_path(path: &str) -> String {
+    format!("{}/bin/activate", path.trim_end_matches('/'))
+}
+
+/// command creating the virtualenv with the venv module of the interpreter
+pub fn venv_cmd(path: &str, python: &str, system_site_packages: bool) -> String {
+    let mut args = vec![python, "-m", "venv"];
+    if system_site_packages {
+        args.push("--system-site-packages");
+    }
+    args.push(path);
+    format!("{} 2>&1", shell_join(&args))
+}
+
+/// what brings the virtualenv to the state
+#[derive(Debug, PartialEq)]
+pub enum VenvAction {
+    Create,
+    Remove,
+}
+
+/// a virtualenv that can't be activated is created again, i.e. over a broken one,
+/// but the folder is removed for the absent state whatever it holds
+pub fn venv_action(present: bool, activatable: bool, folder_exists: bool) -> Option<VenvAction> {
+    match (present, activatable, folder_exists) {
+        (true, false, _) => Some(VenvAction::Create),
+        (false, _, true) => Some(VenvAction::Remove),
+        _ => None,
+    }
+}
+
+/// returns whether the virtualenv can be activated and whether its folder exists
+async fn venv_state(client: &Client, path: &str) -> anyhow::Result<(bool, bool)> {
+    let activatable = file_exists(client, &activate_path(path)).await;
+    let folder = silent(client, &format!("test -d {}", shell_quote(path))).await?;
+    Ok((activatable, folder.exit_status == 0))
+}
+
+/// creates or removes the virtualenv, returns true if it was changed
+#[instrument(skip(client))]
+pub async fn virtualenv_ensure(
+    client: &Client,
+    path: &str,
+    python: Option<&str>,
+    system_site_packages: bool,
+    state: Option<&str>,
+) -> anyhow::Result<bool> {
+    let present = parse_state(state)?;
+    let (activatable, folder_exists) = venv_state(client, path).await?;
+    match venv_action(present, activatable, folder_exists) {
+        None => return Ok(false),
+        Some(VenvAction::Create) => {
+            let python = match python {
+                Some(python) => python.to_string(),
+                None => crate::prelude::python(client).await?,
+            };
+            run(client, &venv_cmd(path, &python, system_site_packages)).await?;
+        }
+        Some(VenvAction::Remove) => {
+            run(client, &format!("rm -rf {} 2>&1", shell_quote(path))).await?;
+        }
+    }
+    Ok(true)
+}
+
+#[instrument(skip(client))]
+pub async fn on_install(client: &Client, opt: &VirtualenvOptions) -> anyhow::Result<bool> {
+    let changed = virtualenv_ensure(
+        client,
+        &opt.path,
+        opt.python.as_deref(),
+        opt.system_site_packages.unwrap_or(false),
+        opt.state.as_deref(),
+    )
+    .await?;
+    Ok(changed)
+}
+
+#[instrument(skip(client))]
+pub async fn on_check(client: &Client, opt: &VirtualenvOptions) -> anyhow::Result<Status> {
+    let present = parse_state(opt.state.as_deref())?;
+    let (activatable, folder_exists) = venv_state(client, &opt.path).await?;
+    Ok(match venv_action(present, activatable, folder_exists) {
+        None if present => Status::new(vec![format!("{} exists", opt.path)], vec![]),
+        None => Status::new(vec![format!("{} absent", opt.path)], vec![]),
+        Some(VenvAction::Create) => Status::new(vec![], vec![format!("{} missing", opt.path)]),
+        Some(VenvAction::Remove) => Status::new(vec![], vec![format!("{} still exists", opt.path)]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_venv_cmd() {
+        assert_eq!(
+            venv_cmd("/opt/app/venv", "python3", false),
+            "'python3' '-m' 'venv' '/opt/app/venv' 2>&1"
+        );
+        assert_eq!(
+            venv_cmd("/opt/my app", "python3.11", true),
+            "'python3.11' '-m' 'venv' '--system-site-packages' '/opt/my app' 2>&1"
+        );
+        assert_eq!(
+            activate_path("/opt/app/venv/"),
+            "/opt/app/venv/bin/activate"
+        );
+    }
+
+    #[test]
+    fn it_decides_what_to_do() {
+        assert_eq!(venv_action(true, true, true), None);
+        assert_eq!(venv_action(true, false, false), Some(VenvAction::Create));
+        // a folder without bin/activate is not a virtualenv yet
+        assert_eq!(venv_action(true, false, true), Some(VenvAction::Create));
+        assert_eq!(venv_action(false, false, false), None);
+        assert_eq!(venv_action(false, true, true), Some(VenvAction::Remove));
+        // a broken virtualenv is still removed
+        assert_eq!(venv_action(false, false, true), Some(VenvAction::Remove));
+    }
+}
@@ -0,0 +1,623 @@
+use crate::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// suppress progress output, i.e. when the report is printed as JSON
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+static RUN_ID: OnceLock<String> = OnceLock::new();
+
+/// id of this run, shared by the report and the `run` span of all log events
+pub fn run_id() -> &'static str {
+    RUN_ID.get_or_init(new_run_id)
+}
+
+/// random UUID v4
+pub fn new_run_id() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+/// results of the whole run, i.e. for the webhook notification
+#[derive(Debug, Default, Serialize)]
+pub struct RunReport {
+    pub run_id: String,
+    pub action: String,
+    pub host: String,
+    pub success: bool,
+    pub reachable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub ok: usize,
+    /// modules that modified the host, counted in ok
+    pub changed: usize,
+    pub failed: usize,
+    /// failures that don't fail the run, with --warn-only
+    #[serde(skip_serializing_if = "is_zero")]
+    pub ignored: usize,
+    #[serde(skip)]
+    pub warn_only: bool,
+    /// consecutive module failures after which the remaining stages are skipped
+    #[serde(skip)]
+    pub failure_threshold: Option<usize>,
+    #[serde(skip)]
+    consecutive_failed: usize,
+    /// stages that were not run because the host kept failing
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+    pub stages: Vec<StageReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl RunReport {
+    pub fn new(action: &str, host: &str) -> Self {
+        RunReport {
+            run_id: run_id().to_string(),
+            action: action.to_string(),
+            host: host.to_string(),
+            success: true,
+            reachable: true,
+            ..Default::default()
+        }
+    }
+
+    /// report of the run that failed to connect to the host
+    pub fn unreachable(action: &str, host: &str, error: &anyhow::Error) -> Self {
+        RunReport {
+            run_id: run_id().to_string(),
+            action: action.to_string(),
+            host: host.to_string(),
+            error: Some(format!("{:#}", error)),
+            ..Default::default()
+        }
+    }
+
+    /// report in the output format, text is the recap
+    pub fn render(&self, format: crate::cli::OutputFormat) -> anyhow::Result<String> {
+        use crate::cli::OutputFormat;
+        Ok(match format {
+            OutputFormat::Text => self.recap(),
+            OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(self)?),
+            OutputFormat::Yaml => serialize_yaml(self)?,
+        })
+    }
+
+    /// fails the whole run before any stage, i.e. when sudo doesn't work
+    pub fn fail(&mut self, error: &anyhow::Error) {
+        self.success = false;
+        self.error = Some(format!("{:#}", error));
+    }
+
+    pub fn connected(&mut self, latency: Duration, os: &str) {
+        self.latency_ms = Some(latency.as_millis() as u64);
+        self.os = Some(os.to_string());
+    }
+
+    pub fn add(&mut self, mut stage: StageReport) {
+        for module in &mut stage.modules {
+            if module.success {
+                self.ok += 1;
+                if module.changed {
+                    self.changed += 1;
+                }
+            } else if self.warn_only {
+                module.ignored = true;
+                self.ignored += 1;
+            } else {
+                self.failed += 1;
+                self.success = false;
+                self.consecutive_failed += 1;
+                continue;
+            }
+            self.consecutive_failed = 0;
+        }
+        self.stages.push(stage);
+    }
+
+    /// true once the host failed failure_threshold modules in a row
+    pub fn failing_fast(&self) -> bool {
+        matches!(self.failure_threshold, Some(n) if self.consecutive_failed >= n)
+    }
+
+    pub fn skip(&mut self, stage: &str) {
+        if !is_quiet() {
+            println!("{}", Outcome::Skipped.line(stage, Outcome::Skipped.label()));
+        }
+        self.skipped.push(stage.to_string());
+    }
+
+    /// 1 when the run failed, 2 when it succeeded with changes and `detailed` is set, 0 otherwise
+    pub fn exit_code(&self, detailed: bool) -> u8 {
+        if !self.success {
+            1
+        } else if detailed && self.changed > 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    /// collect warnings pushed by the modules during the run
+    pub fn finish(&mut self) {
+        self.warnings.extend(take_warnings());
+    }
+
+    /// summary printed at the end of the run
+    pub fn recap(&self) -> String {
+        let mut out = format!(
+            "RECAP {} {}: {} ok, {} failed",
+            self.action, self.host, self.ok, self.failed
+        );
+        if self.changed > 0 {
+            out.push_str(&format!(", {} changed", self.changed));
+        }
+        if self.ignored > 0 {
+            out.push_str(&format!(", {} ignored", self.ignored));
+        }
+        out.push('\n');
+        if let Some(error) = &self.error {
+            out.push_str(&format!("error: {}\n", error));
+        }
+        for (label, ignored) in [("failed", false), ("ignored", true)] {
+            let modules: Vec<String> = self
+                .stages
+                .iter()
+                .flat_map(|stage| {
+                    stage
+                        .modules
+                        .iter()
+                        .filter(|m| !m.success && m.ignored == ignored)
+                        .map(|m| format!("  - {}/{}\n", stage.name, m.module))
+                })
+                .collect();
+            if !modules.is_empty() {
+                out.push_str(&format!("{}:\n{}", label, modules.concat()));
+            }
+        }
+        let previews: Vec<String> = self
+            .stages
+            .iter()
+            .flat_map(|stage| {
+                stage.modules.iter().flat_map(move |m| {
+                    let preview = m.status.as_ref().map(|s| s.preview()).unwrap_or_default();
+                    preview
+                        .iter()
+                        .map(move |p| format!("  - {}/{}: {}\n", stage.name, m.module, p))
+                })
+            })
+            .collect();
+        if !previews.is_empty() {
+            out.push_str(&format!("preview:\n{}", previews.concat()));
+        }
+        if !self.skipped.is_empty() {
+            out.push_str(&format!(
+                "skipped after {} failures in a row:\n",
+                self.failure_threshold.unwrap_or_default()
+            ));
+            for stage in &self.skipped {
+                out.push_str(&format!("  - {}\n", stage));
+            }
+        }
+        if !self.warnings.is_empty() {
+            out.push_str("warnings:\n");
+            for warning in &self.warnings {
+                out.push_str(&format!("  - {}\n", warning));
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StageReport {
+    pub name: String,
+    pub modules: Vec<ModuleReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModuleReport {
+    pub module: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub changed: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub ignored: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    /// what the installation did, i.e. the pulled commits
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub detail: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// result of the module installation
+#[derive(Debug, Default)]
+pub struct Installed {
+    /// the module modified the host
+    pub changed: bool,
+    pub detail: Vec<String>,
+}
+
+impl Installed {
+    pub fn outcome(&self) -> Outcome {
+        if self.changed {
+            Outcome::Changed
+        } else {
+            Outcome::Ok
+        }
+    }
+}
+
+impl From<bool> for Installed {
+    fn from(changed: bool) -> Self {
+        Installed {
+            changed,
+            detail: vec![],
+        }
+    }
+}
+
+impl StageReport {
+    pub fn new(name: &str) -> Self {
+        StageReport {
+            name: name.to_string(),
+            modules: vec![],
+        }
+    }
+
+    /// prints the header of the stage
+    pub fn start(name: &str) -> Self {
+        if !is_quiet() {
+            println!("= {}", name.yellow());
+        }
+        Self::new(name)
+    }
+
+    /// print and record the result of the module installation
+    pub fn installed<T: Into<Installed>>(&mut self, alias: &str, result: anyhow::Result<T>) {
+        match result {
+            Ok(installed) => {
+                let installed = installed.into();
+                if !is_quiet() {
+                    let outcome = installed.outcome();
+                    println!("{}", outcome.line(alias, outcome.label()));
+                    for line in &installed.detail {
+                        println!("  {}", line);
+                    }
+                }
+                self.modules.push(ModuleReport {
+                    module: alias.to_string(),
+                    success: true,
+                    changed: installed.changed,
+                    ignored: false,
+                    status: None,
+                    detail: installed.detail,
+                    error: None,
+                });
+            }
+            Err(e) => self.failed(alias, e),
+        }
+    }
+
+    /// print and record the result of the module check
+    pub fn checked(&mut self, alias: &str, result: anyhow::Result<Status>) {
+        match result {
+            Ok(status) => {
+                if !is_quiet() {
+                    status.print(alias, status.outcome());
+                }
+                self.modules.push(ModuleReport {
+                    module: alias.to_string(),
+                    success: matches!(status, Status::Installed { .. }),
+                    changed: false,
+                    ignored: false,
+                    status: Some(status),
+                    detail: vec![],
+                    error: None,
+                });
+            }
+            Err(e) => self.failed(alias, e),
+        }
+    }
+
+    fn failed(&mut self, alias: &str, e: anyhow::Error) {
+        if !is_quiet() {
+            let detail = format!("{} {}", Outcome::Failed.label(), e);
+            println!("{}", Outcome::Failed.line(alias, &detail));
+        }
+        self.modules.push(ModuleReport {
+            module: alias.to_string(),
+            success: false,
+            changed: false,
+            ignored: false,
+            status: None,
+            detail: vec![],
+            error: Some(e.to_string()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keeps_run_id() {
+        let id = run_id();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+        assert_eq!(run_id(), id);
+        assert_ne!(new_run_id(), new_run_id());
+        let report = RunReport::new("check", "10.0.0.5:22");
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["run_id"], id);
+        let error = anyhow::anyhow!("connection refused");
+        let report = RunReport::unreachable("check", "10.0.0.5:22", &error);
+        assert_eq!(report.run_id, id);
+    }
+
+    #[test]
+    fn it_renders_yaml() {
+        let mut report = RunReport::new("check", "10.0.0.5:22");
+        report.connected(Duration::from_millis(42), "Ubuntu");
+        let mut stage = StageReport::new("disk");
+        stage.checked("apt", Ok(Status::new(vec!["curl ok".to_string()], vec![])));
+        report.add(stage);
+        let yaml = report.render(crate::cli::OutputFormat::Yaml).unwrap();
+        let expected = format!(
+            r#""action": "check"
+"changed": 0
+"failed": 0
+"host": "10.0.0.5:22"
+"latency_ms": 42
+"ok": 1
+"os": "Ubuntu"
+"reachable": true
+"run_id": "{}"
+"stages":
+  - "modules":
+      - "module": "apt"
+"#,
+            run_id()
+        );
+        assert!(yaml.starts_with(&expected), "{}", yaml);
+        assert!(yaml.contains("    \"name\": \"disk\"\n"), "{}", yaml);
+        assert!(yaml.ends_with("\"success\": true\n"), "{}", yaml);
+    }
+
+    #[test]
+    fn it_fails_fast() {
+        let mut report = RunReport::new("install", "10.0.0.5:22");
+        report.failure_threshold = Some(2);
+        let mut stage = StageReport::new("disk");
+        stage.installed("mount", Err::<bool, _>(anyhow::anyhow!("no device")));
+        stage.installed("mkdir", Ok(false));
+        report.add(stage);
+        assert!(!report.failing_fast());
+
+        let mut stage = StageReport::new("docker");
+        stage.installed("apt", Err::<bool, _>(anyhow::anyhow!("dpkg lock")));
+        stage.installed("docker", Err::<bool, _>(anyhow::anyhow!("dpkg lock")));
+        report.add(stage);
+        assert!(report.failing_fast());
+        report.skip("node-exporter");
+        report.skip("aliases");
+        assert_eq!(report.failed, 3);
+        assert!(report
+            .recap()
+            .ends_with("skipped after 2 failures in a row:\n  - node-exporter\n  - aliases\n"));
+        assert!(!RunReport::new("install", "10.0.0.5:22").failing_fast());
+    }
+
+    #[test]
+    fn it_counts_modules() {
+        let mut stage = StageReport::new("disk");
+        stage.installed("mount", Ok(false));
+        stage.installed(
+            "mkdir",
+            Err::<bool, _>(anyhow::anyhow!("permission denied")),
+        );
+        stage.checked("apt", Ok(Status::new(vec!["curl ok".to_string()], vec![])));
+
+        let mut report = RunReport::new("install", "10.0.0.5:22");
+        report.add(stage);
+        assert!(!report.success);
+        assert_eq!(report.ok, 2);
+        assert_eq!(report.failed, 1);
+        assert_eq!(
+            report.stages[0].modules[1].error.as_deref(),
+            Some("permission denied")
+        );
+    }
+
+    #[test]
+    fn it_shows_previews_in_recap() {
+        let mut stage = StageReport::new("tools");
+        let status =
+            Status::new(vec![], vec!["jq missing".to_string()]).with_preview(vec![Preview {
+                action: "install".to_string(),
+                items: vec!["libjq1".to_string(), "jq".to_string()],
+            }]);
+        stage.checked("apt", Ok(status));
+        let mut report = RunReport::new("check", "10.0.0.5:22");
+        report.add(stage);
+        assert_eq!(
+            report.recap(),
+            "RECAP check 10.0.0.5:22: 0 ok, 1 failed\nfailed:\n  - tools/apt\npreview:\n  - tools/apt: would install: libjq1 jq\n"
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        let status = &json["stages"][0]["modules"][0]["status"]["NotInstalled"];
+        assert_eq!(status["fail"], serde_json::json!(["jq missing"]));
+        assert_eq!(
+            status["preview"],
+            serde_json::json!([{"action": "install", "items": ["libjq1", "jq"]}])
+        );
+        // nothing to install once installed
+        let status = Status::new(vec![], vec![]).with_preview(vec![Preview {
+            action: "install".to_string(),
+            items: vec!["jq".to_string()],
+        }]);
+        assert!(status.preview().is_empty());
+    }
+
+    #[test]
+    fn it_shows_warnings_in_recap() {
+        add_warning("host key of 10.0.0.5:22 is not verified");
+        add_warning("host key of 10.0.0.5:22 is not verified");
+        let mut report = RunReport::new("check", "10.0.0.5:22");
+        let mut stage = StageReport::new("disk");
+        stage.installed("mount", Ok(false));
+        report.add(stage);
+        report.finish();
+        assert_eq!(
+            report.recap(),
+            "RECAP check 10.0.0.5:22: 1 ok, 0 failed\nwarnings:\n  - host key of 10.0.0.5:22 is not verified\n"
+        );
+        assert_eq!(
+            RunReport::new("check", "h").recap(),
+            "RECAP check h: 0 ok, 0 failed\n"
+        );
+    }
+
+    #[test]
+    fn it_serializes_reachability() {
+        let mut report = RunReport::new("check", "10.0.0.5:22");
+        report.connected(Duration::from_millis(42), "ubuntu");
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["reachable"], true);
+        assert_eq!(json["latency_ms"], 42);
+        assert_eq!(json["os"], "ubuntu");
+        assert!(json.get("error").is_none());
+
+        let error = anyhow::anyhow!("connection refused").context("failed to connect");
+        let report = RunReport::unreachable("check", "10.0.0.6:22", &error);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["reachable"], false);
+        assert_eq!(json["success"], false);
+        assert_eq!(json["error"], "failed to connect: connection refused");
+        assert!(json.get("latency_ms").is_none());
+        assert!(json.get("os").is_none());
+    }
+
+    #[test]
+    fn it_fails_before_stages() {
+        let mut report = RunReport::new("install", "10.0.0.5:22");
+        report.fail(&anyhow::anyhow!(
+            "sudo requires a password for deploy@10.0.0.5:22"
+        ));
+        assert!(!report.success);
+        assert_eq!(
+            report.recap(),
+            "RECAP install 10.0.0.5:22: 0 ok, 0 failed\nerror: sudo requires a password for deploy@10.0.0.5:22\n"
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["reachable"], true);
+    }
+
+    #[test]
+    fn it_ignores_failures_with_warn_only() {
+        let stage = || {
+            let mut stage = StageReport::new("disk");
+            stage.installed("mount", Err::<bool, _>(anyhow::anyhow!("no free device")));
+            stage.installed("mkdir", Ok(false));
+            stage
+        };
+        let mut report = RunReport::new("install", "h");
+        report.add(stage());
+        assert!(!report.success);
+        assert_eq!(
+            report.recap(),
+            "RECAP install h: 1 ok, 1 failed\nfailed:\n  - disk/mount\n"
+        );
+
+        let mut report = RunReport::new("install", "h");
+        report.warn_only = true;
+        report.add(stage());
+        assert!(report.success);
+        assert_eq!((report.ok, report.failed, report.ignored), (1, 0, 1));
+        assert_eq!(
+            report.recap(),
+            "RECAP install h: 1 ok, 0 failed, 1 ignored\nignored:\n  - disk/mount\n"
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["stages"][0]["modules"][0]["ignored"], true);
+        assert!(json["stages"][0]["modules"][1].get("ignored").is_none());
+    }
+
+    #[test]
+    fn it_exits_by_changes() {
+        let run = |results: Vec<anyhow::Result<bool>>| {
+            let mut stage = StageReport::new("disk");
+            for (i, result) in results.into_iter().enumerate() {
+                stage.installed(&format!("m{}", i), result);
+            }
+            let mut report = RunReport::new("install", "h");
+            report.add(stage);
+            report
+        };
+
+        assert_eq!(Installed::from(true).outcome(), Outcome::Changed);
+        assert_eq!(Installed::from(false).outcome(), Outcome::Ok);
+
+        let report = run(vec![Ok(false), Ok(false)]);
+        assert_eq!((report.ok, report.changed), (2, 0));
+        assert_eq!(report.exit_code(true), 0);
+        assert_eq!(report.recap(), "RECAP install h: 2 ok, 0 failed\n");
+
+        let report = run(vec![Ok(true), Ok(false)]);
+        assert_eq!((report.ok, report.changed), (2, 1));
+        assert_eq!(report.exit_code(true), 2);
+        assert_eq!(report.exit_code(false), 0);
+        assert_eq!(
+            report.recap(),
+            "RECAP install h: 2 ok, 0 failed, 1 changed\n"
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["changed"], 1);
+        assert_eq!(json["stages"][0]["modules"][0]["changed"], true);
+        assert!(json["stages"][0]["modules"][1].get("changed").is_none());
+
+        let mut stage = StageReport::new("repo");
+        let pulled = Installed {
+            changed: true,
+            detail: vec!["1a2b3c4 Fix the build".to_string()],
+        };
+        stage.installed("git", Ok(pulled));
+        let json = serde_json::to_value(&stage).unwrap();
+        assert_eq!(json["modules"][0]["detail"][0], "1a2b3c4 Fix the build");
+
+        let report = run(vec![Ok(true), Err(anyhow::anyhow!("dpkg lock"))]);
+        assert_eq!((report.ok, report.changed, report.failed), (1, 1, 1));
+        assert_eq!(report.exit_code(true), 1);
+        assert_eq!(report.exit_code(false), 1);
+    }
+}